@@ -0,0 +1,227 @@
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::interpreter::{Context, Rng};
+
+/// Which implementation runs the program.
+#[derive(Debug, Default, PartialEq)]
+pub enum Engine {
+    /// The tree-walking interpreter, the reference implementation.
+    #[default]
+    Tree,
+    /// The bytecode compiler and stack machine.
+    Vm,
+}
+
+/// A parsed command line: `plang [flags] <file> [args...]`.
+#[derive(Debug)]
+pub struct Cli {
+    pub file: String,
+    pub engine: Engine,
+    /// How hard to optimize the program before running it, from 0 to 2.
+    pub opt_level: u8,
+    /// Print the optimized program instead of running it.
+    pub dump_ast: bool,
+    /// Directories searched for imports after the importing file's own.
+    pub path: Vec<PathBuf>,
+    pub context: Context,
+}
+
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Cli, String> {
+    let mut context = Context::default();
+    let mut engine = Engine::default();
+    let mut opt_level = 1;
+    let mut dump_ast = false;
+    let mut path = Vec::new();
+
+    let file = loop {
+        match args.next() {
+            Some(flag) if flag.starts_with("--engine=") => {
+                engine = match &flag["--engine=".len()..] {
+                    "tree" => Engine::Tree,
+                    "vm" => Engine::Vm,
+                    e => return Err(format!("Unknown engine {}", e)),
+                }
+            }
+            Some(flag) if flag.starts_with("--opt-level=") => {
+                opt_level = match number("--opt-level", Some(&flag["--opt-level=".len()..]))? {
+                    n @ 0..=2 => n,
+                    n => return Err(format!("Unknown opt level {}", n)),
+                }
+            }
+            Some(flag) if flag == "--dump-ast" => dump_ast = true,
+            Some(flag) if flag.starts_with("--path=") => {
+                path.extend(env::split_paths(&flag["--path=".len()..]))
+            }
+            Some(flag) if flag.starts_with("--") => parse_flag(&mut context, &flag)?,
+            Some(file) => break file,
+            None => return Err(String::from("Provide proper args!")),
+        }
+    };
+
+    context.args = args.collect();
+
+    Ok(Cli {
+        file,
+        engine,
+        opt_level,
+        dump_ast,
+        path,
+        context,
+    })
+}
+
+fn parse_flag(context: &mut Context, flag: &str) -> Result<(), String> {
+    let (name, value) = match flag.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (flag, None),
+    };
+
+    let limits = &mut context.limits;
+
+    match name {
+        "--max-steps" => return number(name, value).map(|n| limits.steps = Some(n)),
+        "--max-depth" => return number(name, value).map(|n| limits.depth = Some(n)),
+        "--max-memory" => return number(name, value).map(|n| limits.memory = Some(n)),
+        "--timeout" => {
+            return number(name, value).map(|n| limits.time = Some(Duration::from_millis(n)))
+        }
+        "--seed" => return number(name, value).map(|n| context.rng = Rng::new(n)),
+        _ => {}
+    }
+
+    let permissions = &mut context.permissions;
+
+    let rule = match name {
+        "--sandbox" if value.is_none() => {
+            permissions.sandbox();
+            return Ok(());
+        }
+        "--allow-cmd" | "--deny-cmd" => &mut permissions.cmd,
+        "--allow-read" | "--deny-read" => &mut permissions.read,
+        "--allow-write" | "--deny-write" => &mut permissions.write,
+        "--allow-net" | "--deny-net" => &mut permissions.net,
+        _ => return Err(format!("Unknown flag {}", flag)),
+    };
+
+    let value = value.ok_or(format!("{} needs a value", name))?;
+
+    if value.is_empty() {
+        rule.restrict();
+    }
+
+    for item in value.split(',').filter(|i| !i.is_empty()) {
+        if name.starts_with("--allow") {
+            rule.allow(item);
+        } else {
+            rule.deny(item);
+        }
+    }
+
+    Ok(())
+}
+
+fn number<T: FromStr>(name: &str, value: Option<&str>) -> Result<T, String> {
+    value
+        .and_then(|v| v.parse().ok())
+        .ok_or(format!("{} needs a number", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(input: &str) -> impl Iterator<Item = String> + '_ {
+        input.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn test1() {
+        let cli = parse_args(args("main.plang a --b")).unwrap();
+
+        assert_eq!(cli.file, "main.plang");
+        assert_eq!(cli.context.args, vec!["a", "--b"]);
+    }
+
+    #[test]
+    fn test2() {
+        let cli = parse_args(args(
+            "--allow-cmd=git,ls --deny-read=/etc --sandbox --allow-net=localhost main.plang",
+        ))
+        .unwrap();
+        let permissions = cli.context.permissions;
+
+        assert!(permissions.check_cmd("git").is_ok());
+        assert!(permissions.check_cmd("rm").is_err());
+        assert!(permissions.check_read("/etc/passwd").is_err());
+        assert!(permissions.check_net("localhost", 80).is_ok());
+        assert!(permissions.check_write("/tmp").is_err());
+    }
+
+    #[test]
+    fn test3() {
+        assert!(parse_args(args("--allow-everything main.plang")).is_err());
+        assert!(parse_args(args("--allow-cmd main.plang")).is_err());
+        assert!(parse_args(args("--sandbox")).is_err());
+    }
+
+    #[test]
+    fn test4() {
+        let cli = parse_args(args(
+            "--max-steps=100 --max-depth=10 --timeout=250 --max-memory=4096 main.plang",
+        ))
+        .unwrap();
+        let limits = cli.context.limits;
+
+        assert_eq!(limits.steps, Some(100));
+        assert_eq!(limits.depth, Some(10));
+        assert_eq!(limits.time, Some(Duration::from_millis(250)));
+        assert_eq!(limits.memory, Some(4096));
+        assert!(parse_args(args("--max-steps=lots main.plang")).is_err());
+    }
+
+    #[test]
+    fn test5() {
+        let cli = parse_args(args("--seed=42 main.plang")).unwrap();
+
+        assert_eq!(cli.context.rng, Rng::new(42));
+        assert!(parse_args(args("--seed=-1 main.plang")).is_err());
+    }
+
+    #[test]
+    fn test6() {
+        assert_eq!(parse_args(args("main.plang")).unwrap().engine, Engine::Tree);
+        assert_eq!(
+            parse_args(args("--engine=vm main.plang")).unwrap().engine,
+            Engine::Vm
+        );
+        assert!(parse_args(args("--engine=jit main.plang")).is_err());
+    }
+
+    #[test]
+    fn test7() {
+        let cli = parse_args(args("main.plang")).unwrap();
+        assert_eq!((cli.opt_level, cli.dump_ast), (1, false));
+
+        let cli = parse_args(args("--opt-level=2 --dump-ast main.plang")).unwrap();
+        assert_eq!((cli.opt_level, cli.dump_ast), (2, true));
+
+        assert!(parse_args(args("--opt-level=3 main.plang")).is_err());
+        assert!(parse_args(args("--opt-level=max main.plang")).is_err());
+    }
+
+    #[test]
+    fn test8() {
+        let cli = parse_args(args("--path=lib:vendor/plang --path=/opt/plang main.plang")).unwrap();
+        assert_eq!(
+            cli.path,
+            vec![
+                PathBuf::from("lib"),
+                PathBuf::from("vendor/plang"),
+                PathBuf::from("/opt/plang")
+            ]
+        );
+    }
+}
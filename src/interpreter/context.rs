@@ -0,0 +1,43 @@
+use std::io::{self, BufReader};
+
+use super::limits::Usage;
+use super::{Deadline, EventLoop, Limits, Permissions, Rng, Scheduler};
+use crate::value::Lines;
+
+/// Runtime state shared by every scope of a running program.
+#[derive(Debug)]
+pub struct Context {
+    /// Arguments passed after the script path on the command line.
+    pub args: Vec<String>,
+    pub permissions: Permissions,
+    pub limits: Limits,
+    pub usage: Usage,
+    /// The program's standard input, shared so that `read_line` and
+    /// `stdin_lines` never lose each other's buffered data.
+    pub stdin: Lines,
+    pub rng: Rng,
+    pub scheduler: Scheduler,
+    pub event_loop: EventLoop,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self {
+            args: Vec::new(),
+            permissions: Permissions::default(),
+            limits: Limits::default(),
+            usage: Usage::default(),
+            stdin: Lines::new(BufReader::new(io::stdin())),
+            rng: Rng::default(),
+            scheduler: Scheduler::default(),
+            event_loop: EventLoop::default(),
+        }
+    }
+}
+
+impl Context {
+    /// When the program's time limit runs out, if it has one.
+    pub fn deadline(&mut self) -> Option<Deadline> {
+        self.usage.deadline(&self.limits)
+    }
+}
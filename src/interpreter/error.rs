@@ -0,0 +1,227 @@
+use thiserror::Error;
+
+use crate::parser::ast::{Expression, Span};
+use crate::stdlib::display;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum RuntimeError {
+    #[error("undefined variable `{0}`")]
+    UndefinedVariable(String),
+
+    #[error("`{0}` is used before its definition")]
+    UseBeforeDefinition(String),
+
+    #[error("assignment to undeclared variable `{0}`")]
+    UndeclaredAssignment(String),
+
+    #[error("undefined function `{0}`")]
+    UndefinedFunction(String),
+
+    #[error("`{name}` expects {expected} argument(s), found {found}")]
+    Arity {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+
+    #[error("type error: {0}")]
+    Type(String),
+
+    #[error("io error: {0}")]
+    Io(String),
+
+    #[error("invalid json at {line}:{column}: {message}")]
+    Json {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    #[error("permission denied: {0}")]
+    Permission(String),
+
+    #[error("limit exceeded: {0}")]
+    LimitExceeded(String),
+
+    #[error("deadlock: every task is blocked")]
+    Deadlock,
+
+    #[error("import error: {0}")]
+    Import(String),
+
+    #[error("uncaught {}", thrown(.0))]
+    Thrown(Expression),
+
+    /// An error and the statements it passed through, innermost first.
+    #[error("{0}")]
+    At(Box<RuntimeError>, Vec<Location>),
+
+    #[error("exit with code {0}")]
+    Exit(i32),
+}
+
+/// A statement an error passed through and the function running it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    /// Set once the error leaves the function; the top level has none.
+    pub function: Option<String>,
+    pub span: Span,
+}
+
+/// Shows a thrown value; a caught error thrown again shows its message.
+fn thrown(value: &Expression) -> String {
+    match value.get("message") {
+        Some(Expression::String(t)) => t.to_string(),
+        _ => display(value),
+    }
+}
+
+impl RuntimeError {
+    /// Records the statement the error passed through, unless it already
+    /// has one in the running function. Exits carry no location.
+    pub fn at(self, span: Span) -> Self {
+        let location = Location {
+            function: None,
+            span,
+        };
+
+        match self {
+            RuntimeError::At(t, mut trace) => {
+                if trace.last().is_some_and(|t| t.function.is_some()) {
+                    trace.push(location);
+                }
+                RuntimeError::At(t, trace)
+            }
+            t @ RuntimeError::Exit(_) => t,
+            t => RuntimeError::At(t.boxed(), vec![location]),
+        }
+    }
+
+    /// Records that the error left a call to `function`.
+    pub fn called(mut self, function: &str) -> Self {
+        if let RuntimeError::At(_, trace) = &mut self {
+            if let Some(t @ Location { function: None, .. }) = trace.last_mut() {
+                t.function = Some(function.to_string());
+            }
+        }
+        self
+    }
+
+    pub fn boxed(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    /// The error without its location.
+    pub fn cause(&self) -> &Self {
+        match self {
+            RuntimeError::At(t, _) => t.cause(),
+            t => t,
+        }
+    }
+
+    /// Where the error was raised.
+    pub fn span(&self) -> Option<Span> {
+        self.trace().first().map(|t| t.span)
+    }
+
+    /// The statements the error passed through, innermost first.
+    pub fn trace(&self) -> &[Location] {
+        match self {
+            RuntimeError::At(_, trace) => trace,
+            _ => &[],
+        }
+    }
+
+    /// Shows the statements the error passed through, innermost last, with
+    /// their lines from `files`, the name and source of each of the
+    /// program's files. Files other than the first are named.
+    pub fn traceback(&self, files: &[(String, String)]) -> String {
+        let mut out = String::from("traceback (innermost last):\n");
+        for t in self.trace().iter().rev() {
+            let file = files.get(t.span.file);
+
+            out.push_str("  ");
+            if let Some((name, _)) = file.filter(|_| t.span.file > 0) {
+                out.push_str(&format!("{}, ", name));
+            }
+            out.push_str(&format!(
+                "line {}, column {}, in {}\n",
+                t.span.line,
+                t.span.column,
+                t.function.as_deref().unwrap_or("<main>")
+            ));
+
+            let line = file.and_then(|(_, source)| source.lines().nth(t.span.line.checked_sub(1)?));
+            if let Some(line) = line {
+                out.push_str(&format!("    {}\n", line.trim()));
+            }
+        }
+        out
+    }
+
+    /// The traceback, if any, followed by the error itself, as the program
+    /// would print it on failing.
+    pub fn report(&self, files: &[(String, String)]) -> String {
+        let mut out = String::new();
+        if !self.trace().is_empty() {
+            out.push_str(&self.traceback(files));
+        }
+        out.push_str(&format!(
+            "error: {}
+",
+            self
+        ));
+        out
+    }
+
+    /// Whether `catch` can handle the error. Exits, exceeded budgets and
+    /// deadlocks always end the program; a deadlock wakes every blocked task
+    /// at once, so which of them caught it would be down to timing.
+    pub fn catchable(&self) -> bool {
+        !matches!(
+            self.cause(),
+            RuntimeError::Exit(_) | RuntimeError::LimitExceeded(_) | RuntimeError::Deadlock
+        )
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self.cause() {
+            RuntimeError::UndefinedVariable(_) => "undefined_variable",
+            RuntimeError::UseBeforeDefinition(_) => "use_before_definition",
+            RuntimeError::UndeclaredAssignment(_) => "undeclared_assignment",
+            RuntimeError::UndefinedFunction(_) => "undefined_function",
+            RuntimeError::Arity { .. } => "arity",
+            RuntimeError::Type(_) => "type",
+            RuntimeError::Io(_) => "io",
+            RuntimeError::Json { .. } => "json",
+            RuntimeError::Permission(_) => "permission",
+            RuntimeError::LimitExceeded(_) => "limit",
+            RuntimeError::Deadlock => "deadlock",
+            RuntimeError::Import(_) => "import",
+            RuntimeError::Thrown(_) => "thrown",
+            RuntimeError::At(..) => unreachable!("cause is never located"),
+            RuntimeError::Exit(_) => "exit",
+        }
+    }
+
+    /// The value `catch` binds: a thrown value as it was thrown, and any
+    /// other error as a map of its `message`, `kind` and `location`.
+    pub fn value(&self) -> Expression {
+        if let RuntimeError::Thrown(t) = self.cause() {
+            return t.clone();
+        }
+
+        let location = match self.span() {
+            Some(span) => Expression::map(vec![
+                ("line", Expression::Number(span.line as f64)),
+                ("column", Expression::Number(span.column as f64)),
+            ]),
+            None => Expression::Nil,
+        };
+        Expression::map(vec![
+            ("message", Expression::String(self.cause().to_string())),
+            ("kind", Expression::String(String::from(self.kind()))),
+            ("location", location),
+        ])
+    }
+}
@@ -0,0 +1,253 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+thread_local! {
+    /// Whether this thread holds the turn of its program's event loop.
+    static TURN: Cell<bool> = const { Cell::new(false) };
+}
+
+/// How many futures may run at once; the rest wait in line for a slot.
+pub const MAX_RUNNING: usize = 64;
+
+/// Work queued until a slot frees up.
+pub type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct State {
+    /// Whether a future holds the turn.
+    busy: bool,
+    /// Futures holding a slot: started, and not waiting on another task.
+    running: usize,
+    /// Futures waiting for a slot, oldest first.
+    queue: VecDeque<Job>,
+}
+
+/// Lets the futures of one program take turns, so only one of them runs
+/// plang code at a time. A future gives up its turn while it awaits another
+/// or waits on a blocking built-in, which is where futures overlap.
+///
+/// Each running future has a thread of its own, and at most [`MAX_RUNNING`]
+/// of them hold a slot. A future waiting on another task gives its slot
+/// back, so the future it waits for can always start.
+#[derive(Clone, Default)]
+pub struct EventLoop(Arc<(Mutex<State>, Condvar)>);
+
+/// The turn of the running future, given back when dropped.
+pub struct Turn<'a>(&'a EventLoop);
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        self.0.give();
+    }
+}
+
+impl std::fmt::Debug for EventLoop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EventLoop")
+    }
+}
+
+impl EventLoop {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.0 .0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn sleep<'a>(&self, state: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        self.0
+             .1
+            .wait(state)
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn take(&self) {
+        let mut state = self.lock();
+        while state.busy {
+            state = self.sleep(state);
+        }
+        state.busy = true;
+        TURN.with(|t| t.set(true));
+    }
+
+    fn give(&self) {
+        if !TURN.with(|t| t.replace(false)) {
+            return;
+        }
+        self.lock().busy = false;
+        self.0 .1.notify_all();
+    }
+
+    /// Starts queued futures while slots are free.
+    fn fill(&self, state: &mut State) {
+        while state.running < MAX_RUNNING {
+            match state.queue.pop_front() {
+                Some(job) => {
+                    state.running += 1;
+                    let event_loop = self.clone();
+                    thread::spawn(move || event_loop.work(job));
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Runs `job` and then queued ones in the same slot until none are left.
+    fn work(&self, mut job: Job) {
+        loop {
+            job();
+
+            let mut state = self.lock();
+            match state.queue.pop_front() {
+                Some(next) => job = next,
+                None => {
+                    state.running -= 1;
+                    self.0 .1.notify_all();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Runs `job` as a future once a slot is free.
+    pub fn spawn(&self, job: Job) {
+        let mut state = self.lock();
+        state.queue.push_back(job);
+        self.fill(&mut state);
+    }
+
+    /// Waits for the calling future's turn.
+    pub fn enter(&self) -> Turn<'_> {
+        self.take();
+        Turn(self)
+    }
+
+    /// Runs `f`, which may block, letting other futures run meanwhile. Code
+    /// outside any future holds no turn and simply runs `f`.
+    pub fn suspend<T>(&self, f: impl FnOnce() -> T) -> T {
+        if !TURN.with(Cell::get) {
+            return f();
+        }
+        self.give();
+        let out = f();
+        self.take();
+        out
+    }
+
+    /// Like `suspend`, for `f` that waits on other tasks of the program: the
+    /// future also gives up its slot, so that what it waits for can start.
+    pub fn wait<T>(&self, f: impl FnOnce() -> T) -> T {
+        if !TURN.with(Cell::get) {
+            return f();
+        }
+        self.give();
+        {
+            let mut state = self.lock();
+            state.running -= 1;
+            self.fill(&mut state);
+        }
+
+        let out = f();
+
+        let mut state = self.lock();
+        while state.running >= MAX_RUNNING {
+            state = self.sleep(state);
+        }
+        state.running += 1;
+        drop(state);
+
+        self.take();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    #[test]
+    fn test1() {
+        let event_loop = EventLoop::default();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let barrier = Arc::new(Barrier::new(2));
+
+        let workers: Vec<_> = (0..2)
+            .map(|i| {
+                let (event_loop, log, barrier) = (event_loop.clone(), log.clone(), barrier.clone());
+                thread::spawn(move || {
+                    let _turn = event_loop.enter();
+                    log.lock().unwrap().push(i);
+                    event_loop.suspend(|| barrier.wait());
+                    log.lock().unwrap().push(i);
+                })
+            })
+            .collect();
+        for w in workers {
+            w.join().unwrap();
+        }
+
+        // The waits overlapped, so both futures started before either ended.
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 4);
+        assert_ne!(log[0], log[1]);
+        assert_eq!(event_loop.suspend(|| 1), 1);
+    }
+
+    #[test]
+    fn test2() {
+        let event_loop = EventLoop::default();
+        let (now, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let (done, finished) = mpsc::channel();
+
+        for _ in 0..MAX_RUNNING * 2 {
+            let (event_loop2, now, peak, done) =
+                (event_loop.clone(), now.clone(), peak.clone(), done.clone());
+            event_loop.spawn(Box::new(move || {
+                let _turn = event_loop2.enter();
+                peak.fetch_max(now.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                event_loop2.suspend(|| thread::sleep(Duration::from_millis(20)));
+                now.fetch_sub(1, Ordering::SeqCst);
+                done.send(()).unwrap();
+            }));
+        }
+
+        for _ in 0..MAX_RUNNING * 2 {
+            finished.recv().unwrap();
+        }
+        assert!(peak.load(Ordering::SeqCst) <= MAX_RUNNING);
+        assert!(peak.load(Ordering::SeqCst) > 1);
+    }
+
+    #[test]
+    fn test3() {
+        // A future waiting on one still in line gives it its slot.
+        let event_loop = EventLoop::default();
+        let (tx, rx) = mpsc::channel();
+        let (done, finished) = mpsc::channel();
+        let rx = Arc::new(Mutex::new(rx));
+
+        for _ in 0..MAX_RUNNING {
+            let (event_loop2, rx, done) = (event_loop.clone(), rx.clone(), done.clone());
+            event_loop.spawn(Box::new(move || {
+                let _turn = event_loop2.enter();
+                event_loop2.wait(|| rx.lock().unwrap().recv().unwrap());
+                done.send(()).unwrap();
+            }));
+        }
+        let sender = event_loop.clone();
+        event_loop.spawn(Box::new(move || {
+            let _turn = sender.enter();
+            for _ in 0..MAX_RUNNING {
+                tx.send(()).unwrap();
+            }
+        }));
+
+        for _ in 0..MAX_RUNNING {
+            finished.recv_timeout(Duration::from_secs(10)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::mem;
+use std::time::{Duration, Instant};
+
+use super::RuntimeError;
+use crate::parser::ast::Expression;
+
+/// Budgets for a running program; `None` means unlimited.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Limits {
+    /// Statements and expressions evaluated.
+    pub steps: Option<u64>,
+    /// Nested function calls.
+    pub depth: Option<usize>,
+    /// Wall time since the program started.
+    pub time: Option<Duration>,
+    /// Approximate bytes held by variables.
+    pub memory: Option<usize>,
+}
+
+impl Limits {
+    /// Checks a call made `depth` calls deep. Each thread counts its own
+    /// depth, since its calls do not nest inside another thread's.
+    pub fn enter(&self, depth: usize) -> Result<(), RuntimeError> {
+        match self.depth {
+            Some(max) if depth >= max => Err(RuntimeError::LimitExceeded(format!(
+                "call depth above {}",
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// When a program runs out of time, which blocking built-ins wait for at
+/// most, since no steps are counted while they block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deadline {
+    pub at: Instant,
+    max: Duration,
+}
+
+impl Deadline {
+    /// Fails once the deadline has passed.
+    pub fn check(&self) -> Result<(), RuntimeError> {
+        if Instant::now() >= self.at {
+            return Err(RuntimeError::LimitExceeded(format!(
+                "ran longer than {}ms",
+                self.max.as_millis()
+            )));
+        }
+        Ok(())
+    }
+
+    /// The time left, at most `timeout` and at least a millisecond, since
+    /// sockets take no zero timeout.
+    pub fn clamp(&self, timeout: Duration) -> Duration {
+        timeout
+            .min(self.at.saturating_duration_since(Instant::now()))
+            .max(Duration::from_millis(1))
+    }
+}
+
+/// How much of the shared budgets a program has used so far.
+#[derive(Debug, Default)]
+pub struct Usage {
+    steps: u64,
+    memory: usize,
+    started: Option<Instant>,
+}
+
+impl Usage {
+    pub fn step(&mut self, limits: &Limits) -> Result<(), RuntimeError> {
+        self.steps += 1;
+
+        if let Some(max) = limits.steps {
+            if self.steps > max {
+                return Err(RuntimeError::LimitExceeded(format!(
+                    "more than {} steps",
+                    max
+                )));
+            }
+        }
+
+        match self.deadline(limits) {
+            Some(deadline) => deadline.check(),
+            None => Ok(()),
+        }
+    }
+
+    /// The end of the time limit, counted from the program's first step.
+    pub fn deadline(&mut self, limits: &Limits) -> Option<Deadline> {
+        let max = limits.time?;
+        let started = *self.started.get_or_insert_with(Instant::now);
+        Some(Deadline {
+            at: started.checked_add(max)?,
+            max,
+        })
+    }
+
+    pub fn allocate(&mut self, limits: &Limits, bytes: usize) -> Result<(), RuntimeError> {
+        match limits.memory {
+            Some(max) if self.memory + bytes > max => Err(RuntimeError::LimitExceeded(format!(
+                "more than {} bytes of memory",
+                max
+            ))),
+            _ => {
+                self.memory += bytes;
+                Ok(())
+            }
+        }
+    }
+
+    /// Swaps an allocation of `old` bytes for one of `new` bytes.
+    pub fn replace(&mut self, limits: &Limits, old: usize, new: usize) -> Result<(), RuntimeError> {
+        self.free(old);
+
+        if let Err(e) = self.allocate(limits, new) {
+            self.memory += old;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn free(&mut self, bytes: usize) {
+        self.memory = self.memory.saturating_sub(bytes);
+    }
+}
+
+/// Approximate heap footprint of a variable binding.
+pub fn size_of(name: &str, value: &Expression) -> usize {
+    name.len() + mem::size_of::<String>() + value_size(value)
+}
+
+fn value_size(value: &Expression) -> usize {
+    mem::size_of::<Expression>()
+        + match value {
+            Expression::String(t) | Expression::Identifier(t) => t.len(),
+            Expression::List(t) => t.iter().map(value_size).sum(),
+            Expression::Map(t) => t.iter().map(|(k, v)| value_size(k) + value_size(v)).sum(),
+            _ => 0,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test1() {
+        let limits = Limits {
+            steps: Some(2),
+            ..Limits::default()
+        };
+        let mut usage = Usage::default();
+
+        assert_eq!(usage.step(&limits), Ok(()));
+        assert_eq!(usage.step(&limits), Ok(()));
+        assert!(matches!(
+            usage.step(&limits),
+            Err(RuntimeError::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test2() {
+        let limits = Limits {
+            depth: Some(1),
+            ..Limits::default()
+        };
+
+        assert_eq!(limits.enter(0), Ok(()));
+        assert!(limits.enter(1).is_err());
+        assert_eq!(Limits::default().enter(1000), Ok(()));
+    }
+
+    #[test]
+    fn test3() {
+        let small = size_of("x", &Expression::Number(1.0));
+        let large = size_of(
+            "x",
+            &Expression::List(vec![Expression::String(String::from("abc")); 10]),
+        );
+
+        assert!(large > small * 5);
+    }
+}
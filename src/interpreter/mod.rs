@@ -1,28 +1,172 @@
 use std::collections::HashMap;
+use std::f64::consts;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
-use crate::parser::ast::{Expression, Op, Statement};
-use crate::stdlib::Std;
+use crate::parser::ast::{Expression, Statement};
+use crate::resolver::resolve;
+use crate::stdlib::{Runtime, Std};
 
-fn register_globals(interpreter: &mut Interpreter) {
-    interpreter.register_global("print", Std::Print);
-    interpreter.register_global("cmd", Std::Cmd);
+pub use context::Context;
+pub use error::RuntimeError;
+pub use event_loop::EventLoop;
+pub use limits::{size_of, Deadline, Limits};
+pub use ops::{index_value, infix, insert};
+pub use permissions::Permissions;
+pub use rng::Rng;
+pub use scheduler::Scheduler;
+
+mod context;
+mod error;
+mod event_loop;
+mod limits;
+mod ops;
+mod permissions;
+mod rng;
+mod scheduler;
+
+/// The built-in functions every program can call, by name.
+pub fn globals() -> HashMap<String, Std> {
+    [
+        ("print", Std::Print),
+        ("cmd", Std::Cmd),
+        ("pipe", Std::Pipe),
+        ("args", Std::Args),
+        ("env", Std::Env),
+        ("set_env", Std::SetEnv),
+        ("exit", Std::Exit),
+        ("http_get", Std::HttpGet),
+        ("http_post", Std::HttpPost),
+        ("http_request", Std::HttpRequest),
+        ("serve", Std::Serve),
+        ("read_file", Std::ReadFile),
+        ("write_file", Std::WriteFile),
+        ("append_file", Std::AppendFile),
+        ("read_lines", Std::ReadLines),
+        ("list_dir", Std::ListDir),
+        ("exists", Std::Exists),
+        ("stat", Std::Stat),
+        ("mkdir", Std::Mkdir),
+        ("remove", Std::Remove),
+        ("copy", Std::Copy),
+        ("rename", Std::Rename),
+        ("json_parse", Std::JsonParse),
+        ("json_stringify", Std::JsonStringify),
+        ("input", Std::Input),
+        ("read_line", Std::ReadLine),
+        ("read_all", Std::ReadAll),
+        ("stdin_lines", Std::StdinLines),
+        ("len", Std::Len),
+        ("upper", Std::Upper),
+        ("lower", Std::Lower),
+        ("trim", Std::Trim),
+        ("split", Std::Split),
+        ("join", Std::Join),
+        ("replace", Std::Replace),
+        ("contains", Std::Contains),
+        ("starts_with", Std::StartsWith),
+        ("ends_with", Std::EndsWith),
+        ("find", Std::Find),
+        ("repeat", Std::Repeat),
+        ("chars", Std::Chars),
+        ("to_string", Std::ToString),
+        ("to_number", Std::ToNumber),
+        ("parse_bool", Std::ParseBool),
+        ("push", Std::Push),
+        ("pop", Std::Pop),
+        ("insert", Std::Insert),
+        ("reverse", Std::Reverse),
+        ("sort", Std::Sort),
+        ("index_of", Std::IndexOf),
+        ("concat", Std::Concat),
+        ("map", Std::Map),
+        ("filter", Std::Filter),
+        ("reduce", Std::Reduce),
+        ("any", Std::Any),
+        ("all", Std::All),
+        ("zip", Std::Zip),
+        ("enumerate", Std::Enumerate),
+        ("flatten", Std::Flatten),
+        ("unique", Std::Unique),
+        ("abs", Std::Abs),
+        ("floor", Std::Floor),
+        ("ceil", Std::Ceil),
+        ("round", Std::Round),
+        ("sqrt", Std::Sqrt),
+        ("pow", Std::Pow),
+        ("exp", Std::Exp),
+        ("log", Std::Log),
+        ("sin", Std::Sin),
+        ("cos", Std::Cos),
+        ("tan", Std::Tan),
+        ("asin", Std::Asin),
+        ("acos", Std::Acos),
+        ("atan", Std::Atan),
+        ("atan2", Std::Atan2),
+        ("min", Std::Min),
+        ("max", Std::Max),
+        ("sum", Std::Sum),
+        ("clamp", Std::Clamp),
+        ("idiv", Std::Idiv),
+        ("is_nan", Std::IsNan),
+        ("is_finite", Std::IsFinite),
+        ("random", Std::Random),
+        ("random_int", Std::RandomInt),
+        ("choice", Std::Choice),
+        ("shuffle", Std::Shuffle),
+        ("seed", Std::Seed),
+        ("now", Std::Now),
+        ("clock", Std::Clock),
+        ("sleep", Std::Sleep),
+        ("format_time", Std::FormatTime),
+        ("parse_time", Std::ParseTime),
+        ("par_map", Std::ParMap),
+        ("par_filter", Std::ParFilter),
+        ("spawn", Std::Spawn),
+        ("channel", Std::Channel),
+        ("send", Std::Send),
+        ("recv", Std::Recv),
+        ("try_recv", Std::TryRecv),
+        ("close", Std::Close),
+        ("select", Std::Select),
+        ("gather", Std::Gather),
+    ]
+    .into_iter()
+    .map(|(name, std)| (String::from(name), std))
+    .collect()
 }
 
-pub fn interpret(input: Vec<Statement>) {
-    let mut interpreter = Interpreter::new(HashMap::new(), HashMap::new(), HashMap::new());
+/// The named numbers every program can read, which the resolver puts in
+/// place of their names.
+pub fn constant(name: &str) -> Option<Expression> {
+    match name {
+        "PI" => Some(Expression::Number(consts::PI)),
+        "E" => Some(Expression::Number(consts::E)),
+        _ => None,
+    }
+}
 
-    register_globals(&mut interpreter);
+pub fn interpret(input: Vec<Statement>, context: Context) -> Result<(), RuntimeError> {
+    let input = resolve(input)?;
+    let mut interpreter =
+        Interpreter::new(globals(), HashMap::new(), Arc::new(Mutex::new(context)));
 
-    interpreter.run(input);
+    interpreter.run(input)?;
+    Ok(())
 }
 
 type Block = Vec<Statement>;
 
+/// A bound variable and the bytes charged for it.
+type Slot = Option<(Expression, usize)>;
+
+type Items = Box<dyn Iterator<Item = Result<Expression, RuntimeError>>>;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Program {
     Fn {
         params: Vec<Expression>,
-        body: Block,
+        body: Arc<Block>,
     },
 }
 
@@ -30,271 +174,445 @@ pub enum Program {
 struct Interpreter {
     globals: HashMap<String, Std>,
     functions: HashMap<String, Program>,
-    variables: HashMap<String, Expression>,
+    /// Variables by the scope depth and slot the resolver gave them.
+    scopes: Vec<Vec<Slot>>,
+    /// Whether this runs a function body, where `return f(x)` is a tail call.
+    function: bool,
+    /// How many calls deep this runs on its thread.
+    depth: usize,
+    context: Arc<Mutex<Context>>,
 }
 
 impl Interpreter {
     fn new(
         globals: HashMap<String, Std>,
         functions: HashMap<String, Program>,
-        variables: HashMap<String, Expression>,
+        context: Arc<Mutex<Context>>,
     ) -> Self {
         Self {
             globals,
             functions,
-            variables,
+            scopes: vec![Vec::new()],
+            function: false,
+            depth: 0,
+            context,
+        }
+    }
+
+    fn scope(&self) -> Self {
+        let mut i = Interpreter::new(
+            self.globals.clone(),
+            self.functions.clone(),
+            self.context.clone(),
+        );
+        i.function = true;
+        i.depth = self.depth + 1;
+        i
+    }
+
+    /// Counts one unit of work against the step and time budgets.
+    fn step(&self) -> Result<(), RuntimeError> {
+        let mut context = self.context();
+        let context = &mut *context;
+
+        context.usage.step(&context.limits)
+    }
+
+    fn get(&self, depth: usize, slot: usize) -> Option<&Expression> {
+        self.scopes.get(depth)?.get(slot)?.as_ref().map(|(v, _)| v)
+    }
+
+    /// Binds a variable, charging its size against the memory budget.
+    fn set(
+        &mut self,
+        name: &str,
+        depth: usize,
+        slot: usize,
+        value: Expression,
+    ) -> Result<(), RuntimeError> {
+        let scope = &mut self.scopes[depth];
+        if scope.len() <= slot {
+            scope.resize(slot + 1, None);
         }
+
+        let bytes = {
+            let mut context = self.context.lock().unwrap_or_else(PoisonError::into_inner);
+            let context = &mut *context;
+
+            if context.limits.memory.is_some() {
+                let old = scope[slot].as_ref().map_or(0, |(_, bytes)| *bytes);
+                let bytes = size_of(name, &value);
+                context.usage.replace(&context.limits, old, bytes)?;
+                bytes
+            } else {
+                0
+            }
+        };
+
+        scope[slot] = Some((value, bytes));
+        Ok(())
     }
 
-    fn register_global(&mut self, name: &str, std: Std) {
-        self.globals.insert(String::from(name), std);
+    fn release(&self, scope: &[Slot]) {
+        let mut context = self.context();
+
+        if context.limits.memory.is_some() {
+            let bytes = scope.iter().flatten().map(|(_, bytes)| bytes).sum();
+            context.usage.free(bytes);
+        }
     }
 
-    fn run(&mut self, ast: Vec<Statement>) -> Option<Expression> {
-        let mut ast = ast.into_iter();
+    fn run(&mut self, ast: Vec<Statement>) -> Result<Option<Expression>, RuntimeError> {
         let mut out: Option<Expression> = None;
 
-        while let Some(statement) = ast.next() {
-            out = self.run_statement(statement);
-            if out != None {
+        for statement in ast {
+            out = self.run_statement(statement)?;
+            if out.is_some() {
                 break;
             }
         }
-        out
+        Ok(out)
     }
 
-    fn run_statement(&mut self, input: Statement) -> Option<Expression> {
+    fn run_statement(&mut self, input: Statement) -> Result<Option<Expression>, RuntimeError> {
+        match input {
+            Statement::At(span, t) => self.run_inner(*t).map_err(|e| e.at(span)),
+            t => self.run_inner(t),
+        }
+    }
+
+    /// Runs a statement that has been unwrapped from its span.
+    fn run_inner(&mut self, input: Statement) -> Result<Option<Expression>, RuntimeError> {
+        self.step()?;
+
         match input {
             Statement::Let { name, initial } => {
-                let temp = match self.evaluate(initial) {
-                    Some(t) => t,
-                    None => panic!("cannot use let without value"),
-                };
+                let temp = self.evaluate(initial)?;
+                let (name, depth, slot) = local(name)?;
 
-                self.variables.insert(
-                    match name {
-                        Expression::Identifier(t) => t,
-                        _ => panic!("Enter proper identifier"),
-                    },
-                    temp,
-                );
-                None
+                self.set(&name, depth, slot, temp)?;
+                Ok(None)
             }
 
             Statement::Fn { name, params, body } => {
                 self.functions.insert(
-                    match name {
-                        Expression::Identifier(t) => t,
-                        _ => panic!("Enter proper function name"),
+                    identifier(name)?,
+                    Program::Fn {
+                        params,
+                        body: Arc::new(body),
                     },
-                    Program::Fn { params, body },
                 );
-                None
+                Ok(None)
             }
 
             Statement::If {
                 condition,
                 then,
                 otherwise,
-            } => match self.evaluate(condition) {
-                Some(cond) => match cond {
-                    Expression::Boolean(t) => {
-                        if t {
-                            self.run(then)
-                        } else {
-                            match otherwise {
-                                Some(o) => self.run(o),
-                                None => None,
-                            }
+            } => match self.evaluate(condition)? {
+                Expression::Boolean(t) => {
+                    if t {
+                        self.run(then)
+                    } else {
+                        match otherwise {
+                            Some(o) => self.run(o),
+                            None => Ok(None),
                         }
                     }
-                    _ => panic!("must be true or false conditional value"),
-                },
-                None => panic!("condition does not return value"),
+                }
+                _ => Err(RuntimeError::Type(String::from(
+                    "must be true or false conditional value",
+                ))),
             },
 
             Statement::Loop {
                 iterable,
                 value,
                 then,
-            } => match iterable {
-                Some(Expression::Identifier(t)) => {
-                    let mut i = Interpreter::new(
-                        self.globals.clone(),
-                        self.functions.clone(),
-                        self.variables.clone(),
-                    );
-
-                    match value {
-                        Some(ts) => match self.evaluate(ts)? {
-                            Expression::List(val) => {
-                                let mut temp: Option<Expression> = None;
-
-                                for n in val {
-                                    i.variables.insert(t.to_string(), n);
-                                    let l = i.run(then.clone());
-
-                                    match l {
-                                        Some(y) => {
-                                            temp = Some(y);
-                                            break;
-                                        }
-                                        _ => {}
-                                    }
-                                }
-
-                                let keys: Vec<String> =
-                                    i.variables.keys().map(|f| f.to_string()).collect();
-
-                                for k in keys {
-                                    if !self.variables.contains_key(&k) {
-                                        i.variables.remove(&k);
-                                    }
-                                }
-
-                                self.variables.extend(i.variables);
-
-                                match &temp {
-                                    Some(Expression::Break) => None,
-                                    _ => temp,
-                                }
+            } => {
+                let items = match iterable {
+                    Some(Expression::Local(name, depth, slot)) => {
+                        let value = match value {
+                            Some(ts) => self.evaluate(ts)?,
+                            None => {
+                                return Err(RuntimeError::Type(String::from(
+                                    "value cannot be None",
+                                )))
                             }
-                            _ => panic!("insert proper iterator"),
-                        },
-                        None => panic!("value cannot be None"),
-                    }
-                }
-                None => {
-                    let mut i = Interpreter::new(
-                        self.globals.clone(),
-                        self.functions.clone(),
-                        self.variables.clone(),
-                    );
-
-                    let temp = loop {
-                        let l = i.run(then.clone());
-
-                        match l {
-                            Some(y) => break y,
-                            _ => {}
-                        }
-                    };
-
-                    let keys: Vec<String> = i.variables.keys().map(|f| f.to_string()).collect();
+                        };
 
-                    for k in keys {
-                        if !self.variables.contains_key(&k) {
-                            i.variables.remove(&k);
-                        }
+                        let items: Items = match value {
+                            Expression::List(val) => Box::new(val.into_iter().map(Ok)),
+                            Expression::Lines(lines) => Box::new(lines.map(|line| {
+                                line.map(Expression::String)
+                                    .map_err(|e| RuntimeError::Io(e.to_string()))
+                            })),
+                            _ => {
+                                return Err(RuntimeError::Type(String::from(
+                                    "insert proper iterator",
+                                )))
+                            }
+                        };
+                        Some(((name, depth, slot), items))
                     }
+                    None => None,
+                    _ => {
+                        return Err(RuntimeError::Type(String::from(
+                            "loop variable must be an identifier",
+                        )))
+                    }
+                };
 
-                    self.variables.extend(i.variables);
+                // Functions declared in the body and the body's own variables
+                // go away with the loop.
+                let functions = self.functions.clone();
+                self.scopes.push(Vec::new());
 
-                    match &temp {
-                        Expression::Break => None,
-                        _ => Some(temp),
-                    }
-                }
+                let temp = self.repeat(items, then);
 
-                _ => panic!(),
-            },
+                let scope = self.scopes.pop().unwrap_or_default();
+                self.release(&scope);
+                self.functions = functions;
+
+                Ok(match temp? {
+                    Some(Expression::Break) => None,
+                    temp => temp,
+                })
+            }
 
             Statement::Return { value } => match value {
-                Some(t) => self.evaluate(t),
-                None => None,
+                Some(Expression::Call(name, params)) if self.function => {
+                    Ok(Some(self.call_expression(*name, params, true)?))
+                }
+                Some(t) => Ok(Some(self.evaluate(t)?)),
+                None => Ok(Some(Expression::Nil)),
             },
 
-            Statement::Break => Some(Expression::Break),
+            Statement::Break => Ok(Some(Expression::Break)),
 
             Statement::Expr { expression } => {
-                self.evaluate(expression);
-                None
+                self.evaluate(expression)?;
+                Ok(None)
+            }
+
+            Statement::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                // A call in tail position would run after the handlers are gone.
+                let function = mem::replace(&mut self.function, false);
+                let mut out = self.run(body);
+
+                if let Some((name, then)) = catch {
+                    let caught = match &out {
+                        Err(e) if e.catchable() => Some(e.value()),
+                        _ => None,
+                    };
+
+                    if let Some(value) = caught {
+                        self.function = function && finally.is_none();
+                        out = local(name)
+                            .and_then(|(name, depth, slot)| self.set(&name, depth, slot, value))
+                            .and_then(|_| self.run(then));
+                    }
+                }
+                self.function = function;
+
+                // A `finally` block that fails, returns or breaks has the last
+                // word, unless the error in flight cannot be caught.
+                match (out, finally.map(|f| self.run(f)).transpose()) {
+                    (Err(e), _) if !e.catchable() => Err(e),
+                    (_, Err(e)) => Err(e),
+                    (_, Ok(Some(Some(t)))) => Ok(Some(t)),
+                    (out, _) => out,
+                }
+            }
+
+            Statement::Throw { value } => Err(RuntimeError::Thrown(self.evaluate(value)?)),
+            Statement::At(..) => unreachable!("spans are unwrapped above"),
+            Statement::Import { .. } | Statement::Export(_) => {
+                unreachable!("the resolver rejects imports and exports")
             }
         }
     }
 
-    fn call(&mut self, input: Program, params: Vec<Expression>) -> Option<Expression> {
-        let (p, body) = match input {
-            Program::Fn { params, body } => (params, body),
-        };
+    /// Runs a loop body until it finishes, breaks or returns.
+    fn repeat(
+        &mut self,
+        mut items: Option<((String, usize, usize), Items)>,
+        then: Block,
+    ) -> Result<Option<Expression>, RuntimeError> {
+        loop {
+            self.step()?;
 
-        let a = params.len();
-        let b = p.len();
-        if a != b {
-            panic!("provide necessary arguments");
+            if let Some(((name, depth, slot), items)) = &mut items {
+                match items.next() {
+                    Some(n) => self.set(name, *depth, *slot, n?)?,
+                    None => return Ok(None),
+                }
+            }
+
+            if let Some(y) = self.run(then.clone())? {
+                return Ok(Some(y));
+            }
         }
+    }
+
+    fn call(
+        &mut self,
+        name: &str,
+        mut input: Program,
+        mut params: Vec<Expression>,
+    ) -> Result<Expression, RuntimeError> {
+        let mut name = name.to_string();
+
+        // Tail calls replace the running function instead of nesting.
+        loop {
+            let (p, body) = match input {
+                Program::Fn { params, body } => (params, body),
+            };
+
+            if params.len() != p.len() {
+                return Err(RuntimeError::Arity {
+                    name,
+                    expected: p.len(),
+                    found: params.len(),
+                });
+            }
+
+            for scope in mem::replace(&mut self.scopes, vec![Vec::new()]) {
+                self.release(&scope);
+            }
 
-        for i in 0..a {
-            self.variables.insert(
-                match p.get(i).unwrap() {
-                    Expression::Identifier(l) => l.to_string(),
-                    _ => panic!(),
+            for (slot, (p, value)) in p.into_iter().zip(params).enumerate() {
+                self.set(&identifier(p)?, 0, slot, value)?;
+            }
+
+            match self.run(body.to_vec()).map_err(|e| e.called(&name))? {
+                Some(Expression::TailCall(function, args)) => match *function {
+                    Expression::Function(n, p, body) => {
+                        name = n;
+                        input = Program::Fn { params: p, body };
+                        params = args;
+                    }
+                    t => return Err(RuntimeError::Type(format!("cannot call {:?}", t))),
                 },
-                params.get(i)?.clone(),
-            );
+                Some(Expression::Break) | None => return Ok(Expression::Nil),
+                Some(t) => return Ok(t),
+            }
         }
+    }
+
+    /// Runs a function in a fresh scope, counting it against the depth budget.
+    fn call_function(
+        &mut self,
+        name: &str,
+        program: Program,
+        params: Vec<Expression>,
+    ) -> Result<Expression, RuntimeError> {
+        self.context().limits.enter(self.depth)?;
+        self.scope().call(name, program, params)
+    }
 
-        self.run(body)
+    fn call_value(
+        &mut self,
+        function: Expression,
+        params: Vec<Expression>,
+    ) -> Result<Expression, RuntimeError> {
+        match function {
+            Expression::Function(name, p, body) => {
+                self.call_function(&name, Program::Fn { params: p, body }, params)
+            }
+            t => Err(RuntimeError::Type(format!("cannot call {:?}", t))),
+        }
     }
 
-    fn evaluate(&mut self, input: Expression) -> Option<Expression> {
-        match input {
-            Expression::Number(t) => Some(Expression::Number(t)),
-            Expression::String(t) => Some(Expression::String(t)),
-            Expression::Boolean(t) => Some(Expression::Boolean(t)),
-            Expression::Identifier(t) => {
-                if self.variables.contains_key(&t) {
-                    self.evaluate(self.variables.get(&t).unwrap().clone())
-                } else {
-                    panic!("Wrong identifier {:?}", t)
+    /// Looks up a name that is not bound to a variable.
+    fn global(&self, t: String) -> Result<Expression, RuntimeError> {
+        match self.functions.get(&t) {
+            Some(Program::Fn { params, body }) => {
+                Ok(Expression::Function(t, params.clone(), body.clone()))
+            }
+            None => Err(RuntimeError::UndefinedVariable(t)),
+        }
+    }
+
+    /// Evaluates a call. In tail position a call to a plang function comes
+    /// back as a `TailCall` for the enclosing `call` to run.
+    fn call_expression(
+        &mut self,
+        name: Expression,
+        params: Vec<Expression>,
+        tail: bool,
+    ) -> Result<Expression, RuntimeError> {
+        let (t, variable) = match name {
+            Expression::Local(t, depth, slot) => (t, Some((depth, slot))),
+            t => (identifier(t)?, None),
+        };
+        let target = match params.first() {
+            Some(Expression::Local(name, depth, slot)) => Some((name.clone(), *depth, *slot)),
+            _ => None,
+        };
+
+        let mut values = Vec::new();
+        for param in params {
+            values.push(self.evaluate(param)?);
+        }
+
+        if let Some(x) = self.globals.get(&t).cloned() {
+            match target {
+                Some((name, depth, slot)) if x.mutates(&values) => {
+                    let mut list = values.remove(0);
+                    let out = x.run_mut(self, &mut list, values)?;
+                    self.set(&name, depth, slot, list)?;
+                    Ok(out)
                 }
+                _ => x.run(self, values),
             }
+        } else if let Some(Program::Fn { params, body }) = self.functions.get(&t) {
+            let f = Expression::Function(t, params.clone(), body.clone());
+            self.call_or_defer(f, values, tail)
+        } else if let Some(f @ Expression::Function(..)) =
+            variable.and_then(|(depth, slot)| self.get(depth, slot))
+        {
+            self.call_or_defer(f.clone(), values, tail)
+        } else {
+            Err(RuntimeError::UndefinedFunction(t))
+        }
+    }
+
+    fn call_or_defer(
+        &mut self,
+        function: Expression,
+        params: Vec<Expression>,
+        tail: bool,
+    ) -> Result<Expression, RuntimeError> {
+        if tail {
+            Ok(Expression::TailCall(function.boxed(), params))
+        } else {
+            self.call_value(function, params)
+        }
+    }
+
+    fn evaluate(&mut self, input: Expression) -> Result<Expression, RuntimeError> {
+        self.step()?;
+
+        match input {
+            Expression::Number(t) => Ok(Expression::Number(t)),
+            Expression::String(t) => Ok(Expression::String(t)),
+            Expression::Boolean(t) => Ok(Expression::Boolean(t)),
+            Expression::Nil => Ok(Expression::Nil),
+            Expression::Local(t, depth, slot) => match self.get(depth, slot) {
+                Some(value) => Ok(value.clone()),
+                None => self.global(t),
+            },
+            Expression::Identifier(t) => self.global(t),
             Expression::Infix(left, op, right) => {
                 let left = self.evaluate(*left)?;
                 let right = self.evaluate(*right)?;
 
-                Some(match (left, op, right) {
-                    (Expression::Number(l), Op::Add, Expression::Number(r)) => {
-                        Expression::Number(l + r)
-                    }
-                    (Expression::Number(l), Op::Multiply, Expression::Number(r)) => {
-                        Expression::Number(l * r)
-                    }
-                    (Expression::Number(l), Op::Divide, Expression::Number(r)) => {
-                        Expression::Number(l / r)
-                    }
-                    (Expression::Number(l), Op::Subtract, Expression::Number(r)) => {
-                        Expression::Number(l - r)
-                    }
-                    (Expression::Number(l), Op::Equals, Expression::Number(r)) => {
-                        Expression::Boolean(l == r)
-                    }
-                    (Expression::Number(l), Op::NotEquals, Expression::Number(r)) => {
-                        Expression::Boolean(l != r)
-                    }
-                    (Expression::Number(l), Op::GreaterThan, Expression::Number(r)) => {
-                        Expression::Boolean(l > r)
-                    }
-                    (Expression::Number(l), Op::LessThan, Expression::Number(r)) => {
-                        Expression::Boolean(l < r)
-                    }
-                    (Expression::Number(l), Op::GreaterThanOrEquals, Expression::Number(r)) => {
-                        Expression::Boolean(l >= r)
-                    }
-                    (Expression::Number(l), Op::LessThanOrEquals, Expression::Number(r)) => {
-                        Expression::Boolean(l <= r)
-                    }
-                    (Expression::Boolean(l), Op::And, Expression::Boolean(r)) => {
-                        Expression::Boolean(l && r)
-                    }
-                    (Expression::Boolean(l), Op::Or, Expression::Boolean(r)) => {
-                        Expression::Boolean(l || r)
-                    }
-                    (Expression::String(l), Op::Equals, Expression::String(r)) => {
-                        Expression::Boolean(l.eq(&r))
-                    }
-                    _ => todo!(),
-                })
+                infix(left, op, right)
             }
             Expression::List(t) => {
                 let mut values: Vec<Expression> = Vec::new();
@@ -303,55 +621,118 @@ impl Interpreter {
                     values.push(self.evaluate(item)?);
                 }
 
-                Some(Expression::List(values))
+                Ok(Expression::List(values))
+            }
+            Expression::Map(t) => {
+                let mut entries: Vec<(Expression, Expression)> = Vec::new();
+
+                for (key, value) in t.into_iter() {
+                    let key = self.evaluate(key)?;
+                    let value = self.evaluate(value)?;
+
+                    insert(&mut entries, key, value);
+                }
+
+                Ok(Expression::Map(entries))
+            }
+            Expression::Index(value, index) => {
+                let value = self.evaluate(*value)?;
+                let index = self.evaluate(*index)?;
+
+                index_value(value, index)
             }
             Expression::Assign(t, value) => {
                 let value = self.evaluate(*value)?;
-                let i = match *t {
-                    Expression::Identifier(i) => i,
-                    _ => panic!("expected identifier"),
-                };
+                let (name, depth, slot) = local(*t)?;
 
-                if self.variables.contains_key(&i) {
-                    self.variables.insert(i, value);
+                if self.get(depth, slot).is_none() {
+                    return Err(RuntimeError::UndefinedVariable(name));
                 }
-                None
+                self.set(&name, depth, slot, value)?;
+                Ok(Expression::Nil)
             }
-            Expression::Call(name, params) => match *name {
-                Expression::Identifier(t) => {
-                    let params = params
-                        .into_iter()
-                        .map(|t| self.evaluate(t).unwrap())
-                        .collect();
-
-                    if self.globals.contains_key(&t) {
-                        let x = self.globals.get(&t).unwrap();
-
-                        x.run(params);
-                        None
-                    } else if self.functions.contains_key(&t) {
-                        let x = self.functions.get(&t).unwrap();
-                        let mut i = Interpreter::new(
-                            self.globals.clone(),
-                            self.functions.clone(),
-                            HashMap::new(),
-                        );
-
-                        i.call(x.clone(), params)
-                    } else {
-                        panic!("Enter proper function name")
+            Expression::Call(name, params) => self.call_expression(*name, params, false),
+            Expression::Async(function, args) => {
+                let mut values = match *function {
+                    Statement::Fn { name, params, body } => {
+                        vec![Expression::Function(
+                            identifier(name)?,
+                            params,
+                            Arc::new(body),
+                        )]
                     }
+                    t => return Err(RuntimeError::Type(format!("cannot evaluate {:?}", t))),
+                };
+                for arg in args {
+                    values.push(self.evaluate(arg)?);
                 }
-                _ => panic!("Enter proper function name"),
-            },
-            _ => panic!(),
+                Std::Async.run(self, values)
+            }
+            Expression::Await(value) => {
+                let value = self.evaluate(*value)?;
+                Std::Await.run(self, vec![value])
+            }
+            t @ Expression::Function(..) => Ok(t),
+            t => Err(RuntimeError::Type(format!("cannot evaluate {:?}", t))),
+        }
+    }
+}
+
+impl Runtime for Interpreter {
+    fn context(&self) -> MutexGuard<'_, Context> {
+        self.context.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn invoke(
+        &mut self,
+        function: Expression,
+        input: Vec<Expression>,
+    ) -> Result<Expression, RuntimeError> {
+        self.call_value(function, input)
+    }
+
+    fn fork(&self) -> Box<dyn Runtime + Send> {
+        Box::new(Interpreter::new(
+            self.globals.clone(),
+            self.functions.clone(),
+            self.context.clone(),
+        ))
+    }
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        for scope in &self.scopes {
+            self.release(scope);
         }
     }
 }
 
+fn identifier(input: Expression) -> Result<String, RuntimeError> {
+    match input {
+        Expression::Identifier(t) => Ok(t),
+        t => Err(RuntimeError::Type(format!(
+            "expected identifier, found {:?}",
+            t
+        ))),
+    }
+}
+
+/// Unpacks a variable the resolver has bound.
+fn local(input: Expression) -> Result<(String, usize, usize), RuntimeError> {
+    match input {
+        Expression::Local(t, depth, slot) => Ok((t, depth, slot)),
+        t => Err(RuntimeError::Type(format!(
+            "expected a resolved variable, found {:?}",
+            t
+        ))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vm::tests::{agree, check, exits};
 
     #[test]
     fn test1() {
@@ -378,6 +759,285 @@ mod tests {
             },
         ];
 
-        interpret(input);
+        interpret(input.clone(), Context::default()).unwrap();
+        crate::vm::run(input, Context::default()).unwrap();
+    }
+
+    #[test]
+    fn test2() {
+        let context = || Context {
+            args: vec![String::from("x")],
+            ..Context::default()
+        };
+
+        assert_eq!(
+            agree("let a = args()\nexit(7)", context),
+            Err(RuntimeError::Exit(7))
+        );
+    }
+
+    #[test]
+    fn test3() {
+        assert_eq!(
+            exits("let a = b"),
+            Err(RuntimeError::UndefinedVariable(String::from("b")))
+        );
+    }
+
+    #[test]
+    fn test4() {
+        assert_eq!(
+            exits(
+                "let m = { a: [1, 2], b: \"xy\" }
+            let x = m.a[-1]
+            let y = m[\"b\"][0]
+            let z = m.c
+            if x == 2 and y == \"x\" and z == nil {
+                exit(0)
+            }
+            exit(1)",
+            ),
+            Err(RuntimeError::Exit(0))
+        );
+    }
+
+    #[test]
+    fn test5() {
+        let limits = Limits {
+            steps: Some(1000),
+            ..Limits::default()
+        };
+
+        assert!(matches!(
+            check("loop { }", limits),
+            Err(RuntimeError::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test6() {
+        let limits = Limits {
+            depth: Some(50),
+            ..Limits::default()
+        };
+
+        assert!(matches!(
+            check("fn f(x) { return 1 + f(x) }\nf(1)", limits.clone()),
+            Err(RuntimeError::LimitExceeded(_))
+        ));
+        assert_eq!(
+            check("fn f(x) { return x }\nlet y = f(f(f(1)))", limits),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test7() {
+        let limits = Limits {
+            time: Some(std::time::Duration::from_millis(20)),
+            ..Limits::default()
+        };
+
+        assert!(matches!(
+            check("let x = 0\nloop { x = x + 1 }", limits),
+            Err(RuntimeError::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test8() {
+        let limits = Limits {
+            memory: Some(4096),
+            ..Limits::default()
+        };
+
+        assert!(matches!(
+            check("let x = [1]\nloop { x = [x, x] }", limits.clone()),
+            Err(RuntimeError::LimitExceeded(_))
+        ));
+        assert_eq!(
+            check("let x = 0\nloop i : [1, 2, 3] { x = [i] }", limits),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test9() {
+        assert_eq!(
+            exits(
+                "fn double(x) { return x * 2 }
+            let f = double
+            let y = f(4)
+            if y == 8 { exit(0) }
+            exit(1)",
+            ),
+            Err(RuntimeError::Exit(0))
+        );
+    }
+
+    #[test]
+    fn test10() {
+        type Engine = fn(Vec<Statement>, Context) -> Result<(), RuntimeError>;
+
+        for (i, run) in [interpret as Engine, crate::vm::run]
+            .into_iter()
+            .enumerate()
+        {
+            let file = std::env::temp_dir().join(format!("plang-interpreter-test10-{}", i));
+            std::fs::remove_file(&file).ok();
+
+            let source = format!(
+                "fn hello(req) {{ return {{ status: 201, body: req.query.name }} }}
+                fn ready(port) {{ write_file({:?}, port) }}
+                serve(0, {{ \"/hello\": hello }}, {{ requests: 2, ready: ready }})",
+                file.to_str().unwrap()
+            );
+            let server = std::thread::spawn(move || {
+                let (_, input) = crate::parser::parse(&source).unwrap();
+                run(input, Context::default())
+            });
+
+            let get = |path: &str| {
+                for _ in 0..500 {
+                    if let Ok(port) = std::fs::read_to_string(&file) {
+                        let url = format!("http://127.0.0.1:{}{}", port, path);
+                        let out = Std::HttpGet.run(
+                            &mut crate::stdlib::tests::TestRuntime::default(),
+                            vec![Expression::String(url)],
+                        );
+                        if let Ok(out) = out {
+                            return out;
+                        }
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+                panic!("server never answered {}", path);
+            };
+
+            let hello = get("/hello?name=plang");
+            assert_eq!(hello.get("status"), Some(&Expression::Number(201.0)));
+            assert_eq!(
+                hello.get("body"),
+                Some(&Expression::String(String::from("plang")))
+            );
+
+            let missing = get("/missing");
+            assert_eq!(missing.get("status"), Some(&Expression::Number(404.0)));
+
+            assert_eq!(server.join().unwrap(), Ok(()));
+            std::fs::remove_file(&file).unwrap();
+        }
+    }
+
+    #[test]
+    fn test11() {
+        assert_eq!(
+            exits(
+                "let s = \"ab\" + \"cd\"
+            if s == \"abcd\" and \"apple\" < \"banana\" { exit(len(upper(s))) }",
+            ),
+            Err(RuntimeError::Exit(4))
+        );
+    }
+
+    #[test]
+    fn test12() {
+        assert_eq!(
+            exits(
+                "fn double(x) { return x * 2 }
+            fn big(x) { return x > 4 }
+            fn add(a, b) { return a + b }
+            fn desc(a, b) { return b - a }
+            let xs = [3, 1, 2]
+            push(xs, 4)
+            sort(xs, desc)
+            let last = pop(xs)
+            loop x : [5, 6] {
+                push(xs, x)
+            }
+            if xs == [4, 3, 2, 5, 6] and map(xs, double) == [8, 6, 4, 10, 12] {
+                exit(reduce(filter(xs + [last, 7], big), add, 0))
+            }",
+            ),
+            Err(RuntimeError::Exit(18))
+        );
+    }
+
+    #[test]
+    fn test13() {
+        assert_eq!(
+            exits(
+                "fn area(r) { return PI * pow(r, 2) }
+            exit(round(area(2)) + max([1, idiv(7, 2)]) + sum([abs(-1), floor(E)]))",
+            ),
+            Err(RuntimeError::Exit(19))
+        );
+    }
+
+    #[test]
+    fn test14() {
+        let limits = Limits {
+            depth: Some(50),
+            ..Limits::default()
+        };
+
+        assert_eq!(
+            check(
+                "fn count(n, total) {
+                    if n == 0 { return total }
+                    return count(n - 1, total + n)
+                }
+                fn even(n) { if n == 0 { return true }\nreturn odd(n - 1) }
+                fn odd(n) { if n == 0 { return false }\nreturn even(n - 1) }
+                fn run(f, n) { return f(n, 0) }
+                if run(count, 20000) == 200010000 and odd(10001) { exit(3) }",
+                limits
+            ),
+            Err(RuntimeError::Exit(3))
+        );
+    }
+
+    #[test]
+    fn test15() {
+        let (_, input) = crate::parser::parse("let a = 1\nif a == 1 {\n  throw a + 1\n}").unwrap();
+        let out = interpret(input, Context::default()).unwrap_err();
+
+        assert_eq!(out.cause(), &RuntimeError::Thrown(Expression::Number(2.0)));
+        let span = out.span().unwrap();
+        assert_eq!((span.line, span.column), (3, 3));
+    }
+
+    #[test]
+    fn test16() {
+        let source = "fn f(x) {\n  return x + \"a\"\n}\nfn g() { return [f(1)] }\n\nprint(g())";
+        let (_, input) = crate::parser::parse(source).unwrap();
+        let out = interpret(input, Context::default()).unwrap_err();
+
+        assert_eq!(
+            out.traceback(&[(String::from("main.plang"), source.to_string())]),
+            "traceback (innermost last):
+  line 6, column 1, in <main>
+    print(g())
+  line 4, column 10, in g
+    fn g() { return [f(1)] }
+  line 2, column 3, in f
+    return x + \"a\"
+"
+        );
+        assert_eq!(out.span().map(|t| t.line), Some(2));
+    }
+
+    #[test]
+    fn test17() {
+        assert_eq!(constant("PI"), Some(Expression::Number(consts::PI)));
+        assert_eq!(constant("TAU"), None);
+        assert_eq!(
+            exits("fn f() { let PI = 3\nreturn PI }\nexit(f() + floor(PI) + floor(E))"),
+            Err(RuntimeError::Exit(8))
+        );
+        assert_eq!(
+            exits("try { exit(floor(PI)) } catch e { exit(1) }"),
+            Err(RuntimeError::Exit(3))
+        );
     }
 }
@@ -0,0 +1,115 @@
+use crate::parser::ast::{Expression, Op};
+
+use super::RuntimeError;
+
+/// Applies a binary operator to two evaluated operands.
+pub fn infix(left: Expression, op: Op, right: Expression) -> Result<Expression, RuntimeError> {
+    Ok(match (left, op, right) {
+        (Expression::Number(l), Op::Add, Expression::Number(r)) => Expression::Number(l + r),
+        (Expression::Number(l), Op::Multiply, Expression::Number(r)) => Expression::Number(l * r),
+        (Expression::Number(l), Op::Divide, Expression::Number(r)) => Expression::Number(l / r),
+        (Expression::Number(l), Op::Subtract, Expression::Number(r)) => Expression::Number(l - r),
+        (Expression::Number(l), Op::Equals, Expression::Number(r)) => Expression::Boolean(l == r),
+        (Expression::Number(l), Op::NotEquals, Expression::Number(r)) => {
+            Expression::Boolean(l != r)
+        }
+        (Expression::Number(l), Op::GreaterThan, Expression::Number(r)) => {
+            Expression::Boolean(l > r)
+        }
+        (Expression::Number(l), Op::LessThan, Expression::Number(r)) => Expression::Boolean(l < r),
+        (Expression::Number(l), Op::GreaterThanOrEquals, Expression::Number(r)) => {
+            Expression::Boolean(l >= r)
+        }
+        (Expression::Number(l), Op::LessThanOrEquals, Expression::Number(r)) => {
+            Expression::Boolean(l <= r)
+        }
+        (Expression::Boolean(l), Op::And, Expression::Boolean(r)) => Expression::Boolean(l && r),
+        (Expression::Boolean(l), Op::Or, Expression::Boolean(r)) => Expression::Boolean(l || r),
+        (Expression::String(l), Op::Add, Expression::String(r)) => Expression::String(l + &r),
+        (Expression::String(l), Op::Equals, Expression::String(r)) => Expression::Boolean(l.eq(&r)),
+        (Expression::String(l), Op::NotEquals, Expression::String(r)) => {
+            Expression::Boolean(l.ne(&r))
+        }
+        (Expression::String(l), Op::GreaterThan, Expression::String(r)) => {
+            Expression::Boolean(l > r)
+        }
+        (Expression::String(l), Op::LessThan, Expression::String(r)) => Expression::Boolean(l < r),
+        (Expression::String(l), Op::GreaterThanOrEquals, Expression::String(r)) => {
+            Expression::Boolean(l >= r)
+        }
+        (Expression::String(l), Op::LessThanOrEquals, Expression::String(r)) => {
+            Expression::Boolean(l <= r)
+        }
+        (Expression::List(mut l), Op::Add, Expression::List(r)) => {
+            l.extend(r);
+            Expression::List(l)
+        }
+        (Expression::List(l), Op::Equals, Expression::List(r)) => Expression::Boolean(l == r),
+        (Expression::List(l), Op::NotEquals, Expression::List(r)) => Expression::Boolean(l != r),
+        (Expression::Nil, Op::Equals, r) => Expression::Boolean(r == Expression::Nil),
+        (l, Op::Equals, Expression::Nil) => Expression::Boolean(l == Expression::Nil),
+        (Expression::Nil, Op::NotEquals, r) => Expression::Boolean(r != Expression::Nil),
+        (l, Op::NotEquals, Expression::Nil) => Expression::Boolean(l != Expression::Nil),
+        (l, op, r) => {
+            return Err(RuntimeError::Type(format!(
+                "unsupported operation {:?} {:?} {:?}",
+                l, op, r
+            )))
+        }
+    })
+}
+
+/// Looks up `index` in a list, string or map value.
+pub fn index_value(value: Expression, index: Expression) -> Result<Expression, RuntimeError> {
+    match (value, index) {
+        (Expression::List(l), Expression::Number(n)) => {
+            match position(n, l.len()).and_then(|n| l.into_iter().nth(n)) {
+                Some(t) => Ok(t),
+                None => Err(RuntimeError::Type(format!("index {} out of range", n))),
+            }
+        }
+        (Expression::String(s), Expression::Number(n)) => {
+            let len = s.chars().count();
+            match position(n, len).and_then(|n| s.chars().nth(n)) {
+                Some(t) => Ok(Expression::String(t.to_string())),
+                None => Err(RuntimeError::Type(format!("index {} out of range", n))),
+            }
+        }
+        (Expression::Map(m), key) => Ok(m
+            .into_iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+            .unwrap_or(Expression::Nil)),
+        (t, i) => Err(RuntimeError::Type(format!(
+            "cannot index {:?} with {:?}",
+            t, i
+        ))),
+    }
+}
+
+/// Adds a map entry, replacing the value of an equal key.
+pub fn insert(entries: &mut Vec<(Expression, Expression)>, key: Expression, value: Expression) {
+    match entries.iter_mut().find(|(k, _)| *k == key) {
+        Some(entry) => entry.1 = value,
+        None => entries.push((key, value)),
+    }
+}
+
+/// Converts a plang index into a position, counting negative indexes from the end.
+fn position(index: f64, len: usize) -> Option<usize> {
+    if index.fract() != 0.0 {
+        return None;
+    }
+
+    let index = if index < 0.0 {
+        len as f64 + index
+    } else {
+        index
+    };
+
+    if index >= 0.0 && index < len as f64 {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,326 @@
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use super::RuntimeError;
+
+/// Allow and deny lists for a single capability.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Rule {
+    /// `None` permits everything that is not denied.
+    pub allow: Option<Vec<String>>,
+    pub deny: Vec<String>,
+}
+
+impl Rule {
+    pub fn allow(&mut self, item: &str) {
+        self.allow
+            .get_or_insert_with(Vec::new)
+            .push(item.to_string());
+    }
+
+    pub fn deny(&mut self, item: &str) {
+        self.deny.push(item.to_string());
+    }
+
+    /// Denies everything that has not been explicitly allowed.
+    pub fn restrict(&mut self) {
+        self.allow.get_or_insert_with(Vec::new);
+    }
+
+    fn permits(&self, matches: impl Fn(&str) -> bool) -> bool {
+        let allowed = match &self.allow {
+            Some(allow) => allow.iter().any(|a| matches(a)),
+            None => true,
+        };
+        allowed && !self.deny.iter().any(|d| matches(d))
+    }
+}
+
+/// What the process, file system and network built-ins may touch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Permissions {
+    pub cmd: Rule,
+    pub read: Rule,
+    pub write: Rule,
+    pub net: Rule,
+    /// The `PATH` at startup, which allowed command names are resolved
+    /// against so that a script cannot substitute its own programs.
+    pub search: Vec<PathBuf>,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            cmd: Rule::default(),
+            read: Rule::default(),
+            write: Rule::default(),
+            net: Rule::default(),
+            search: env::var_os("PATH")
+                .map(|p| env::split_paths(&p).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Permissions {
+    /// Denies every capability that has no allow list of its own.
+    pub fn sandbox(&mut self) {
+        self.cmd.restrict();
+        self.read.restrict();
+        self.write.restrict();
+        self.net.restrict();
+    }
+
+    /// Returns the program to spawn. When commands are restricted, a bare
+    /// name must be allowed exactly and is resolved against the startup
+    /// `PATH`, and a path must itself be on the allow list.
+    pub fn check_cmd(&self, program: &str) -> Result<PathBuf, RuntimeError> {
+        let denied = || RuntimeError::Permission(format!("run `{}`", program));
+        let name = Path::new(program)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(program);
+
+        if self.cmd.deny.iter().any(|d| d == program || d == name) {
+            return Err(denied());
+        }
+
+        let allow = match &self.cmd.allow {
+            Some(allow) => allow,
+            None => return Ok(PathBuf::from(program)),
+        };
+
+        if !allow.iter().any(|c| c == program) {
+            Err(denied())
+        } else if program.contains(std::path::MAIN_SEPARATOR) {
+            Ok(PathBuf::from(program))
+        } else {
+            self.search
+                .iter()
+                .map(|dir| dir.join(program))
+                .find(|p| executable(p))
+                .ok_or_else(|| RuntimeError::Io(format!("cannot find `{}` on PATH", program)))
+        }
+    }
+
+    /// A restricted script may not change `PATH`, which its commands and
+    /// their children would otherwise search, nor the variables that make
+    /// loaders and interpreters run extra code in an allowed program.
+    pub fn check_env(&self, name: &str) -> Result<(), RuntimeError> {
+        let loads = name == "PATH"
+            || ["LD_", "DYLD_"].iter().any(|p| name.starts_with(p))
+            || [
+                "BASH_ENV",
+                "ENV",
+                "SHELLOPTS",
+                "PERL5OPT",
+                "PERL5LIB",
+                "PYTHONPATH",
+                "PYTHONSTARTUP",
+                "RUBYOPT",
+                "NODE_OPTIONS",
+                "GIT_CONFIG_GLOBAL",
+                "GIT_EXEC_PATH",
+            ]
+            .contains(&name);
+
+        if self.cmd.allow.is_some() && loads {
+            Err(RuntimeError::Permission(format!("change `{}`", name)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// A directory to run commands in must be both readable and writable.
+    pub fn check_cwd(&self, path: &str) -> Result<(), RuntimeError> {
+        self.check_read(path)?;
+        self.check_write(path)
+    }
+
+    pub fn check_read(&self, path: &str) -> Result<(), RuntimeError> {
+        check_path(&self.read, "read", path)
+    }
+
+    pub fn check_write(&self, path: &str) -> Result<(), RuntimeError> {
+        check_path(&self.write, "write", path)
+    }
+
+    pub fn check_net(&self, host: &str, port: u16) -> Result<(), RuntimeError> {
+        let address = format!("{}:{}", host, port);
+
+        if self.net.permits(|h| h == host || h == address) {
+            Ok(())
+        } else {
+            Err(RuntimeError::Permission(format!(
+                "connect to `{}`",
+                address
+            )))
+        }
+    }
+}
+
+fn check_path(rule: &Rule, action: &str, path: &str) -> Result<(), RuntimeError> {
+    let target = resolve(Path::new(path));
+
+    if rule.permits(|root| target.starts_with(resolve(Path::new(root)))) {
+        Ok(())
+    } else {
+        Err(RuntimeError::Permission(format!("{} `{}`", action, path)))
+    }
+}
+
+#[cfg(unix)]
+fn executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Makes `path` absolute, following symlinks for the part of it that exists.
+fn resolve(path: &Path) -> PathBuf {
+    let absolute = match env::current_dir() {
+        Ok(cwd) => cwd.join(path),
+        Err(_) => path.to_path_buf(),
+    };
+
+    let mut normal = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::ParentDir => {
+                normal.pop();
+            }
+            Component::CurDir => {}
+            c => normal.push(c),
+        }
+    }
+
+    let mut existing = normal.as_path();
+    let mut rest = Vec::new();
+    while !existing.exists() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return normal,
+        }
+    }
+
+    match fs::canonicalize(existing) {
+        Ok(mut real) => {
+            real.extend(rest.into_iter().rev());
+            real
+        }
+        Err(_) => normal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test1() {
+        let permissions = Permissions::default();
+
+        assert_eq!(permissions.check_cmd("rm"), Ok(PathBuf::from("rm")));
+        assert_eq!(permissions.check_read("/etc/passwd"), Ok(()));
+        assert_eq!(permissions.check_net("example.com", 80), Ok(()));
+    }
+
+    #[test]
+    fn test2() {
+        let mut permissions = Permissions::default();
+        permissions.cmd.allow("git");
+        permissions.cmd.deny("rm");
+
+        assert!(permissions.check_cmd("git").is_ok());
+        assert!(permissions.check_cmd("/usr/bin/git").is_err());
+        assert!(permissions.check_cmd("ls").is_err());
+        assert!(permissions.check_cmd("rm").is_err());
+    }
+
+    #[test]
+    fn test3() {
+        let mut permissions = Permissions::default();
+        permissions.read.allow("/tmp/plang-data");
+        permissions.write.deny("/tmp/plang-data/secret");
+
+        assert_eq!(permissions.check_read("/tmp/plang-data/a/b.txt"), Ok(()));
+        assert!(permissions.check_read("/tmp/plang-data/../x").is_err());
+        assert!(permissions.check_read("/tmp/plang-database").is_err());
+        assert_eq!(permissions.check_write("/tmp/x"), Ok(()));
+        assert!(permissions.check_write("/tmp/plang-data/secret/x").is_err());
+    }
+
+    #[test]
+    fn test4() {
+        let mut permissions = Permissions::default();
+        permissions.net.allow("localhost:8080");
+        permissions.net.allow("example.com");
+        permissions.sandbox();
+
+        assert_eq!(permissions.check_net("localhost", 8080), Ok(()));
+        assert_eq!(permissions.check_net("example.com", 443), Ok(()));
+        assert!(permissions.check_net("localhost", 80).is_err());
+        assert!(permissions.check_cmd("ls").is_err());
+        assert!(permissions.check_read("/").is_err());
+    }
+
+    #[test]
+    fn test5() {
+        let dir = env::temp_dir().join("plang-permissions-test5");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("sh"), "").unwrap();
+
+        let mut permissions = Permissions::default();
+        permissions.cmd.allow("sh");
+        permissions.cmd.allow("/usr/local/bin/tool");
+        permissions.sandbox();
+        permissions.search.insert(0, dir.clone());
+
+        let sh = permissions.check_cmd("sh").unwrap();
+        assert!(sh.is_absolute());
+        assert_ne!(sh, dir.join("sh"));
+        assert!(permissions
+            .check_cmd(dir.join("sh").to_str().unwrap())
+            .is_err());
+        assert!(permissions.check_cmd("/usr/local/bin/tool").is_ok());
+        assert!(permissions.check_env("PATH").is_err());
+        assert_eq!(permissions.check_env("HOME"), Ok(()));
+        assert_eq!(Permissions::default().check_env("PATH"), Ok(()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test6() {
+        let mut permissions = Permissions::default();
+        permissions.cmd.allow("sh");
+        permissions.read.allow("/tmp/plang-work");
+        permissions.write.allow("/tmp/plang-work");
+        permissions.sandbox();
+
+        for name in [
+            "LD_PRELOAD",
+            "LD_LIBRARY_PATH",
+            "DYLD_INSERT_LIBRARIES",
+            "BASH_ENV",
+        ] {
+            assert!(permissions.check_env(name).is_err(), "{}", name);
+        }
+        assert_eq!(permissions.check_env("LANG"), Ok(()));
+        assert_eq!(Permissions::default().check_env("LD_PRELOAD"), Ok(()));
+
+        assert_eq!(permissions.check_cwd("/tmp/plang-work/a"), Ok(()));
+        assert!(permissions.check_cwd("/").is_err());
+        permissions.write.deny("/tmp/plang-work/ro");
+        assert!(permissions.check_cwd("/tmp/plang-work/ro").is_err());
+    }
+}
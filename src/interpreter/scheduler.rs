@@ -0,0 +1,180 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+use super::{Deadline, RuntimeError};
+
+#[derive(Debug)]
+struct State {
+    /// Tasks started and not yet finished, the main program included.
+    running: usize,
+    /// Waiting tasks that found nothing to do since the last progress.
+    blocked: usize,
+    /// Tasks blocked through `block`, which progress does not wake.
+    parked: usize,
+    /// Bumped whenever a task makes progress that waiters may act on.
+    progress: u64,
+    /// Deadlocks found so far; a waiter gives up once this moves.
+    deadlocks: u64,
+}
+
+/// Tracks the tasks of one program so blocking operations can wait for each
+/// other and notice when none of them can make progress. Channels change
+/// their contents only while holding its lock, so no wake-up is lost.
+/// Waits give up at the program's deadline, if the copy waiting has one.
+#[derive(Debug, Clone)]
+pub struct Scheduler(Arc<(Mutex<State>, Condvar)>, Option<Deadline>);
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        let state = State {
+            running: 1,
+            blocked: 0,
+            parked: 0,
+            progress: 0,
+            deadlocks: 0,
+        };
+        Scheduler(Arc::new((Mutex::new(state), Condvar::new())), None)
+    }
+}
+
+impl Scheduler {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.0 .0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wake(&self) {
+        self.0 .1.notify_all();
+    }
+
+    /// Wakes every waiter to look again. Until each has, it no longer counts
+    /// as blocked, so a wake-up in flight is never taken for a deadlock.
+    fn advance(&self, state: &mut State) {
+        state.progress += 1;
+        state.blocked = 0;
+        self.wake();
+    }
+
+    /// Declares a deadlock when every running task is blocked.
+    fn check(&self, state: &mut State) {
+        let blocked = state.blocked + state.parked;
+        if blocked > 0 && blocked >= state.running {
+            state.deadlocks += 1;
+            self.wake();
+        }
+    }
+
+    /// This scheduler, with waits ending at `deadline`.
+    pub fn until(mut self, deadline: Option<Deadline>) -> Self {
+        self.1 = deadline;
+        self
+    }
+
+    pub fn start(&self, tasks: usize) {
+        self.lock().running += tasks;
+    }
+
+    pub fn finish(&self, tasks: usize) {
+        let mut state = self.lock();
+        state.running -= tasks;
+        self.advance(&mut state);
+        self.check(&mut state);
+    }
+
+    /// Counts the current task as blocked while it waits on work it cannot
+    /// observe through `wait`.
+    pub fn block(&self) {
+        let mut state = self.lock();
+        state.parked += 1;
+        self.check(&mut state);
+    }
+
+    pub fn unblock(&self) {
+        self.lock().parked -= 1;
+    }
+
+    /// Runs `f` under the lock, waking waiters if it made progress.
+    pub fn poll<T>(&self, f: impl FnOnce() -> Option<T>) -> Option<T> {
+        let mut state = self.lock();
+        let out = f();
+        if out.is_some() {
+            self.advance(&mut state);
+        }
+        out
+    }
+
+    /// Blocks until `ready` gives a value, trying again whenever another
+    /// task makes progress, or fails once every task is blocked or the
+    /// deadline has passed.
+    pub fn wait<T>(&self, mut ready: impl FnMut() -> Option<T>) -> Result<T, RuntimeError> {
+        let mut state = self.lock();
+        let seen = state.deadlocks;
+        // The progress count at which this task last counted itself blocked.
+        let mut counted = None;
+
+        let out = loop {
+            if let Some(t) = ready() {
+                self.advance(&mut state);
+                break Ok(t);
+            }
+            if state.deadlocks != seen {
+                break Err(RuntimeError::Deadlock);
+            }
+            if let Some(Err(e)) = self.1.map(|d| d.check()) {
+                break Err(e);
+            }
+            if counted != Some(state.progress) {
+                counted = Some(state.progress);
+                state.blocked += 1;
+                self.check(&mut state);
+                if state.deadlocks != seen {
+                    break Err(RuntimeError::Deadlock);
+                }
+            }
+            let condvar = &self.0 .1;
+            state = match self.1 {
+                Some(deadline) => {
+                    let timeout = deadline.at.saturating_duration_since(Instant::now());
+                    condvar
+                        .wait_timeout(state, timeout)
+                        .map_or_else(|e| e.into_inner().0, |(t, _)| t)
+                }
+                None => condvar.wait(state).unwrap_or_else(PoisonError::into_inner),
+            };
+        };
+
+        if out.is_err() && counted == Some(state.progress) {
+            state.blocked -= 1;
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test1() {
+        let scheduler = Scheduler::default();
+        let flag = Arc::new(Mutex::new(false));
+
+        scheduler.start(1);
+        let (s, f) = (scheduler.clone(), flag.clone());
+        let worker = thread::spawn(move || {
+            s.poll(|| {
+                *f.lock().unwrap() = true;
+                Some(())
+            });
+            s.finish(1);
+        });
+
+        assert_eq!(
+            scheduler.wait(|| flag.lock().unwrap().then_some(())),
+            Ok(())
+        );
+        worker.join().unwrap();
+
+        assert_eq!(scheduler.wait(|| None::<()>), Err(RuntimeError::Deadlock));
+    }
+}
@@ -0,0 +1,840 @@
+//! Links the modules a program imports into it before it runs.
+//!
+//! Each module file is loaded once. Its top-level functions move to the front
+//! of the program under names qualified with the module, like `utils.sum`,
+//! and the rest of its top level becomes the function `<utils>`, which
+//! returns a map of what the module exports. The program runs those in the
+//! order the modules load and keeps each map in a variable of the same name:
+//! `import "utils" as u` binds `u` to it, while `from "utils" import sum`
+//! makes `sum` refer to `utils.sum`.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use crate::interpreter::{Permissions, RuntimeError};
+use crate::parser::ast::{Block, Expression, Span, Statement};
+use crate::parser::parse_file;
+use crate::resolver::declared_names;
+use crate::token::Token;
+
+/// The extension of module files, which imports may leave out.
+const EXTENSION: &str = "plang";
+
+/// What a module exports: the qualified name of each function, or `None`
+/// for a variable.
+type Exports = HashMap<String, Option<String>>;
+
+struct Module {
+    /// The name its functions are qualified with.
+    name: String,
+    exports: Exports,
+}
+
+/// A file with its imports resolved.
+struct Linked {
+    body: Block,
+    /// The variables holding the modules it imports.
+    modules: Vec<String>,
+    exports: Exports,
+}
+
+#[derive(Default)]
+pub struct Loader {
+    /// Directories searched after the importing file's own.
+    search: Vec<PathBuf>,
+    /// What the program may read, which covers the modules it imports.
+    permissions: Permissions,
+    /// The name and source of every file loaded, the program first; spans
+    /// index into it.
+    pub files: Vec<(String, String)>,
+    modules: HashMap<PathBuf, Module>,
+    names: HashSet<String>,
+    /// The files being loaded, outermost first.
+    loading: Vec<PathBuf>,
+    /// The functions of the modules loaded so far and the calls that run them.
+    prelude: Block,
+}
+
+fn canonical(path: &Path) -> Result<PathBuf, RuntimeError> {
+    fs::canonicalize(path).map_err(|e| RuntimeError::Import(format!("{}: {}", path.display(), e)))
+}
+
+fn identifier(input: Expression) -> Result<String, RuntimeError> {
+    match input {
+        Expression::Identifier(t) => Ok(t),
+        t => Err(RuntimeError::Type(format!(
+            "expected identifier, found {:?}",
+            t
+        ))),
+    }
+}
+
+/// The variable and function that hold a module's exports.
+fn value_of(module: &str) -> String {
+    format!("<{}>", module)
+}
+
+/// Collects the names of the functions a body defines, outside nested functions.
+fn defined_functions(input: &Block, out: &mut HashSet<String>) {
+    for statement in input {
+        match statement.inner() {
+            Statement::Fn {
+                name: Expression::Identifier(t),
+                ..
+            } => {
+                out.insert(t.to_string());
+            }
+            Statement::If {
+                then, otherwise, ..
+            } => {
+                defined_functions(then, out);
+                if let Some(o) = otherwise {
+                    defined_functions(o, out);
+                }
+            }
+            Statement::Loop { then, .. } => defined_functions(then, out),
+            Statement::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                defined_functions(body, out);
+                if let Some((_, c)) = catch {
+                    defined_functions(c, out);
+                }
+                if let Some(f) = finally {
+                    defined_functions(f, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Loader {
+    pub fn new(search: Vec<PathBuf>, permissions: Permissions) -> Self {
+        Self {
+            search,
+            permissions,
+            ..Self::default()
+        }
+    }
+
+    /// Links the program in `path`, parsed from `source`, with every module
+    /// it imports.
+    pub fn link(&mut self, path: &Path, source: &str, input: Block) -> Result<Block, RuntimeError> {
+        self.files
+            .push((path.display().to_string(), source.to_string()));
+        self.loading.push(canonical(path)?);
+
+        let linked = self.file(path, None, input)?;
+        self.loading.pop();
+
+        let mut out = mem::take(&mut self.prelude);
+        out.extend(linked.body);
+        Ok(out)
+    }
+
+    /// Finds an import next to the file importing it, then on the search path.
+    fn find(&self, importer: &Path, import: &str) -> Result<PathBuf, RuntimeError> {
+        let mut name = PathBuf::from(import);
+        if name.extension().is_none() {
+            name.set_extension(EXTENSION);
+        }
+
+        let here = importer.parent().unwrap_or(Path::new(""));
+        std::iter::once(here)
+            .chain(self.search.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(&name))
+            .find(|path| path.is_file())
+            .ok_or_else(|| RuntimeError::Import(format!("cannot find module `{}`", import)))
+    }
+
+    /// Loads a module unless it already is, returning its key in `modules`.
+    /// `span` is where it is first imported, which its top level runs at.
+    fn load(&mut self, importer: &Path, import: &str, span: Span) -> Result<PathBuf, RuntimeError> {
+        let path = self.find(importer, import)?;
+        let key = canonical(&path)?;
+
+        if self.modules.contains_key(&key) {
+            return Ok(key);
+        }
+        if let Some(at) = self.loading.iter().position(|t| *t == key) {
+            let cycle: Vec<_> = self.loading[at..]
+                .iter()
+                .chain([&key])
+                .map(|t| t.file_name().unwrap_or_default().to_string_lossy())
+                .collect();
+            return Err(RuntimeError::Import(format!(
+                "import cycle: {}",
+                cycle.join(" -> ")
+            )));
+        }
+
+        self.permissions.check_read(&path.to_string_lossy())?;
+        let source = fs::read_to_string(&path)
+            .map_err(|e| RuntimeError::Import(format!("{}: {}", path.display(), e)))?;
+        // Errors name the line rather than quote it, so that an import cannot
+        // be used to print a file the program may not otherwise show.
+        let input = match parse_file(&source, self.files.len()) {
+            Ok((rest, input)) if rest.trim().is_empty() => input,
+            Ok((rest, _)) => {
+                let at = source.len() - rest.trim_start().len();
+                return Err(RuntimeError::Import(format!(
+                    "{}: cannot parse line {}",
+                    path.display(),
+                    source[..at].matches('\n').count() + 1
+                )));
+            }
+            Err(_) => {
+                return Err(RuntimeError::Import(format!(
+                    "{}: cannot parse",
+                    path.display()
+                )))
+            }
+        };
+        self.files.push((path.display().to_string(), source));
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let mut name = stem.to_string();
+        for n in 2.. {
+            if !self.names.contains(&name) {
+                break;
+            }
+            name = format!("{}_{}", stem, n);
+        }
+        self.names.insert(name.clone());
+
+        self.loading.push(key.clone());
+        let linked = self.file(&path, Some(&name), input)?;
+        self.loading.pop();
+
+        let (functions, mut body): (Block, Block) = linked
+            .body
+            .into_iter()
+            .partition(|t| matches!(t.inner(), Statement::Fn { .. }));
+        self.prelude.extend(functions);
+
+        let mut exports: Vec<_> = linked.exports.iter().collect();
+        exports.sort();
+        body.push(Statement::Return {
+            value: Some(Expression::Map(
+                exports
+                    .into_iter()
+                    .map(|(t, function)| {
+                        let value = function.as_ref().unwrap_or(t);
+                        (
+                            Expression::String(t.to_string()),
+                            Expression::Identifier(value.to_string()),
+                        )
+                    })
+                    .collect(),
+            )),
+        });
+
+        let value = value_of(&name);
+        let params: Vec<_> = linked
+            .modules
+            .into_iter()
+            .map(Expression::Identifier)
+            .collect();
+        self.prelude.push(Statement::At(
+            span,
+            Statement::Fn {
+                name: Expression::Identifier(value.clone()),
+                params: params.clone(),
+                body,
+            }
+            .boxed(),
+        ));
+        self.prelude.push(Statement::At(
+            span,
+            Statement::Let {
+                name: Expression::Identifier(value.clone()),
+                initial: Expression::Call(Expression::Identifier(value).boxed(), params),
+            }
+            .boxed(),
+        ));
+
+        self.modules.insert(
+            key.clone(),
+            Module {
+                name,
+                exports: linked.exports,
+            },
+        );
+        Ok(key)
+    }
+
+    /// Loads what one file imports and renames what its code refers to.
+    /// `module` is the name of the module the file is, unless it is the
+    /// program.
+    fn file(
+        &mut self,
+        path: &Path,
+        module: Option<&str>,
+        input: Block,
+    ) -> Result<Linked, RuntimeError> {
+        let mut functions = HashMap::new();
+        let mut aliases = HashMap::new();
+        let mut modules = Vec::new();
+        let mut exports = Exports::new();
+        let mut body = Vec::new();
+
+        for statement in input {
+            let (span, statement) = match statement {
+                Statement::At(span, t) => (span, *t),
+                t => (Span::default(), t),
+            };
+
+            match statement {
+                Statement::Import {
+                    path: import,
+                    name,
+                    names,
+                } => {
+                    let key = self.load(path, &import, span)?;
+                    let imported = &self.modules[&key];
+                    let value = value_of(&imported.name);
+                    if !modules.contains(&value) {
+                        modules.push(value.clone());
+                    }
+
+                    if let Some(name) = name {
+                        body.push(Statement::At(
+                            span,
+                            Statement::Let {
+                                name: name.clone(),
+                                initial: Expression::Identifier(value.clone()),
+                            }
+                            .boxed(),
+                        ));
+                        aliases.insert(
+                            identifier(name)?,
+                            (imported.name.clone(), imported.exports.clone()),
+                        );
+                    }
+
+                    for name in names {
+                        let t = identifier(name)?;
+                        match imported.exports.get(&t) {
+                            Some(Some(function)) => {
+                                functions.insert(t, function.clone());
+                            }
+                            Some(None) => body.push(Statement::At(
+                                span,
+                                Statement::Let {
+                                    name: Expression::Identifier(t.clone()),
+                                    initial: Expression::Index(
+                                        Expression::Identifier(value.clone()).boxed(),
+                                        Expression::String(t).boxed(),
+                                    ),
+                                }
+                                .boxed(),
+                            )),
+                            None => {
+                                return Err(RuntimeError::Import(format!(
+                                    "`{}` does not export `{}`",
+                                    imported.name, t
+                                )))
+                            }
+                        }
+                    }
+                }
+
+                Statement::Export(t) => {
+                    match (&*t, module) {
+                        (
+                            Statement::Fn {
+                                name: Expression::Identifier(name),
+                                ..
+                            },
+                            Some(module),
+                        ) => {
+                            let function = format!("{}{}{}", module, Token::DOT, name);
+                            exports.insert(name.clone(), Some(function));
+                        }
+                        (
+                            Statement::Let {
+                                name: Expression::Identifier(name),
+                                ..
+                            },
+                            Some(_),
+                        ) => {
+                            exports.insert(name.clone(), None);
+                        }
+                        _ => {}
+                    }
+                    body.push(Statement::At(span, t));
+                }
+
+                t => body.push(Statement::At(span, t.boxed())),
+            }
+        }
+
+        for statement in &body {
+            if let Statement::Fn {
+                name: Expression::Identifier(name),
+                ..
+            } = statement.inner()
+            {
+                if functions.contains_key(name) {
+                    return Err(RuntimeError::Import(format!(
+                        "`{}` is both imported and defined",
+                        name
+                    )));
+                }
+                if let Some(module) = module {
+                    let function = format!("{}{}{}", module, Token::DOT, name);
+                    functions.insert(name.clone(), function);
+                }
+            }
+        }
+
+        let mut variables = HashSet::new();
+        declared_names(&body, &mut variables);
+        for alias in aliases.keys() {
+            variables.remove(alias);
+        }
+
+        let mut rename = Rename {
+            functions: &functions,
+            aliases: &aliases,
+            variables,
+            nested: HashSet::new(),
+            depth: 0,
+        };
+        Ok(Linked {
+            body: rename.block(body)?,
+            modules,
+            exports,
+        })
+    }
+}
+
+/// Renames the functions one file's code refers to after linking.
+struct Rename<'a> {
+    /// Function names to the names they are linked under.
+    functions: &'a HashMap<String, String>,
+    /// Import aliases to the name and exports of their module.
+    aliases: &'a HashMap<String, (String, Exports)>,
+    /// Variables of the code being renamed, which hide functions from reads.
+    variables: HashSet<String>,
+    /// Functions defined inside the enclosing functions, which hide linked ones.
+    nested: HashSet<String>,
+    /// How many functions enclose the code; 0 is the top level.
+    depth: usize,
+}
+
+impl Rename<'_> {
+    fn block(&mut self, input: Block) -> Result<Block, RuntimeError> {
+        input.into_iter().map(|s| self.statement(s)).collect()
+    }
+
+    /// The function a call to `t` runs.
+    fn call(&self, t: String) -> Result<String, RuntimeError> {
+        if let Some((alias, name)) = t.split_once(Token::DOT) {
+            return match self.aliases.get(alias) {
+                Some((module, exports)) => match exports.get(name) {
+                    Some(Some(function)) => Ok(function.clone()),
+                    _ => Err(RuntimeError::Import(format!(
+                        "`{}` does not export a function `{}`",
+                        module, name
+                    ))),
+                },
+                // Module functions are linked under dotted names, which only
+                // an alias may reach.
+                None => Err(RuntimeError::Import(format!(
+                    "`{}` is not an imported module",
+                    alias
+                ))),
+            };
+        }
+
+        match self.functions.get(&t) {
+            Some(function) if !self.nested.contains(&t) => Ok(function.clone()),
+            _ => Ok(t),
+        }
+    }
+
+    /// What a read of `t` refers to; variables come before functions.
+    fn read(&self, t: String) -> String {
+        match self.functions.get(&t) {
+            Some(function) if !self.nested.contains(&t) && !self.variables.contains(&t) => {
+                function.clone()
+            }
+            _ => t,
+        }
+    }
+
+    fn statement(&mut self, input: Statement) -> Result<Statement, RuntimeError> {
+        Ok(match input {
+            Statement::At(span, t) => Statement::At(span, self.statement(*t)?.boxed()),
+
+            Statement::Let { name, initial } => Statement::Let {
+                name,
+                initial: self.expression(initial)?,
+            },
+
+            Statement::If {
+                condition,
+                then,
+                otherwise,
+            } => Statement::If {
+                condition: self.expression(condition)?,
+                then: self.block(then)?,
+                otherwise: otherwise.map(|o| self.block(o)).transpose()?,
+            },
+
+            Statement::Fn { name, params, body } => {
+                let name = match name {
+                    Expression::Identifier(t) if self.depth == 0 => {
+                        Expression::Identifier(self.functions.get(&t).cloned().unwrap_or(t))
+                    }
+                    t => t,
+                };
+
+                let mut variables = HashSet::new();
+                for param in &params {
+                    if let Expression::Identifier(t) = param {
+                        variables.insert(t.to_string());
+                    }
+                }
+                declared_names(&body, &mut variables);
+                let mut nested = self.nested.clone();
+                defined_functions(&body, &mut nested);
+
+                let variables = mem::replace(&mut self.variables, variables);
+                let nested = mem::replace(&mut self.nested, nested);
+                self.depth += 1;
+                let body = self.block(body);
+                self.depth -= 1;
+                self.variables = variables;
+                self.nested = nested;
+
+                Statement::Fn {
+                    name,
+                    params,
+                    body: body?,
+                }
+            }
+
+            Statement::Loop {
+                iterable,
+                value,
+                then,
+            } => Statement::Loop {
+                iterable,
+                value: value.map(|v| self.expression(v)).transpose()?,
+                then: self.block(then)?,
+            },
+
+            Statement::Expr { expression } => Statement::Expr {
+                expression: self.expression(expression)?,
+            },
+
+            Statement::Return { value } => Statement::Return {
+                value: value.map(|v| self.expression(v)).transpose()?,
+            },
+
+            Statement::Break => Statement::Break,
+
+            Statement::Try {
+                body,
+                catch,
+                finally,
+            } => Statement::Try {
+                body: self.block(body)?,
+                catch: catch
+                    .map(|(name, c)| Ok::<_, RuntimeError>((name, self.block(c)?)))
+                    .transpose()?,
+                finally: finally.map(|f| self.block(f)).transpose()?,
+            },
+
+            Statement::Throw { value } => Statement::Throw {
+                value: self.expression(value)?,
+            },
+
+            Statement::Import { .. } => {
+                return Err(RuntimeError::Import(String::from(
+                    "imports must be at the top level of a file",
+                )))
+            }
+
+            Statement::Export(_) => {
+                return Err(RuntimeError::Import(String::from(
+                    "exports must be at the top level of a file",
+                )))
+            }
+        })
+    }
+
+    fn expression(&mut self, input: Expression) -> Result<Expression, RuntimeError> {
+        Ok(match input {
+            Expression::Identifier(t) => Expression::Identifier(self.read(t)),
+
+            Expression::Call(name, params) => {
+                let name = match *name {
+                    Expression::Identifier(t) => Expression::Identifier(self.call(t)?),
+                    t => self.expression(t)?,
+                };
+                let params = params
+                    .into_iter()
+                    .map(|p| self.expression(p))
+                    .collect::<Result<_, _>>()?;
+                Expression::Call(name.boxed(), params)
+            }
+
+            // `u.f` is the function `f` of the module imported as `u`.
+            Expression::Index(value, index) => match (*value, *index) {
+                (Expression::Identifier(alias), Expression::String(key))
+                    if self.aliases.contains_key(&alias) && !self.variables.contains(&alias) =>
+                {
+                    let (module, exports) = &self.aliases[&alias];
+                    match exports.get(&key) {
+                        Some(Some(function)) => Expression::Identifier(function.clone()),
+                        Some(None) => Expression::Index(
+                            Expression::Identifier(alias).boxed(),
+                            Expression::String(key).boxed(),
+                        ),
+                        None => {
+                            return Err(RuntimeError::Import(format!(
+                                "`{}` does not export `{}`",
+                                module, key
+                            )))
+                        }
+                    }
+                }
+                (value, index) => Expression::Index(
+                    self.expression(value)?.boxed(),
+                    self.expression(index)?.boxed(),
+                ),
+            },
+
+            Expression::Infix(left, op, right) => Expression::Infix(
+                self.expression(*left)?.boxed(),
+                op,
+                self.expression(*right)?.boxed(),
+            ),
+
+            Expression::List(items) => Expression::List(
+                items
+                    .into_iter()
+                    .map(|t| self.expression(t))
+                    .collect::<Result<_, _>>()?,
+            ),
+
+            Expression::Map(entries) => Expression::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| Ok((self.expression(k)?, self.expression(v)?)))
+                    .collect::<Result<_, RuntimeError>>()?,
+            ),
+
+            Expression::Assign(t, value) => Expression::Assign(t, self.expression(*value)?.boxed()),
+
+            Expression::Async(function, args) => Expression::Async(
+                self.statement(*function)?.boxed(),
+                args.into_iter()
+                    .map(|t| self.expression(t))
+                    .collect::<Result<_, _>>()?,
+            ),
+
+            Expression::Await(value) => Expression::Await(self.expression(*value)?.boxed()),
+
+            t => t,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{interpret, Context};
+    use crate::parser::parse;
+
+    /// Writes `files` to a fresh directory named after the test.
+    fn write(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("plang-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        for (name, source) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    fn link(loader: &mut Loader, path: &Path) -> Result<Block, RuntimeError> {
+        let source = fs::read_to_string(path).unwrap();
+        let (_, input) = parse(&source).unwrap();
+        loader.link(path, &source, input)
+    }
+
+    #[test]
+    fn test1() {
+        let dir = write(
+            "test1",
+            &[
+                (
+                    "main.plang",
+                    "import \"lib/utils.plang\" as u
+                    from \"lib/utils\" import sum, base
+                    import \"lib/math\" as math
+                    fn total(xs) { return sum(map(xs, math.square)) }
+                    let f = u.sum
+                    exit(total([1, 2]) + f([base]) + u.base + len(u))",
+                ),
+                (
+                    "lib/utils.plang",
+                    "from \"math\" import square
+                    fn add(a, b) { return a + b }
+                    pub fn sum(xs) { return reduce(xs, add, 0) }
+                    export let base = square(3)",
+                ),
+                ("lib/math.plang", "pub fn square(x) { return x * x }"),
+            ],
+        );
+
+        let mut loader = Loader::default();
+        let out = link(&mut loader, &dir.join("main.plang")).unwrap();
+
+        // Each module runs once, however often it is imported.
+        let inits = out
+            .iter()
+            .filter(|t| matches!(t.inner(), Statement::Let { name: Expression::Identifier(n), .. } if n.starts_with('<')))
+            .count();
+        assert_eq!(inits, 2);
+        assert_eq!(loader.files.len(), 3);
+
+        assert_eq!(
+            interpret(out.clone(), Context::default()),
+            Err(RuntimeError::Exit(5 + 9 + 9 + 2))
+        );
+        assert_eq!(
+            crate::vm::run(out, Context::default()),
+            Err(RuntimeError::Exit(25))
+        );
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test2() {
+        let dir = write(
+            "test2",
+            &[
+                ("a.plang", "import \"b\" as b"),
+                ("b.plang", "import \"c\" as c"),
+                ("c.plang", "import \"a\" as a"),
+                ("d.plang", "from \"e\" import hidden"),
+                (
+                    "e.plang",
+                    "fn hidden() { }\npub fn shown() { }\nexport let x = 1",
+                ),
+                ("f.plang", "import \"e\" as e\ne.x(1)"),
+                ("g.plang", "import \"missing\" as m"),
+                ("h.plang", "from \"e\" import shown\nfn shown() { }"),
+                ("i.plang", "if true { import \"e\" as e }"),
+                ("j.plang", "import \"e\" as x\ne.hidden()"),
+            ],
+        );
+        let error = |name: &str| link(&mut Loader::default(), &dir.join(name)).unwrap_err();
+
+        assert_eq!(
+            error("a.plang"),
+            RuntimeError::Import(String::from(
+                "import cycle: a.plang -> b.plang -> c.plang -> a.plang"
+            ))
+        );
+        assert_eq!(
+            error("d.plang"),
+            RuntimeError::Import(String::from("`e` does not export `hidden`"))
+        );
+        assert_eq!(
+            error("f.plang"),
+            RuntimeError::Import(String::from("`e` does not export a function `x`"))
+        );
+        assert_eq!(
+            error("g.plang"),
+            RuntimeError::Import(String::from("cannot find module `missing`"))
+        );
+        assert_eq!(
+            error("h.plang"),
+            RuntimeError::Import(String::from("`shown` is both imported and defined"))
+        );
+        assert_eq!(error("i.plang").kind(), "import");
+        assert_eq!(
+            error("j.plang"),
+            RuntimeError::Import(String::from("`e` is not an imported module"))
+        );
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test3() {
+        let dir = write(
+            "test3",
+            &[
+                ("app/main.plang", "import \"checks\" as c\nc.positive(-1)"),
+                (
+                    "vendor/checks.plang",
+                    "pub fn positive(x) {\n  if x < 0 { throw \"negative\" }\n}",
+                ),
+            ],
+        );
+
+        let path = dir.join("app/main.plang");
+        assert!(link(&mut Loader::default(), &path).is_err());
+
+        let mut loader = Loader::new(vec![dir.join("vendor")], Permissions::default());
+        let out = link(&mut loader, &path).unwrap();
+        let error = interpret(out, Context::default()).unwrap_err();
+
+        let trace: Vec<_> = error
+            .trace()
+            .iter()
+            .map(|t| (t.function.as_deref(), t.span.file, t.span.line))
+            .collect();
+        assert_eq!(trace, vec![(Some("checks.positive"), 1, 2), (None, 0, 2)]);
+        assert!(error
+            .traceback(&loader.files)
+            .contains("checks.plang, line 2, column 14, in checks.positive\n    if x < 0"));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test4() {
+        let dir = write(
+            "test4",
+            &[
+                ("app/main.plang", "import \"../secret.txt\" as s"),
+                ("app/leak.plang", "import \"notes.txt\" as s"),
+                ("app/notes.txt", "\nTOP SECRET token=abc123"),
+                ("secret.txt", "TOP SECRET token=abc123"),
+            ],
+        );
+
+        let mut permissions = Permissions::default();
+        permissions.read.allow(dir.join("app").to_str().unwrap());
+        permissions.sandbox();
+        let error = |name: &str| {
+            link(
+                &mut Loader::new(Vec::new(), permissions.clone()),
+                &dir.join("app").join(name),
+            )
+            .unwrap_err()
+        };
+
+        assert!(matches!(error("main.plang"), RuntimeError::Permission(_)));
+        let message = error("leak.plang").to_string();
+        assert!(
+            message.ends_with("notes.txt: cannot parse line 2"),
+            "{}",
+            message
+        );
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -1,38 +1,87 @@
 use std::env;
 use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
 
-use crate::interpreter::interpret;
+use crate::cli::{parse_args, Engine};
+use crate::interpreter::{interpret, RuntimeError};
+use crate::loader::Loader;
+use crate::optimizer::optimize;
 use crate::parser::parse;
 
+mod cli;
 mod interpreter;
+mod loader;
+mod optimizer;
 mod parser;
+mod resolver;
 mod stdlib;
 mod token;
+mod value;
+mod vm;
 
 fn main() {
-    let args = env::args().nth(1);
-
-    let file = if let Some(f) = args {
-        f
-    } else {
-        panic!("Provide proper args!");
+    let cli = match parse_args(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("usage: plang [flags] <file> [args...]");
+            process::exit(2);
+        }
     };
 
-    let maybe_content = fs::read_to_string(file);
-    let content = if maybe_content.is_ok() {
-        maybe_content.unwrap()
+    let file = Path::new(&cli.file);
+    let content = if let Ok(content) = fs::read_to_string(file) {
+        content
     } else {
         panic!("File not found!");
     };
 
-    match parse(&content) {
-        Ok((_, output)) => {
-            interpret(output);
-        }
+    let output = match parse(&content) {
+        Ok((rest, output)) if rest.trim().is_empty() => output,
+        Ok((rest, _)) => panic!(
+            "Unexpected input: {}",
+            rest.trim_start().lines().next().unwrap()
+        ),
         Err(e) => panic!("{:#?}", e),
+    };
+
+    let mut search = cli.path;
+    if let Some(path) = env::var_os("PLANG_PATH") {
+        search.extend(env::split_paths(&path));
+    }
+
+    let mut loader = Loader::new(search, cli.context.permissions.clone());
+    let output = match loader.link(file, &content, output) {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let output = optimize(output, cli.opt_level);
+
+    if cli.dump_ast {
+        println!("{:#?}", output);
+        return;
     }
 
-    let (_, parse) = parse(&content).unwrap();
+    let result = match cli.engine {
+        Engine::Tree => interpret(output, cli.context),
+        Engine::Vm => vm::run(output, cli.context),
+    };
+
+    let code = match result {
+        Ok(()) => 0,
+        Err(RuntimeError::Exit(code)) => code,
+        Err(e) => {
+            eprint!("{}", e.report(&loader.files));
+            1
+        }
+    };
 
-    interpret(parse);
+    io::stdout().flush().ok();
+    process::exit(code);
 }
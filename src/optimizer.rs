@@ -0,0 +1,489 @@
+//! Rewrites a parsed program into a cheaper equivalent before it runs.
+//!
+//! Level 1 folds operators whose operands are literals, keeps only the taken
+//! branch of an `if` on a literal condition and drops statements that follow a
+//! `return` or `break`. Level 2 also inlines calls to trivial functions, whose
+//! whole body is `return` of an expression over their parameters.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::interpreter::{globals, infix};
+use crate::parser::ast::{Block, Expression, Statement};
+
+/// A function simple enough to substitute at its call sites.
+struct Inline {
+    params: Vec<String>,
+    body: Expression,
+}
+
+struct Optimizer {
+    level: u8,
+    /// Number of `fn` statements declaring each name, anywhere in the program.
+    definitions: HashMap<String, usize>,
+    builtins: HashSet<String>,
+    inline: HashMap<String, Inline>,
+    /// How many blocks deep the optimizer is; 0 is the top level.
+    depth: usize,
+}
+
+fn definitions(input: &Block, out: &mut HashMap<String, usize>) {
+    for statement in input {
+        match statement.inner() {
+            Statement::Fn { name, body, .. } => {
+                if let Expression::Identifier(t) = name {
+                    *out.entry(t.to_string()).or_default() += 1;
+                }
+                definitions(body, out);
+            }
+            Statement::If {
+                then, otherwise, ..
+            } => {
+                definitions(then, out);
+                if let Some(o) = otherwise {
+                    definitions(o, out);
+                }
+            }
+            Statement::Loop { then, .. } => definitions(then, out),
+            Statement::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                definitions(body, out);
+                if let Some((_, c)) = catch {
+                    definitions(c, out);
+                }
+                if let Some(f) = finally {
+                    definitions(f, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn literal(input: &Expression) -> bool {
+    matches!(
+        input,
+        Expression::Number(_) | Expression::String(_) | Expression::Boolean(_) | Expression::Nil
+    )
+}
+
+fn terminal(input: Option<&Statement>) -> bool {
+    matches!(
+        input.map(Statement::inner),
+        Some(Statement::Return { .. } | Statement::Break | Statement::Throw { .. })
+    )
+}
+
+/// Whether an expression only combines literals and the given parameters.
+fn pure(input: &Expression, params: &[String]) -> bool {
+    match input {
+        Expression::Identifier(t) => params.contains(t),
+        Expression::Infix(l, _, r) | Expression::Index(l, r) => pure(l, params) && pure(r, params),
+        Expression::List(items) => items.iter().all(|i| pure(i, params)),
+        Expression::Map(entries) => entries
+            .iter()
+            .all(|(k, v)| pure(k, params) && pure(v, params)),
+        t => literal(t),
+    }
+}
+
+fn uses(input: &Expression, name: &str) -> bool {
+    match input {
+        Expression::Identifier(t) => t == name,
+        Expression::Infix(l, _, r) | Expression::Index(l, r) => uses(l, name) || uses(r, name),
+        Expression::List(items) => items.iter().any(|i| uses(i, name)),
+        Expression::Map(entries) => entries.iter().any(|(k, v)| uses(k, name) || uses(v, name)),
+        _ => false,
+    }
+}
+
+fn substitute(input: &Expression, args: &HashMap<&str, &Expression>) -> Expression {
+    match input {
+        Expression::Identifier(t) => args[t.as_str()].clone(),
+        Expression::Infix(l, op, r) => Expression::Infix(
+            substitute(l, args).boxed(),
+            op.clone(),
+            substitute(r, args).boxed(),
+        ),
+        Expression::Index(l, r) => {
+            Expression::Index(substitute(l, args).boxed(), substitute(r, args).boxed())
+        }
+        Expression::List(items) => {
+            Expression::List(items.iter().map(|i| substitute(i, args)).collect())
+        }
+        Expression::Map(entries) => Expression::Map(
+            entries
+                .iter()
+                .map(|(k, v)| (substitute(k, args), substitute(v, args)))
+                .collect(),
+        ),
+        t => t.clone(),
+    }
+}
+
+impl Inline {
+    fn new(params: &[Expression], body: &Block) -> Option<Self> {
+        let params = params
+            .iter()
+            .map(|p| match p {
+                Expression::Identifier(t) => Some(t.to_string()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let distinct: HashSet<_> = params.iter().collect();
+        if distinct.len() != params.len() {
+            return None;
+        }
+
+        match body
+            .iter()
+            .map(Statement::inner)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [Statement::Return { value: Some(t) }] if pure(t, &params) => Some(Self {
+                params,
+                body: t.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The body with the arguments in place of the parameters, if substituting
+    /// them cannot change what the call evaluates or reports.
+    fn apply(&self, args: &[Expression]) -> Option<Expression> {
+        if args.len() != self.params.len() {
+            return None;
+        }
+
+        for (p, arg) in self.params.iter().zip(args) {
+            let safe = match arg {
+                Expression::Identifier(_) => uses(&self.body, p),
+                t => literal(t),
+            };
+            if !safe {
+                return None;
+            }
+        }
+
+        let args = self.params.iter().map(String::as_str).zip(args).collect();
+        Some(substitute(&self.body, &args))
+    }
+}
+
+impl Optimizer {
+    fn block(&mut self, input: Block) -> Block {
+        let mut out = Vec::new();
+
+        for statement in input {
+            self.statement(statement, &mut out);
+            if terminal(out.last()) {
+                break;
+            }
+        }
+        out
+    }
+
+    fn nested(&mut self, input: Block) -> Block {
+        self.depth += 1;
+        let out = self.block(input);
+        self.depth -= 1;
+        out
+    }
+
+    fn statement(&mut self, input: Statement, out: &mut Block) {
+        let statement = match input {
+            Statement::At(span, t) => {
+                let start = out.len();
+                self.statement(*t, out);
+
+                // Statements spliced in from a block keep their own spans.
+                if out.len() == start + 1 && !matches!(out[start], Statement::At(..)) {
+                    let t = out.pop().expect("pushed above");
+                    out.push(Statement::At(span, t.boxed()));
+                }
+                return;
+            }
+
+            Statement::Let { name, initial } => Statement::Let {
+                name,
+                initial: self.expression(initial),
+            },
+
+            Statement::Fn { name, params, body } => {
+                let body = self.nested(body);
+
+                if let Expression::Identifier(t) = &name {
+                    let trivial = self.level >= 2
+                        && self.depth == 0
+                        && self.definitions.get(t) == Some(&1)
+                        && !self.builtins.contains(t);
+
+                    if let Some(f) = Inline::new(&params, &body).filter(|_| trivial) {
+                        self.inline.insert(t.to_string(), f);
+                    }
+                }
+                Statement::Fn { name, params, body }
+            }
+
+            Statement::If {
+                condition,
+                then,
+                otherwise,
+            } => match self.expression(condition) {
+                // `if` blocks share the surrounding scope, so the taken branch
+                // can take the place of the whole statement.
+                Expression::Boolean(true) => {
+                    out.extend(self.nested(then));
+                    return;
+                }
+                Expression::Boolean(false) => {
+                    if let Some(o) = otherwise {
+                        out.extend(self.nested(o));
+                    }
+                    return;
+                }
+                condition => Statement::If {
+                    condition,
+                    then: self.nested(then),
+                    otherwise: otherwise.map(|o| self.nested(o)),
+                },
+            },
+
+            Statement::Loop {
+                iterable,
+                value,
+                then,
+            } => Statement::Loop {
+                iterable,
+                value: value.map(|v| self.expression(v)),
+                then: self.nested(then),
+            },
+
+            Statement::Return { value } => Statement::Return {
+                value: value.map(|v| self.expression(v)),
+            },
+
+            Statement::Expr { expression } => Statement::Expr {
+                expression: self.expression(expression),
+            },
+
+            Statement::Break => Statement::Break,
+
+            Statement::Try {
+                body,
+                catch,
+                finally,
+            } => Statement::Try {
+                body: self.nested(body),
+                catch: catch.map(|(name, c)| (name, self.nested(c))),
+                finally: finally.map(|f| self.nested(f)),
+            },
+
+            Statement::Throw { value } => Statement::Throw {
+                value: self.expression(value),
+            },
+
+            // The loader links these away before a program runs.
+            t @ (Statement::Import { .. } | Statement::Export(_)) => t,
+        };
+
+        out.push(statement);
+    }
+
+    fn expression(&mut self, input: Expression) -> Expression {
+        match input {
+            Expression::Infix(left, op, right) => {
+                let left = self.expression(*left);
+                let right = self.expression(*right);
+
+                if literal(&left) && literal(&right) {
+                    // Operations that fail are left for the engines to report.
+                    if let Ok(t) = infix(left.clone(), op.clone(), right.clone()) {
+                        return t;
+                    }
+                }
+                Expression::Infix(left.boxed(), op, right.boxed())
+            }
+
+            Expression::Call(name, params) => {
+                let params: Vec<_> = params.into_iter().map(|p| self.expression(p)).collect();
+
+                let inlined = match &*name {
+                    Expression::Identifier(t) => self.inline.get(t).and_then(|f| f.apply(&params)),
+                    _ => None,
+                };
+
+                match inlined {
+                    Some(body) => self.expression(body),
+                    None => Expression::Call(name, params),
+                }
+            }
+
+            Expression::Assign(name, value) => {
+                Expression::Assign(name, self.expression(*value).boxed())
+            }
+
+            Expression::List(items) => {
+                Expression::List(items.into_iter().map(|i| self.expression(i)).collect())
+            }
+
+            Expression::Map(entries) => Expression::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (self.expression(k), self.expression(v)))
+                    .collect(),
+            ),
+
+            Expression::Index(value, index) => Expression::Index(
+                self.expression(*value).boxed(),
+                self.expression(*index).boxed(),
+            ),
+
+            Expression::Async(function, args) => {
+                let mut out = Vec::new();
+                self.statement(*function, &mut out);
+                Expression::Async(
+                    out.pop().expect("a function").boxed(),
+                    args.into_iter().map(|a| self.expression(a)).collect(),
+                )
+            }
+
+            Expression::Await(value) => Expression::Await(self.expression(*value).boxed()),
+
+            t => t,
+        }
+    }
+}
+
+/// Optimizes a parsed program; level 0 returns it unchanged.
+pub fn optimize(input: Block, level: u8) -> Block {
+    if level == 0 {
+        return input;
+    }
+
+    let mut optimizer = Optimizer {
+        level,
+        definitions: HashMap::new(),
+        builtins: globals().into_keys().collect(),
+        inline: HashMap::new(),
+        depth: 0,
+    };
+    definitions(&input, &mut optimizer.definitions);
+
+    optimizer.block(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{interpret, Context, RuntimeError};
+    use crate::parser::ast::Op;
+
+    fn optimized(source: &str, level: u8) -> Block {
+        let (_, input) = crate::parser::parse(source).unwrap();
+        optimize(input, level)
+    }
+
+    /// The statements of `source` after optimizing, without their spans.
+    fn statements(source: &str, level: u8) -> Vec<Statement> {
+        optimized(source, level)
+            .iter()
+            .map(|t| t.inner().clone())
+            .collect()
+    }
+
+    #[test]
+    fn test1() {
+        assert_eq!(
+            optimized("let x = 10.0 + 3 * 2\nlet y = \"a\" + \"b\"", 1)
+                .iter()
+                .map(Statement::inner)
+                .collect::<Vec<_>>(),
+            vec![
+                &Statement::Let {
+                    name: Expression::Identifier(String::from("x")),
+                    initial: Expression::Number(16.0),
+                },
+                &Statement::Let {
+                    name: Expression::Identifier(String::from("y")),
+                    initial: Expression::String(String::from("ab")),
+                },
+            ]
+        );
+
+        // Failing operations and non-literal operands stay as they are.
+        assert_eq!(
+            optimized("let x = 1 + \"a\"", 1),
+            optimized("let x = 1 + \"a\"", 0)
+        );
+        assert_eq!(
+            *optimized("let y = 1\nlet x = y + 2 * 3", 1)[1].inner(),
+            Statement::Let {
+                name: Expression::Identifier(String::from("x")),
+                initial: Expression::Infix(
+                    Expression::Identifier(String::from("y")).boxed(),
+                    Op::Add,
+                    Expression::Number(6.0).boxed()
+                ),
+            }
+        );
+    }
+
+    #[test]
+    fn test2() {
+        assert_eq!(
+            statements(
+                "if 1 > 2 { print(1) } else { print(2)\nreturn 3\nprint(4) }\nprint(5)",
+                1
+            ),
+            statements("print(2)\nreturn 3", 0)
+        );
+        assert_eq!(
+            statements("loop { break\nprint(1) }\nif false { print(2) }", 1),
+            statements("loop { break }", 0)
+        );
+    }
+
+    #[test]
+    fn test3() {
+        let source = "fn double(x) { return x * 2 }
+            fn twice(x) { return x + x }
+            let a = double(4)
+            let b = double(a)
+            let c = twice(double(1))";
+
+        assert_eq!(
+            statements(source, 2)[2..],
+            statements("let a = 8\nlet b = a * 2\nlet c = 4", 0)[..]
+        );
+        assert_eq!(optimized(source, 1), optimized(source, 0));
+
+        // Functions declared twice, or shadowed by a built-in, are left alone.
+        let source = "fn f(x) { return x }
+            if true { fn f(x) { return 1 } }
+            fn len(x) { return 0 }
+            let a = f(1) + len([1])";
+        assert_eq!(optimized(source, 2)[3], optimized(source, 0)[3]);
+    }
+
+    #[test]
+    fn test4() {
+        let source = "fn sq(x) { return x * x }
+            let total = 0
+            loop i : [1, 2, 3] {
+                if 2 > 1 { total = total + sq(i) }
+            }
+            exit(total + sq(2 + 2))";
+
+        for level in 0..=2 {
+            assert_eq!(
+                interpret(optimized(source, level), Context::default()),
+                Err(RuntimeError::Exit(30))
+            );
+        }
+    }
+}
@@ -1,7 +1,22 @@
+use std::sync::Arc;
+
+use crate::value::{Channel, Future, Lines, Task};
+
 pub type Block = Vec<Statement>;
 
+/// Where a statement starts in its source; lines and columns count from 1.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Span {
+    /// Which of the program's files; 0 is the one being run.
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Statement {
+    /// A statement and where it starts, which errors raised in it report.
+    At(Span, Box<Statement>),
     Let {
         name: Expression,
         initial: Expression,
@@ -28,6 +43,37 @@ pub enum Statement {
         value: Option<Expression>,
     },
     Break,
+    Try {
+        body: Block,
+        /// The variable the error is bound to and the block handling it.
+        catch: Option<(Expression, Block)>,
+        finally: Option<Block>,
+    },
+    Throw {
+        value: Expression,
+    },
+    /// `import "path" as name`, or `from "path" import a, b` with `names`.
+    Import {
+        path: String,
+        name: Option<Expression>,
+        names: Vec<Expression>,
+    },
+    /// A function or variable other files may import.
+    Export(Box<Statement>),
+}
+
+impl Statement {
+    pub fn boxed(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    /// The statement without the span around it.
+    pub fn inner(&self) -> &Self {
+        match self {
+            Statement::At(_, t) => t.inner(),
+            t => t,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -35,13 +81,31 @@ pub enum Expression {
     Number(f64),
     String(String),
     Boolean(bool),
+    Nil,
     Identifier(String),
+    /// A variable the resolver bound to its scope depth and slot.
+    Local(String, usize, usize),
     Assign(Box<Expression>, Box<Expression>),
     Call(Box<Expression>, Vec<Expression>),
     Infix(Box<Expression>, Op, Box<Expression>),
-    Prefix(Op, Box<Expression>),
     List(Vec<Expression>),
+    Map(Vec<(Expression, Expression)>),
+    Index(Box<Expression>, Box<Expression>),
+    /// A function value; copies share the body, which identifies the function.
+    Function(String, Vec<Expression>, Arc<Block>),
+    Lines(Lines),
+    Task(Task),
+    Future(Future),
+    Channel(Channel),
     Break,
+    /// A future of a call: the `Fn` statement of the function, which binds
+    /// no name, and the arguments. The body of an `async fn` returns one.
+    Async(Box<Statement>, Vec<Expression>),
+    /// `await x`.
+    Await(Box<Expression>),
+    /// A call in tail position, handed back to the enclosing call to run in
+    /// place: the function value and its evaluated arguments.
+    TailCall(Box<Expression>, Vec<Expression>),
 }
 
 impl Expression {
@@ -52,6 +116,27 @@ impl Expression {
     pub fn boxed(self) -> Box<Self> {
         Box::new(self)
     }
+
+    /// Builds a map value with string keys.
+    pub fn map(entries: Vec<(&str, Expression)>) -> Self {
+        Expression::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (Expression::String(k.to_string()), v))
+                .collect(),
+        )
+    }
+
+    /// Looks up a string key in a map value.
+    pub fn get(&self, key: &str) -> Option<&Expression> {
+        match self {
+            Expression::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| matches!(k, Expression::String(k) if k == key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -60,7 +145,6 @@ pub enum Op {
     Subtract,
     Multiply,
     Divide,
-    Modulo,
     Equals,
     NotEquals,
     LessThan,
@@ -69,5 +153,4 @@ pub enum Op {
     GreaterThanOrEquals,
     And,
     Or,
-    Not,
 }
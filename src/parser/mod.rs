@@ -1,18 +1,94 @@
+use std::cell::RefCell;
+
 use nom::IResult;
 
-use ast::Statement;
+use ast::{Span, Statement};
 use parse_block::parse_items;
 
 pub mod ast;
 mod parse_block;
 mod parse_expr;
 mod parse_fn;
+mod parse_go;
 mod parse_if;
+mod parse_import;
 mod parse_let;
 mod parse_loop;
 mod parse_return;
+mod parse_try;
 mod tools;
 
+/// The source being parsed: its file, its length and the offset each of its
+/// lines starts at. Parsers only see the input left, so spans are worked
+/// out from how much of it remains.
+#[derive(Default)]
+struct Source {
+    file: usize,
+    len: usize,
+    lines: Vec<usize>,
+}
+
+thread_local! {
+    static SOURCE: RefCell<Source> = RefCell::new(Source::default());
+}
+
+/// The span of the text `rest` starts with, within the source being parsed.
+fn span(rest: &str) -> Span {
+    SOURCE.with(|source| {
+        let Source { file, len, lines } = &*source.borrow();
+        let offset = len.saturating_sub(rest.len());
+        let line = lines.partition_point(|start| *start <= offset);
+        let start = line.checked_sub(1).map_or(0, |i| lines[i]);
+
+        Span {
+            file: *file,
+            line,
+            column: offset - start + 1,
+        }
+    })
+}
+
 pub fn parse(input: &str) -> IResult<&str, Vec<Statement>> {
-    parse_items(input)
+    parse_file(input, 0)
+}
+
+/// Parses the program's file number `file`, which its spans record.
+pub fn parse_file(input: &str, file: usize) -> IResult<&str, Vec<Statement>> {
+    let lines = std::iter::once(0)
+        .chain(input.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let outer = SOURCE.with(|source| {
+        source.replace(Source {
+            file,
+            len: input.len(),
+            lines,
+        })
+    });
+
+    let out = parse_items(input);
+    SOURCE.with(|source| source.replace(outer));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test1() {
+        let (_, out) = parse("let a = 1\n\nif a == 1 {\n   print(a) }").unwrap();
+
+        let spans: Vec<_> = match &out[..] {
+            [Statement::At(first, _), Statement::At(second, t)] => match &**t {
+                Statement::If { then, .. } => match &then[..] {
+                    [Statement::At(third, _)] => vec![*first, *second, *third],
+                    t => panic!("{:?}", t),
+                },
+                t => panic!("{:?}", t),
+            },
+            t => panic!("{:?}", t),
+        };
+        let spans: Vec<_> = spans.iter().map(|s| (s.line, s.column)).collect();
+        assert_eq!(spans, vec![(1, 1), (3, 1), (4, 4)]);
+    }
 }
@@ -1,28 +1,47 @@
 use crate::parser::ast::Statement;
 use crate::parser::parse_expr::parse_expr;
 use crate::parser::parse_fn::parse_fn;
+use crate::parser::parse_go::parse_go;
 use crate::parser::parse_if::parse_if;
+use crate::parser::parse_import::{parse_export, parse_from, parse_import};
 use crate::parser::parse_let::parse_let;
 use crate::parser::parse_loop::parse_loop;
 use crate::parser::parse_return::{parse_break, parse_return};
+use crate::parser::parse_try::{parse_throw, parse_try};
+use crate::parser::span;
 use crate::parser::tools::parse_tag;
 use crate::token::Token;
 
 use nom::branch::alt;
+use nom::character::complete::multispace0;
 use nom::multi::many0;
 use nom::sequence::delimited;
 use nom::IResult;
 
-pub fn parse_items(input: &str) -> IResult<&str, Vec<Statement>> {
-    many0(alt((
+/// Parses one statement, recording where it starts.
+fn parse_statement(input: &str) -> IResult<&str, Statement> {
+    let (input, _) = multispace0(input)?;
+    let (rest, x) = alt((
         parse_let,
         parse_if,
         parse_loop,
         parse_fn,
+        parse_go,
+        parse_try,
+        parse_throw,
+        parse_import,
+        parse_from,
+        parse_export,
         parse_expr,
         parse_return,
         parse_break,
-    )))(input)
+    ))(input)?;
+
+    Ok((rest, Statement::At(span(input), x.boxed())))
+}
+
+pub fn parse_items(input: &str) -> IResult<&str, Vec<Statement>> {
+    many0(parse_statement)(input)
 }
 
 pub fn parse_block(input: &str) -> IResult<&str, Vec<Statement>> {
@@ -1,5 +1,5 @@
 use crate::parser::ast::{Expression, Statement};
-use crate::parser::tools::{parse_call, parse_identifier, parse_tag};
+use crate::parser::tools::{parse_await, parse_call, parse_identifier, parse_tag};
 use crate::parser::tools::{parse_iterator, parse_value};
 use crate::token::Token;
 
@@ -18,7 +18,7 @@ fn parse_assignment(input: &str) -> IResult<&str, Expression> {
 }
 
 pub fn parse_expr(input: &str) -> IResult<&str, Statement> {
-    let (input, x) = alt((parse_assignment, parse_call))(input)?;
+    let (input, x) = alt((parse_assignment, parse_await, parse_call))(input)?;
     Ok((input, Statement::Expr { expression: x }))
 }
 
@@ -63,4 +63,41 @@ mod tests {
             ))
         )
     }
+
+    #[test]
+    fn test3() {
+        let call = |name: &str, params| {
+            Expression::Call(Expression::Identifier(String::from(name)).boxed(), params)
+        };
+
+        assert_eq!(
+            parse_expr("x = await f(1) + 1"),
+            Ok((
+                "",
+                Statement::Expr {
+                    expression: Expression::Assign(
+                        Expression::Identifier(String::from("x")).boxed(),
+                        Expression::Infix(
+                            Expression::Await(call("f", vec![Expression::Number(1.0)]).boxed())
+                                .boxed(),
+                            Op::Add,
+                            Expression::Number(1.0).boxed()
+                        )
+                        .boxed()
+                    )
+                }
+            ))
+        );
+        assert_eq!(
+            parse_expr("await t"),
+            Ok((
+                "",
+                Statement::Expr {
+                    expression: Expression::Await(
+                        Expression::Identifier(String::from("t")).boxed()
+                    )
+                }
+            ))
+        );
+    }
 }
@@ -1,14 +1,16 @@
-use crate::parser::ast::Statement;
+use crate::parser::ast::{Block, Expression, Statement};
 use crate::parser::parse_block::parse_block;
-use crate::parser::tools::{parse_identifier, parse_tag};
+use crate::parser::tools::{parse_identifier, parse_keyword, parse_tag};
 use crate::token::Token;
 
 use nom::character::complete::multispace1;
+use nom::combinator::opt;
 use nom::multi::separated_list0;
 use nom::sequence::{delimited, preceded};
 use nom::IResult;
 
 pub fn parse_fn(input: &str) -> IResult<&str, Statement> {
+    let (input, is_async) = opt(parse_keyword(Token::ASYNC))(input)?;
     let (input, ..) = parse_tag(Token::FN)(input)?;
 
     let (input, x) = preceded(multispace1, parse_identifier)(input)?;
@@ -21,6 +23,10 @@ pub fn parse_fn(input: &str) -> IResult<&str, Statement> {
 
     let (input, z) = parse_block(input)?;
 
+    if is_async.is_some() {
+        return Ok((input, asynchronous(x, y, z)));
+    }
+
     Ok((
         input,
         Statement::Fn {
@@ -31,10 +37,28 @@ pub fn parse_fn(input: &str) -> IResult<&str, Statement> {
     ))
 }
 
+/// `async fn f(x) { ... }` becomes a function `f` that returns a future of
+/// an unbound function, also named `f`, with the body, so that calling `f`
+/// gives a future instead of running the body.
+fn asynchronous(name: Expression, params: Vec<Expression>, body: Block) -> Statement {
+    let inner = Statement::Fn {
+        name: name.clone(),
+        params: params.clone(),
+        body,
+    };
+
+    Statement::Fn {
+        name,
+        params: params.clone(),
+        body: vec![Statement::Return {
+            value: Some(Expression::Async(inner.boxed(), params)),
+        }],
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::parser::ast::Expression;
 
     #[test]
     fn test1() {
@@ -70,10 +94,41 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::single_match)]
     fn test3() {
         match parse_fn("fnmain() {  }") {
             Ok(_) => panic!(),
             Err(_) => {}
         }
     }
+
+    #[test]
+    fn test4() {
+        let f = Expression::Identifier(String::from("f"));
+        let x = Expression::Identifier(String::from("x"));
+        let inner = Statement::Fn {
+            name: f.clone(),
+            params: vec![x.clone()],
+            body: vec![],
+        };
+
+        assert_eq!(
+            parse_fn("async fn f(x) { }"),
+            Ok((
+                "",
+                Statement::Fn {
+                    name: f,
+                    params: vec![x.clone()],
+                    body: vec![Statement::Return {
+                        value: Some(Expression::Async(inner.boxed(), vec![x]))
+                    }]
+                }
+            ))
+        )
+    }
+
+    #[test]
+    fn test5() {
+        assert!(parse_fn("asyncfn main() {  }").is_err());
+    }
 }
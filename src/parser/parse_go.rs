@@ -0,0 +1,49 @@
+use crate::parser::ast::{Expression, Statement};
+use crate::parser::tools::{parse_call, parse_keyword};
+use crate::token::Token;
+
+use nom::IResult;
+
+/// `go f(args)` starts `f` as a task, the same as `spawn(f, args)`.
+pub fn parse_go(input: &str) -> IResult<&str, Statement> {
+    let (input, ..) = parse_keyword(Token::GO)(input)?;
+    let (input, call) = parse_call(input)?;
+
+    let expression = match call {
+        Expression::Call(name, mut params) => {
+            params.insert(0, *name);
+            Expression::Call(
+                Expression::Identifier(String::from("spawn")).boxed(),
+                params,
+            )
+        }
+        t => t,
+    };
+    Ok((input, Statement::Expr { expression }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test1() {
+        assert_eq!(
+            parse_go("go worker(tx, 1)"),
+            Ok((
+                "",
+                Statement::Expr {
+                    expression: Expression::Call(
+                        Expression::Identifier(String::from("spawn")).boxed(),
+                        vec![
+                            Expression::Identifier(String::from("worker")),
+                            Expression::Identifier(String::from("tx")),
+                            Expression::Number(1.0),
+                        ]
+                    )
+                }
+            ))
+        );
+        assert!(parse_go("good(x)").is_err());
+    }
+}
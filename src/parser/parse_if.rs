@@ -43,20 +43,27 @@ mod tests {
 
     #[test]
     fn test1() {
-        assert_eq!(
-            parse_if("if true { let t = 0 } else { }"),
-            Ok((
-                "",
-                Statement::If {
-                    condition: Expression::Boolean(true),
-                    then: vec![Statement::Let {
+        let (rest, out) = parse_if("if true { let t = 0 } else { }").unwrap();
+
+        assert_eq!(rest, "");
+        match out {
+            Statement::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                assert_eq!(condition, Expression::Boolean(true));
+                assert_eq!(
+                    then.iter().map(Statement::inner).collect::<Vec<_>>(),
+                    vec![&Statement::Let {
                         name: Expression::Identifier(String::from("t")),
                         initial: Expression::Number(0.0)
-                    }],
-                    otherwise: Some(vec![])
-                }
-            ))
-        );
+                    }]
+                );
+                assert_eq!(otherwise, Some(vec![]));
+            }
+            t => panic!("{:?}", t),
+        }
     }
 
     #[test]
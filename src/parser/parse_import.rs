@@ -0,0 +1,117 @@
+use crate::parser::ast::{Expression, Statement};
+use crate::parser::parse_fn::parse_fn;
+use crate::parser::parse_let::parse_let;
+use crate::parser::tools::{parse_identifier, parse_keyword, parse_string, parse_tag};
+use crate::token::Token;
+
+use nom::branch::alt;
+use nom::multi::separated_list1;
+use nom::sequence::preceded;
+use nom::IResult;
+
+fn parse_path(input: &str) -> IResult<&str, String> {
+    let (input, path) = parse_string(input)?;
+    match path {
+        Expression::String(path) => Ok((input, path)),
+        _ => unreachable!("parse_string gives strings"),
+    }
+}
+
+/// `import "path" as name`
+pub fn parse_import(input: &str) -> IResult<&str, Statement> {
+    let (input, path) = preceded(parse_keyword(Token::IMPORT), parse_path)(input)?;
+    let (input, name) = preceded(parse_keyword(Token::AS), parse_identifier)(input)?;
+
+    Ok((
+        input,
+        Statement::Import {
+            path,
+            name: Some(name),
+            names: vec![],
+        },
+    ))
+}
+
+/// `from "path" import a, b`
+pub fn parse_from(input: &str) -> IResult<&str, Statement> {
+    let (input, path) = preceded(parse_keyword(Token::FROM), parse_path)(input)?;
+    let (input, names) = preceded(
+        parse_keyword(Token::IMPORT),
+        separated_list1(parse_tag(Token::COMMA), parse_identifier),
+    )(input)?;
+
+    Ok((
+        input,
+        Statement::Import {
+            path,
+            name: None,
+            names,
+        },
+    ))
+}
+
+/// `export fn`, `export let` or `pub fn`.
+pub fn parse_export(input: &str) -> IResult<&str, Statement> {
+    let (input, x) = alt((
+        preceded(parse_keyword(Token::EXPORT), alt((parse_fn, parse_let))),
+        preceded(parse_keyword(Token::PUB), parse_fn),
+    ))(input)?;
+
+    Ok((input, Statement::Export(x.boxed())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test1() {
+        assert_eq!(
+            parse_import("import \"lib/utils.plang\" as u"),
+            Ok((
+                "",
+                Statement::Import {
+                    path: String::from("lib/utils.plang"),
+                    name: Some(Expression::Identifier(String::from("u"))),
+                    names: vec![],
+                }
+            ))
+        );
+        assert_eq!(
+            parse_from("from \"utils\" import sum, pi"),
+            Ok((
+                "",
+                Statement::Import {
+                    path: String::from("utils"),
+                    name: None,
+                    names: vec![
+                        Expression::Identifier(String::from("sum")),
+                        Expression::Identifier(String::from("pi"))
+                    ],
+                }
+            ))
+        );
+        assert!(parse_import("import utils as u").is_err());
+    }
+
+    #[test]
+    fn test2() {
+        assert_eq!(
+            parse_export("pub fn f() { }"),
+            Ok((
+                "",
+                Statement::Export(
+                    Statement::Fn {
+                        name: Expression::Identifier(String::from("f")),
+                        params: vec![],
+                        body: vec![],
+                    }
+                    .boxed()
+                )
+            ))
+        );
+        assert!(parse_export("export let x = 1").is_ok());
+        assert!(parse_export("pub let x = 1").is_err());
+        assert!(parse_export("exported(1)").is_err());
+    }
+}
@@ -165,4 +165,76 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn test9() {
+        assert_eq!(
+            parse_let("let x = nil"),
+            Ok((
+                "",
+                Statement::Let {
+                    name: Expression::Identifier(String::from("x")),
+                    initial: Expression::Nil
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test10() {
+        assert_eq!(
+            parse_let("let _my_var2 = 1"),
+            Ok((
+                "",
+                Statement::Let {
+                    name: Expression::Identifier(String::from("_my_var2")),
+                    initial: Expression::Number(1.0)
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test11() {
+        assert_eq!(
+            parse_let("let x = { a: 1, \"b\": [true] }"),
+            Ok((
+                "",
+                Statement::Let {
+                    name: Expression::Identifier(String::from("x")),
+                    initial: Expression::Map(vec![
+                        (
+                            Expression::String(String::from("a")),
+                            Expression::Number(1.0)
+                        ),
+                        (
+                            Expression::String(String::from("b")),
+                            Expression::List(vec![Expression::Boolean(true)])
+                        ),
+                    ])
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test12() {
+        assert_eq!(
+            parse_let("let x = r.stdout[0]"),
+            Ok((
+                "",
+                Statement::Let {
+                    name: Expression::Identifier(String::from("x")),
+                    initial: Expression::Index(
+                        Expression::Index(
+                            Expression::Identifier(String::from("r")).boxed(),
+                            Expression::String(String::from("stdout")).boxed(),
+                        )
+                        .boxed(),
+                        Expression::Number(0.0).boxed(),
+                    )
+                }
+            ))
+        );
+    }
 }
@@ -1,18 +1,14 @@
 use crate::parser::ast::{Expression, Statement};
 use crate::parser::parse_block::parse_block;
-use crate::parser::tools::{parse_identifier, parse_iterator, parse_tag};
+use crate::parser::tools::{parse_identifier, parse_tag, parse_value};
 use crate::token::Token;
 
-use nom::branch::alt;
 use nom::sequence::tuple;
 use nom::IResult;
 
 fn parse_iterator_value(input: &str) -> IResult<&str, (Expression, Expression)> {
-    let (input, (x, _, y)) = tuple((
-        parse_identifier,
-        parse_tag(Token::COLON),
-        alt((parse_identifier, parse_iterator)),
-    ))(input)?;
+    let (input, (x, _, y)) =
+        tuple((parse_identifier, parse_tag(Token::COLON), parse_value))(input)?;
     Ok((input, (x, y)))
 }
 
@@ -100,4 +96,23 @@ mod tests {
             ))
         )
     }
+
+    #[test]
+    fn test4() {
+        assert_eq!(
+            parse_loop(" loop line : read_lines(\"a.txt\") { }"),
+            Ok((
+                "",
+                Statement::Loop {
+                    iterable: Expression::Identifier(String::from("line")).some(),
+                    value: Expression::Call(
+                        Expression::Identifier(String::from("read_lines")).boxed(),
+                        vec![Expression::String(String::from("a.txt"))]
+                    )
+                    .some(),
+                    then: vec![]
+                }
+            ))
+        )
+    }
 }
@@ -0,0 +1,90 @@
+use crate::parser::ast::Statement;
+use crate::parser::parse_block::parse_block;
+use crate::parser::tools::{parse_identifier, parse_keyword, parse_value};
+use crate::token::Token;
+
+use nom::branch::alt;
+use nom::combinator::{map, opt};
+use nom::sequence::{pair, preceded};
+use nom::IResult;
+
+fn parse_finally(input: &str) -> IResult<&str, Vec<Statement>> {
+    preceded(parse_keyword(Token::FINALLY), parse_block)(input)
+}
+
+/// `try { } catch e { } finally { }`, with a `catch`, a `finally` or both.
+pub fn parse_try(input: &str) -> IResult<&str, Statement> {
+    let (input, ..) = parse_keyword(Token::TRY)(input)?;
+    let (input, body) = parse_block(input)?;
+
+    let catch = preceded(
+        parse_keyword(Token::CATCH),
+        pair(parse_identifier, parse_block),
+    );
+    let (input, (catch, finally)) = alt((
+        pair(map(catch, Some), opt(parse_finally)),
+        map(parse_finally, |f| (None, Some(f))),
+    ))(input)?;
+
+    Ok((
+        input,
+        Statement::Try {
+            body,
+            catch,
+            finally,
+        },
+    ))
+}
+
+pub fn parse_throw(input: &str) -> IResult<&str, Statement> {
+    let (input, value) = preceded(parse_keyword(Token::THROW), parse_value)(input)?;
+    Ok((input, Statement::Throw { value }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ast::Expression;
+
+    #[test]
+    fn test1() {
+        assert_eq!(
+            parse_try("try { } catch e { } finally { }"),
+            Ok((
+                "",
+                Statement::Try {
+                    body: vec![],
+                    catch: Some((Expression::Identifier(String::from("e")), vec![])),
+                    finally: Some(vec![]),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_try("try { } finally { }"),
+            Ok((
+                "",
+                Statement::Try {
+                    body: vec![],
+                    catch: None,
+                    finally: Some(vec![]),
+                }
+            ))
+        );
+        assert!(parse_try("try { }").is_err());
+        assert!(parse_try("try { } catch { }").is_err());
+    }
+
+    #[test]
+    fn test2() {
+        assert_eq!(
+            parse_throw("throw \"oops\""),
+            Ok((
+                "",
+                Statement::Throw {
+                    value: Expression::String(String::from("oops"))
+                }
+            ))
+        );
+        assert!(parse_throw("thrown(1)").is_err());
+    }
+}
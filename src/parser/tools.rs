@@ -4,12 +4,13 @@ use crate::token::Token;
 
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until},
-    character::complete::{alpha1, alphanumeric1, multispace0},
-    combinator::peek,
+    bytes::complete::{tag, take_until, take_while1},
+    character::complete::satisfy,
+    character::complete::{alpha1, multispace0},
+    combinator::{not, opt, peek},
     multi::{many0, separated_list0},
     number::complete::double,
-    sequence::{delimited, preceded, tuple},
+    sequence::{delimited, preceded, separated_pair, terminated, tuple},
     IResult,
 };
 
@@ -21,35 +22,48 @@ pub fn parse_tag<'a>(t: &'a str) -> impl Fn(&'a str) -> IResult<&'a str, &'a str
     move |input: &'a str| preceded(multispace0, tag(t))(input)
 }
 
+pub fn parse_keyword<'a>(t: &'a str) -> impl Fn(&'a str) -> IResult<&'a str, &'a str> {
+    move |input: &'a str| {
+        terminated(
+            parse_tag(t),
+            not(satisfy(|c: char| c.is_alphanumeric() || c == '_')),
+        )(input)
+    }
+}
+
 pub fn parse_identifier(input: &str) -> IResult<&str, Expression> {
-    let (input, (_, x)) = preceded(multispace0, tuple((peek(alpha1), alphanumeric1)))(input)?;
+    let (input, (_, x)) = preceded(
+        multispace0,
+        tuple((
+            peek(alt((alpha1, tag("_")))),
+            take_while1(|c: char| c.is_alphanumeric() || c == '_'),
+        )),
+    )(input)?;
     Ok((input, Expression::Identifier(String::from(x))))
 }
 
 pub fn parse_value(input: &str) -> IResult<&str, Expression> {
-    alt((parse_string, parse_boolean, parse_math_expr))(input)
+    parse_math_expr(input)
 }
 
 pub fn parse_iterator(input: &str) -> IResult<&str, Expression> {
     parse_list(input)
 }
 
+pub fn parse_string(input: &str) -> IResult<&str, Expression> {
+    let (input, x) = preceded(
+        multispace0,
+        delimited(tag("\""), take_until("\""), tag("\"")),
+    )(input)?;
+    Ok((input, Expression::String(String::from(x))))
+}
+
 pub fn parse_call(input: &str) -> IResult<&str, Expression> {
     let (input, (x, y)) = tuple((
-        parse_identifier,
+        parse_name,
         delimited(
             parse_tag(Token::LEFT_PAREN),
-            separated_list0(
-                parse_tag(Token::COMMA),
-                alt((
-                    parse_string,
-                    parse_number,
-                    parse_boolean,
-                    parse_call,
-                    parse_identifier,
-                    parse_iterator,
-                )),
-            ),
+            separated_list0(parse_tag(Token::COMMA), parse_value),
             parse_tag(Token::RIGHT_PAREN),
         ),
     ))(input)?;
@@ -57,49 +71,105 @@ pub fn parse_call(input: &str) -> IResult<&str, Expression> {
     Ok((input, Expression::Call(x.boxed(), y)))
 }
 
+pub fn parse_await(input: &str) -> IResult<&str, Expression> {
+    let (input, x) = preceded(parse_keyword(Token::AWAIT), parse_operation)(input)?;
+    Ok((input, Expression::Await(x.boxed())))
+}
+
 // ****************
 // helper functions
 // ****************
 
-fn parse_string(input: &str) -> IResult<&str, Expression> {
-    let (input, x) = preceded(
-        multispace0,
-        delimited(tag("\""), take_until("\""), tag("\"")),
-    )(input)?;
-    Ok((input, Expression::String(String::from(x))))
+/// A name, qualified with the module it comes from as in `u.sum`.
+fn parse_name(input: &str) -> IResult<&str, Expression> {
+    let (input, x) = parse_identifier(input)?;
+    let (input, y) = opt(preceded(parse_tag(Token::DOT), parse_identifier))(input)?;
+
+    match (x, y) {
+        (Expression::Identifier(x), Some(Expression::Identifier(y))) => Ok((
+            input,
+            Expression::Identifier(format!("{}{}{}", x, Token::DOT, y)),
+        )),
+        (x, _) => Ok((input, x)),
+    }
 }
 
 fn parse_number(input: &str) -> IResult<&str, Expression> {
-    let (input, x) = preceded(multispace0, double)(input)?;
+    let (input, x) = preceded(
+        tuple((
+            multispace0,
+            peek(satisfy(|c| c.is_ascii_digit() || c == '.' || c == '-')),
+        )),
+        double,
+    )(input)?;
     Ok((input, Expression::Number(x)))
 }
 
 fn parse_boolean(input: &str) -> IResult<&str, Expression> {
-    let (input, x) = preceded(multispace0, alt((tag("true"), tag("false"))))(input)?;
+    let (input, x) = alt((parse_keyword("true"), parse_keyword("false")))(input)?;
     Ok((input, Expression::Boolean(x.parse().unwrap())))
 }
 
+fn parse_nil(input: &str) -> IResult<&str, Expression> {
+    let (input, _) = parse_keyword(Token::NIL)(input)?;
+    Ok((input, Expression::Nil))
+}
+
 fn parse_list(input: &str) -> IResult<&str, Expression> {
     let (input, x) = delimited(
         parse_tag(Token::LEFT_BRACKET),
+        separated_list0(parse_tag(Token::COMMA), parse_value),
+        parse_tag(Token::RIGHT_BRACKET),
+    )(input)?;
+    Ok((input, Expression::List(x)))
+}
+
+fn parse_map_key(input: &str) -> IResult<&str, Expression> {
+    match parse_identifier(input)? {
+        (input, Expression::Identifier(x)) => Ok((input, Expression::String(x))),
+        (input, x) => Ok((input, x)),
+    }
+}
+
+fn parse_map(input: &str) -> IResult<&str, Expression> {
+    let (input, x) = delimited(
+        parse_tag(Token::LEFT_BRACE),
         separated_list0(
             parse_tag(Token::COMMA),
-            alt((
-                parse_string,
-                parse_number,
-                parse_boolean,
-                parse_call,
-                parse_identifier,
-                parse_list,
-            )),
+            separated_pair(
+                alt((parse_string, parse_number, parse_boolean, parse_map_key)),
+                parse_tag(Token::COLON),
+                parse_value,
+            ),
         ),
-        parse_tag(Token::RIGHT_BRACKET),
+        parse_tag(Token::RIGHT_BRACE),
     )(input)?;
-    Ok((input, Expression::List(x)))
+    Ok((input, Expression::Map(x)))
 }
 
 fn parse_raw_value(input: &str) -> IResult<&str, Expression> {
-    alt((parse_number, parse_call, parse_identifier))(input)
+    alt((
+        parse_await,
+        parse_string,
+        parse_number,
+        parse_boolean,
+        parse_nil,
+        parse_list,
+        parse_map,
+        parse_call,
+        parse_identifier,
+    ))(input)
+}
+
+fn parse_index(input: &str) -> IResult<&str, Expression> {
+    alt((
+        preceded(parse_tag(Token::DOT), parse_map_key),
+        delimited(
+            parse_tag(Token::LEFT_BRACKET),
+            parse_value,
+            parse_tag(Token::RIGHT_BRACKET),
+        ),
+    ))(input)
 }
 
 fn parse_parens(input: &str) -> IResult<&str, Expression> {
@@ -111,7 +181,14 @@ fn parse_parens(input: &str) -> IResult<&str, Expression> {
 }
 
 fn parse_operation(input: &str) -> IResult<&str, Expression> {
-    alt((parse_parens, parse_raw_value))(input)
+    let (input, x) = alt((parse_parens, parse_raw_value))(input)?;
+    let (input, indexes) = many0(parse_index)(input)?;
+    Ok((
+        input,
+        indexes
+            .into_iter()
+            .fold(x, |acc, i| Expression::Index(acc.boxed(), i.boxed())),
+    ))
 }
 
 fn parse_assignment(input: &str) -> IResult<&str, Expression> {
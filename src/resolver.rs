@@ -0,0 +1,508 @@
+//! Binds every variable to a `(scope depth, slot)` pair before a program runs,
+//! so the engines index into scopes instead of looking names up, and reports
+//! reads before a definition and assignments to undeclared names up front.
+//!
+//! Depth 0 is the body of the enclosing function (or the top level) and each
+//! loop opens one more scope; `if` blocks share the scope around them. A `let`
+//! of a name that is already visible rebinds that variable. Functions see only
+//! their parameters and their own variables.
+
+use std::collections::HashSet;
+
+use crate::interpreter::{constant, globals, RuntimeError};
+use crate::parser::ast::{Block, Expression, Statement};
+
+struct Resolver<'a> {
+    /// Every function name declared anywhere in the program.
+    functions: &'a HashSet<String>,
+    builtins: &'a HashSet<String>,
+    /// Variable names by depth; a name's position is its slot.
+    scopes: Vec<Vec<String>>,
+    /// Every name the current function binds somewhere.
+    declared: HashSet<String>,
+    /// Names bound so far, in or out of scope.
+    seen: HashSet<String>,
+}
+
+/// Collects the function names declared anywhere in a block.
+fn function_names(input: &Block, out: &mut HashSet<String>) {
+    for statement in input {
+        match statement.inner() {
+            Statement::Fn { name, body, .. } => {
+                if let Expression::Identifier(t) = name {
+                    out.insert(t.to_string());
+                }
+                function_names(body, out);
+            }
+            Statement::If {
+                then, otherwise, ..
+            } => {
+                function_names(then, out);
+                if let Some(o) = otherwise {
+                    function_names(o, out);
+                }
+            }
+            Statement::Loop { then, .. } => function_names(then, out),
+            // The body of an `async fn`.
+            Statement::Return {
+                value: Some(Expression::Async(function, _)),
+            } => {
+                if let Statement::Fn { body, .. } = &**function {
+                    function_names(body, out);
+                }
+            }
+            Statement::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                function_names(body, out);
+                if let Some((_, c)) = catch {
+                    function_names(c, out);
+                }
+                if let Some(f) = finally {
+                    function_names(f, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Collects the variable names a function body binds, outside nested functions.
+pub fn declared_names(input: &Block, out: &mut HashSet<String>) {
+    for statement in input {
+        match statement.inner() {
+            Statement::Let {
+                name: Expression::Identifier(t),
+                ..
+            } => {
+                out.insert(t.to_string());
+            }
+            Statement::If {
+                then, otherwise, ..
+            } => {
+                declared_names(then, out);
+                if let Some(o) = otherwise {
+                    declared_names(o, out);
+                }
+            }
+            Statement::Loop { iterable, then, .. } => {
+                if let Some(Expression::Identifier(t)) = iterable {
+                    out.insert(t.to_string());
+                }
+                declared_names(then, out);
+            }
+            Statement::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                declared_names(body, out);
+                if let Some((Expression::Identifier(t), c)) = catch {
+                    out.insert(t.to_string());
+                    declared_names(c, out);
+                }
+                if let Some(f) = finally {
+                    declared_names(f, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn identifier(input: Expression) -> Result<String, RuntimeError> {
+    match input {
+        Expression::Identifier(t) => Ok(t),
+        t => Err(RuntimeError::Type(format!(
+            "expected identifier, found {:?}",
+            t
+        ))),
+    }
+}
+
+impl<'a> Resolver<'a> {
+    fn new(
+        functions: &'a HashSet<String>,
+        builtins: &'a HashSet<String>,
+        params: &[Expression],
+        body: &Block,
+    ) -> Result<Self, RuntimeError> {
+        let mut resolver = Self {
+            functions,
+            builtins,
+            scopes: vec![Vec::new()],
+            declared: HashSet::new(),
+            seen: HashSet::new(),
+        };
+
+        declared_names(body, &mut resolver.declared);
+        for p in params {
+            let name = identifier(p.clone())?;
+            // Parameters always get their own slot, in order.
+            resolver.scopes[0].push(name.clone());
+            resolver.seen.insert(name.clone());
+            resolver.declared.insert(name);
+        }
+        Ok(resolver)
+    }
+
+    fn lookup(&self, name: &str) -> Option<(usize, usize)> {
+        self.scopes
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(depth, scope)| scope.iter().rposition(|n| n == name).map(|s| (depth, s)))
+    }
+
+    /// Binds a name in the innermost scope unless it is already visible.
+    fn declare(&mut self, name: String) -> Expression {
+        let (depth, slot) = match self.lookup(&name) {
+            Some(found) => found,
+            None => {
+                let depth = self.scopes.len() - 1;
+                let scope = &mut self.scopes[depth];
+                scope.push(name.clone());
+                (depth, scope.len() - 1)
+            }
+        };
+
+        self.seen.insert(name.clone());
+        Expression::Local(name, depth, slot)
+    }
+
+    fn block(&mut self, input: Block) -> Result<Block, RuntimeError> {
+        input.into_iter().map(|s| self.statement(s)).collect()
+    }
+
+    fn statement(&mut self, input: Statement) -> Result<Statement, RuntimeError> {
+        Ok(match input {
+            Statement::At(span, t) => {
+                Statement::At(span, self.statement(*t).map_err(|e| e.at(span))?.boxed())
+            }
+
+            Statement::Let { name, initial } => {
+                let initial = self.expression(initial)?;
+                let name = self.declare(identifier(name)?);
+                Statement::Let { name, initial }
+            }
+
+            Statement::Fn { name, params, body } => {
+                let mut inner = Resolver::new(self.functions, self.builtins, &params, &body)?;
+                let body = inner.block(body)?;
+                Statement::Fn { name, params, body }
+            }
+
+            Statement::If {
+                condition,
+                then,
+                otherwise,
+            } => Statement::If {
+                condition: self.expression(condition)?,
+                then: self.block(then)?,
+                otherwise: otherwise.map(|o| self.block(o)).transpose()?,
+            },
+
+            Statement::Loop {
+                iterable,
+                value,
+                then,
+            } => {
+                let value = value.map(|v| self.expression(v)).transpose()?;
+
+                self.scopes.push(Vec::new());
+                let iterable = iterable
+                    .map(|i| identifier(i).map(|name| self.declare(name)))
+                    .transpose();
+                let then = iterable.clone().and_then(|_| self.block(then));
+                self.scopes.pop();
+
+                Statement::Loop {
+                    iterable: iterable?,
+                    value,
+                    then: then?,
+                }
+            }
+
+            Statement::Return { value } => Statement::Return {
+                value: value.map(|v| self.expression(v)).transpose()?,
+            },
+
+            Statement::Expr { expression } => Statement::Expr {
+                expression: self.expression(expression)?,
+            },
+
+            Statement::Break => Statement::Break,
+
+            Statement::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                let body = self.block(body);
+
+                let catch = catch
+                    .map(|(name, c)| {
+                        let name = self.declare(identifier(name)?);
+                        Ok::<_, RuntimeError>((name, self.block(c)?))
+                    })
+                    .transpose()?;
+
+                Statement::Try {
+                    body: body?,
+                    catch,
+                    finally: finally.map(|f| self.block(f)).transpose()?,
+                }
+            }
+
+            Statement::Throw { value } => Statement::Throw {
+                value: self.expression(value)?,
+            },
+
+            Statement::Import { .. } | Statement::Export(_) => {
+                return Err(RuntimeError::Import(String::from(
+                    "imports and exports must be linked before the program runs",
+                )))
+            }
+        })
+    }
+
+    fn expression(&mut self, input: Expression) -> Result<Expression, RuntimeError> {
+        Ok(match input {
+            Expression::Identifier(t) => match self.lookup(&t) {
+                Some((depth, slot)) => Expression::Local(t, depth, slot),
+                None if self.functions.contains(&t) => Expression::Identifier(t),
+                None => match constant(&t) {
+                    Some(value) => value,
+                    None if self.declared.contains(&t) && !self.seen.contains(&t) => {
+                        return Err(RuntimeError::UseBeforeDefinition(t))
+                    }
+                    None => return Err(RuntimeError::UndefinedVariable(t)),
+                },
+            },
+
+            Expression::Assign(name, value) => {
+                let value = self.expression(*value)?;
+                let name = identifier(*name)?;
+
+                match self.lookup(&name) {
+                    Some((depth, slot)) => Expression::Assign(
+                        Expression::Local(name, depth, slot).boxed(),
+                        value.boxed(),
+                    ),
+                    None => return Err(RuntimeError::UndeclaredAssignment(name)),
+                }
+            }
+
+            Expression::Call(name, params) => {
+                let name = match *name {
+                    Expression::Identifier(t) if !self.builtins.contains(&t) => {
+                        match self.lookup(&t) {
+                            Some((depth, slot)) => Expression::Local(t, depth, slot),
+                            None => Expression::Identifier(t),
+                        }
+                    }
+                    t => t,
+                };
+
+                let params = params
+                    .into_iter()
+                    .map(|p| self.expression(p))
+                    .collect::<Result<_, _>>()?;
+                Expression::Call(name.boxed(), params)
+            }
+
+            Expression::Infix(left, op, right) => Expression::Infix(
+                self.expression(*left)?.boxed(),
+                op,
+                self.expression(*right)?.boxed(),
+            ),
+
+            Expression::List(items) => Expression::List(
+                items
+                    .into_iter()
+                    .map(|i| self.expression(i))
+                    .collect::<Result<_, _>>()?,
+            ),
+
+            Expression::Map(entries) => Expression::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| Ok((self.expression(k)?, self.expression(v)?)))
+                    .collect::<Result<_, RuntimeError>>()?,
+            ),
+
+            Expression::Index(value, index) => Expression::Index(
+                self.expression(*value)?.boxed(),
+                self.expression(*index)?.boxed(),
+            ),
+
+            Expression::Async(function, args) => Expression::Async(
+                self.statement(*function)?.boxed(),
+                args.into_iter()
+                    .map(|a| self.expression(a))
+                    .collect::<Result<_, _>>()?,
+            ),
+
+            Expression::Await(value) => Expression::Await(self.expression(*value)?.boxed()),
+
+            t => t,
+        })
+    }
+}
+
+/// Resolves a parsed program for either engine.
+pub fn resolve(input: Block) -> Result<Block, RuntimeError> {
+    let mut functions = HashSet::new();
+    function_names(&input, &mut functions);
+
+    let builtins: HashSet<String> = globals().into_keys().collect();
+
+    let mut resolver = Resolver::new(&functions, &builtins, &[], &input)?;
+    resolver.block(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ast::Span;
+
+    fn resolve_source(source: &str) -> Result<Block, RuntimeError> {
+        let (_, input) = crate::parser::parse(source).unwrap();
+        resolve(input)
+    }
+
+    fn local(name: &str, depth: usize, slot: usize) -> Expression {
+        Expression::Local(String::from(name), depth, slot)
+    }
+
+    #[test]
+    fn test1() {
+        let out = resolve_source(
+            "let a = 1
+            loop i : [a] {
+                let b = i
+                a = b
+            }",
+        )
+        .unwrap();
+
+        assert_eq!(
+            *out[1].inner(),
+            Statement::Loop {
+                iterable: Some(local("i", 1, 0)),
+                value: Some(Expression::List(vec![local("a", 0, 0)])),
+                then: vec![
+                    Statement::At(
+                        Span {
+                            file: 0,
+                            line: 3,
+                            column: 17
+                        },
+                        Statement::Let {
+                            name: local("b", 1, 1),
+                            initial: local("i", 1, 0),
+                        }
+                        .boxed()
+                    ),
+                    Statement::At(
+                        Span {
+                            file: 0,
+                            line: 4,
+                            column: 17
+                        },
+                        Statement::Expr {
+                            expression: Expression::Assign(
+                                local("a", 0, 0).boxed(),
+                                local("b", 1, 1).boxed()
+                            ),
+                        }
+                        .boxed()
+                    ),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test2() {
+        let out = resolve_source(
+            "fn f(x, y) { let z = x\nreturn g(z) }
+            fn g(x) { return x }
+            let h = f
+            h(1, 2)",
+        )
+        .unwrap();
+
+        match out[0].inner() {
+            Statement::Fn { body, .. } => assert_eq!(
+                *body[1].inner(),
+                Statement::Return {
+                    value: Some(Expression::Call(
+                        Expression::Identifier(String::from("g")).boxed(),
+                        vec![local("z", 0, 2)]
+                    )),
+                }
+            ),
+            t => panic!("{:?}", t),
+        }
+        assert_eq!(
+            *out[3].inner(),
+            Statement::Expr {
+                expression: Expression::Call(
+                    local("h", 0, 0).boxed(),
+                    vec![Expression::Number(1.0), Expression::Number(2.0)]
+                ),
+            }
+        );
+    }
+
+    #[test]
+    fn test3() {
+        assert_eq!(
+            resolve_source("print(x)\nlet x = 1").map_err(|e| e.cause().clone()),
+            Err(RuntimeError::UseBeforeDefinition(String::from("x")))
+        );
+        assert_eq!(
+            resolve_source("let x = 1\ny = x").map_err(|e| e.cause().clone()),
+            Err(RuntimeError::UndeclaredAssignment(String::from("y")))
+        );
+        assert_eq!(
+            resolve_source("loop i : [1] { let t = i }\nprint(t)").map_err(|e| e.cause().clone()),
+            Err(RuntimeError::UndefinedVariable(String::from("t")))
+        );
+        assert_eq!(
+            resolve_source("let outer = 1\nfn f() { return outer }").map_err(|e| e.cause().clone()),
+            Err(RuntimeError::UndefinedVariable(String::from("outer")))
+        );
+        assert!(resolve_source("let x = PI\nif x > 3 { let y = x }\nprint(y)").is_ok());
+    }
+
+    #[test]
+    fn test4() {
+        let at = |source| {
+            let span = resolve_source(source).unwrap_err().span().unwrap();
+            (span.line, span.column)
+        };
+
+        assert_eq!(at("let a = 1\nprint(x)\nlet x = 1"), (2, 1));
+        assert_eq!(at("let x = 1\nif x > 0 {\n  y = x\n}"), (3, 3));
+        assert_eq!(at("fn f() {\n  return outer\n}"), (2, 3));
+    }
+
+    #[test]
+    fn test5() {
+        // A `try` body is checked like any other code.
+        assert_eq!(
+            resolve_source("try { print(x) } catch e { }").map_err(|e| e.cause().clone()),
+            Err(RuntimeError::UndefinedVariable(String::from("x")))
+        );
+        assert_eq!(
+            resolve_source("try { print(x)\nlet x = 1 } catch e { }")
+                .map_err(|e| e.cause().clone()),
+            Err(RuntimeError::UseBeforeDefinition(String::from("x")))
+        );
+        assert!(resolve_source("fn f() { return 1 }\ntry { print(f, PI) } catch e { }").is_ok());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::interpreter::{RuntimeError, Scheduler};
+use crate::parser::ast::Expression;
+use crate::value::Channel;
+
+fn end(name: &str, value: &Expression, sender: bool) -> Result<Channel, RuntimeError> {
+    match value {
+        Expression::Channel(c) if c.is_sender() == sender => Ok(c.clone()),
+        t => Err(RuntimeError::Type(format!(
+            "{} expects a {}, found {:?}",
+            name,
+            if sender { "sender" } else { "receiver" },
+            t
+        ))),
+    }
+}
+
+fn closed() -> RuntimeError {
+    RuntimeError::Type(String::from("channel is closed"))
+}
+
+/// A `[sender, receiver]` pair, unbounded unless a capacity is given.
+pub fn run_channel(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let capacity = match input.as_slice() {
+        [] => None,
+        [Expression::Number(n)] if n.fract() == 0.0 && *n >= 1.0 => Some(*n as usize),
+        _ => {
+            return Err(RuntimeError::Type(String::from(
+                "channel expects an optional capacity of at least 1",
+            )))
+        }
+    };
+
+    let (sender, receiver) = Channel::pair(capacity);
+
+    Ok(Expression::List(vec![
+        Expression::Channel(sender),
+        Expression::Channel(receiver),
+    ]))
+}
+
+/// Queues a value, waiting for room when the channel is full.
+pub fn run_send(scheduler: &Scheduler, input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let (channel, value) = match input.as_slice() {
+        [channel, value] => (end("send", channel, true)?, value),
+        _ => {
+            return Err(RuntimeError::Type(String::from(
+                "send expects a sender and a value",
+            )))
+        }
+    };
+
+    scheduler.wait(|| {
+        let mut queue = channel.queue();
+        if queue.closed {
+            Some(Err(closed()))
+        } else if queue.capacity.is_some_and(|c| queue.items.len() >= c) {
+            None
+        } else {
+            queue.items.push_back(value.clone());
+            Some(Ok(Expression::Nil))
+        }
+    })?
+}
+
+/// Waits for the next value; `nil` once the channel is closed and empty.
+pub fn run_recv(scheduler: &Scheduler, input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [channel] => {
+            let channel = end("recv", channel, false)?;
+            scheduler.wait(|| channel.take())
+        }
+        _ => Err(RuntimeError::Type(String::from("recv expects a receiver"))),
+    }
+}
+
+/// The next value if one is waiting, `nil` otherwise.
+pub fn run_try_recv(
+    scheduler: &Scheduler,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [channel] => {
+            let channel = end("try_recv", channel, false)?;
+            Ok(scheduler
+                .poll(|| channel.queue().items.pop_front())
+                .unwrap_or(Expression::Nil))
+        }
+        _ => Err(RuntimeError::Type(String::from(
+            "try_recv expects a receiver",
+        ))),
+    }
+}
+
+pub fn run_close(
+    scheduler: &Scheduler,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let channel = match input.as_slice() {
+        [channel] => end("close", channel, true)?,
+        _ => return Err(RuntimeError::Type(String::from("close expects a sender"))),
+    };
+
+    scheduler
+        .poll(|| {
+            let mut queue = channel.queue();
+            (!queue.closed).then(|| queue.closed = true)
+        })
+        .map(|_| Expression::Nil)
+        .ok_or_else(closed)
+}
+
+/// Waits on several receivers at once and returns `[index, value]` for the
+/// first one, in list order, with a value waiting. Closed receivers are
+/// skipped, and once all of them are closed and drained the result is `nil`.
+pub fn run_select(
+    scheduler: &Scheduler,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let channels = match input.as_slice() {
+        [Expression::List(items)] if !items.is_empty() => items
+            .iter()
+            .map(|c| end("select", c, false))
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => {
+            return Err(RuntimeError::Type(String::from(
+                "select expects a non-empty list of receivers",
+            )))
+        }
+    };
+
+    scheduler.wait(|| {
+        let mut open = false;
+
+        for (i, channel) in channels.iter().enumerate() {
+            let mut queue = channel.queue();
+            if let Some(value) = queue.items.pop_front() {
+                return Some(Expression::List(vec![Expression::Number(i as f64), value]));
+            }
+            open |= !queue.closed;
+        }
+        (!open).then_some(Expression::Nil)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(input: Vec<Expression>) -> (Expression, Expression) {
+        match run_channel(input) {
+            Ok(Expression::List(ends)) => (ends[0].clone(), ends[1].clone()),
+            t => panic!("{:?}", t),
+        }
+    }
+
+    #[test]
+    fn test1() {
+        let scheduler = Scheduler::default();
+        let (tx, rx) = pair(vec![]);
+        let n = Expression::Number;
+
+        run_send(&scheduler, vec![tx.clone(), n(1.0)]).unwrap();
+        run_send(&scheduler, vec![tx.clone(), n(2.0)]).unwrap();
+        assert_eq!(run_recv(&scheduler, vec![rx.clone()]), Ok(n(1.0)));
+        assert_eq!(run_try_recv(&scheduler, vec![rx.clone()]), Ok(n(2.0)));
+        assert_eq!(
+            run_try_recv(&scheduler, vec![rx.clone()]),
+            Ok(Expression::Nil)
+        );
+
+        assert_eq!(
+            run_recv(&scheduler, vec![rx.clone()]),
+            Err(RuntimeError::Deadlock)
+        );
+
+        run_close(&scheduler, vec![tx.clone()]).unwrap();
+        assert!(run_close(&scheduler, vec![tx.clone()]).is_err());
+        assert!(run_send(&scheduler, vec![tx.clone(), n(3.0)]).is_err());
+        assert_eq!(run_recv(&scheduler, vec![rx.clone()]), Ok(Expression::Nil));
+        assert!(run_recv(&scheduler, vec![tx]).is_err());
+    }
+
+    #[test]
+    fn test2() {
+        let scheduler = Scheduler::default();
+        let (tx1, rx1) = pair(vec![Expression::Number(1.0)]);
+        let (tx2, rx2) = pair(vec![]);
+
+        run_send(&scheduler, vec![tx1.clone(), Expression::Boolean(true)]).unwrap();
+        assert_eq!(
+            run_send(&scheduler, vec![tx1, Expression::Nil]),
+            Err(RuntimeError::Deadlock)
+        );
+
+        assert_eq!(
+            run_select(&scheduler, vec![Expression::List(vec![rx2.clone(), rx1])]),
+            Ok(Expression::List(vec![
+                Expression::Number(1.0),
+                Expression::Boolean(true)
+            ]))
+        );
+        run_close(&scheduler, vec![tx2]).unwrap();
+        assert_eq!(
+            run_select(&scheduler, vec![Expression::List(vec![rx2])]),
+            Ok(Expression::Nil)
+        );
+        assert!(run_channel(vec![Expression::Number(0.0)]).is_err());
+    }
+}
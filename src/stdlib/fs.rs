@@ -0,0 +1,326 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::interpreter::{Permissions, RuntimeError};
+use crate::parser::ast::Expression;
+use crate::value::Lines;
+
+use super::function::get_print;
+
+fn io(path: &str) -> impl Fn(std::io::Error) -> RuntimeError + '_ {
+    move |e| RuntimeError::Io(format!("{}: {}", path, e))
+}
+
+fn path<'a>(name: &str, input: &'a [Expression]) -> Result<&'a str, RuntimeError> {
+    match input.first() {
+        Some(Expression::String(path)) => Ok(path),
+        t => Err(RuntimeError::Type(format!(
+            "{} expects a path, found {:?}",
+            name, t
+        ))),
+    }
+}
+
+fn paths<'a>(name: &str, input: &'a [Expression]) -> Result<(&'a str, &'a str), RuntimeError> {
+    match input {
+        [Expression::String(from), Expression::String(to)] => Ok((from, to)),
+        _ => Err(RuntimeError::Type(format!("{} expects two paths", name))),
+    }
+}
+
+fn contents(name: &str, input: &[Expression]) -> Result<String, RuntimeError> {
+    match input.get(1) {
+        Some(t) => get_print(t.clone()),
+        None => Err(RuntimeError::Type(format!("{} expects contents", name))),
+    }
+}
+
+pub fn run_read_file(
+    permissions: &Permissions,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let path = path("read_file", &input)?;
+    permissions.check_read(path)?;
+
+    Ok(Expression::String(
+        fs::read_to_string(path).map_err(io(path))?,
+    ))
+}
+
+pub fn run_write_file(
+    permissions: &Permissions,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let path = path("write_file", &input)?;
+    let data = contents("write_file", &input)?;
+    permissions.check_write(path)?;
+
+    fs::write(path, data).map_err(io(path))?;
+    Ok(Expression::Nil)
+}
+
+pub fn run_append_file(
+    permissions: &Permissions,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let path = path("append_file", &input)?;
+    let data = contents("append_file", &input)?;
+    permissions.check_write(path)?;
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut f| f.write_all(data.as_bytes()))
+        .map_err(io(path))?;
+    Ok(Expression::Nil)
+}
+
+/// Opens a file for `loop line : read_lines(path) { }` without reading it all.
+pub fn run_read_lines(
+    permissions: &Permissions,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let path = path("read_lines", &input)?;
+    permissions.check_read(path)?;
+
+    let file = File::open(path).map_err(io(path))?;
+    Ok(Expression::Lines(Lines::new(BufReader::new(file))))
+}
+
+pub fn run_list_dir(
+    permissions: &Permissions,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let path = path("list_dir", &input)?;
+    permissions.check_read(path)?;
+
+    let mut names = Vec::new();
+    for entry in fs::read_dir(path).map_err(io(path))? {
+        let entry = entry.map_err(io(path))?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+
+    Ok(Expression::List(
+        names.into_iter().map(Expression::String).collect(),
+    ))
+}
+
+pub fn run_exists(
+    permissions: &Permissions,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let path = path("exists", &input)?;
+    permissions.check_read(path)?;
+
+    Ok(Expression::Boolean(Path::new(path).exists()))
+}
+
+pub fn run_stat(
+    permissions: &Permissions,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let path = path("stat", &input)?;
+    permissions.check_read(path)?;
+
+    let meta = fs::metadata(path).map_err(io(path))?;
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(
+            Expression::Nil,
+            |t| Expression::Number(t.as_millis() as f64),
+        );
+
+    Ok(Expression::map(vec![
+        ("size", Expression::Number(meta.len() as f64)),
+        ("is_file", Expression::Boolean(meta.is_file())),
+        ("is_dir", Expression::Boolean(meta.is_dir())),
+        (
+            "readonly",
+            Expression::Boolean(meta.permissions().readonly()),
+        ),
+        ("modified", modified),
+    ]))
+}
+
+pub fn run_mkdir(
+    permissions: &Permissions,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let path = path("mkdir", &input)?;
+    permissions.check_write(path)?;
+
+    fs::create_dir_all(path).map_err(io(path))?;
+    Ok(Expression::Nil)
+}
+
+/// Removes a file or an empty directory, or any directory with `{ recursive: true }`.
+pub fn run_remove(
+    permissions: &Permissions,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let path = path("remove", &input)?;
+    permissions.check_write(path)?;
+
+    let recursive = match input.get(1) {
+        Some(options) => matches!(options.get("recursive"), Some(Expression::Boolean(true))),
+        None => false,
+    };
+
+    let meta = fs::symlink_metadata(path).map_err(io(path))?;
+    if !meta.is_dir() {
+        fs::remove_file(path)
+    } else if recursive {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_dir(path)
+    }
+    .map_err(io(path))?;
+    Ok(Expression::Nil)
+}
+
+pub fn run_copy(
+    permissions: &Permissions,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let (from, to) = paths("copy", &input)?;
+    permissions.check_read(from)?;
+    permissions.check_write(to)?;
+
+    fs::copy(from, to).map_err(io(from))?;
+    Ok(Expression::Nil)
+}
+
+pub fn run_rename(
+    permissions: &Permissions,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let (from, to) = paths("rename", &input)?;
+    permissions.check_write(from)?;
+    permissions.check_write(to)?;
+
+    fs::rename(from, to).map_err(io(from))?;
+    Ok(Expression::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn string(s: &str) -> Expression {
+        Expression::String(String::from(s))
+    }
+
+    fn temp(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("plang-fs-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test1() {
+        let p = Permissions::default();
+        let dir = temp("test1");
+        let file = format!("{}/a.txt", dir);
+
+        run_mkdir(&p, vec![string(&dir)]).unwrap();
+        run_write_file(&p, vec![string(&file), string("one\n")]).unwrap();
+        run_append_file(&p, vec![string(&file), string("two\r\nthree")]).unwrap();
+
+        assert_eq!(
+            run_read_file(&p, vec![string(&file)]),
+            Ok(string("one\ntwo\r\nthree"))
+        );
+
+        let lines = match run_read_lines(&p, vec![string(&file)]).unwrap() {
+            Expression::Lines(lines) => lines,
+            t => panic!("{:?}", t),
+        };
+        assert_eq!(lines.next_line().unwrap(), Some(String::from("one")));
+        assert_eq!(lines.next_line().unwrap(), Some(String::from("two")));
+        assert_eq!(lines.next_line().unwrap(), Some(String::from("three")));
+        assert_eq!(lines.next_line().unwrap(), None);
+
+        let stat = run_stat(&p, vec![string(&file)]).unwrap();
+        assert_eq!(stat.get("size"), Some(&Expression::Number(14.0)));
+        assert_eq!(stat.get("is_file"), Some(&Expression::Boolean(true)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test2() {
+        let p = Permissions::default();
+        let dir = temp("test2");
+        let a = format!("{}/a", dir);
+        let b = format!("{}/b", dir);
+        let c = format!("{}/sub/c", dir);
+
+        run_mkdir(&p, vec![string(&format!("{}/sub", dir))]).unwrap();
+        run_write_file(&p, vec![string(&a), Expression::Number(1.0)]).unwrap();
+        run_copy(&p, vec![string(&a), string(&b)]).unwrap();
+        run_rename(&p, vec![string(&b), string(&c)]).unwrap();
+
+        assert_eq!(
+            run_list_dir(&p, vec![string(&dir)]),
+            Ok(Expression::List(vec![string("a"), string("sub")]))
+        );
+        assert_eq!(run_read_file(&p, vec![string(&c)]), Ok(string("1")));
+        assert_eq!(
+            run_exists(&p, vec![string(&b)]),
+            Ok(Expression::Boolean(false))
+        );
+
+        assert!(run_remove(&p, vec![string(&dir)]).is_err());
+        run_remove(
+            &p,
+            vec![
+                string(&dir),
+                Expression::map(vec![("recursive", Expression::Boolean(true))]),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            run_exists(&p, vec![string(&dir)]),
+            Ok(Expression::Boolean(false))
+        );
+    }
+
+    #[test]
+    fn test3() {
+        let p = Permissions::default();
+
+        assert!(matches!(
+            run_read_file(&p, vec![string("/plang/no/such/file")]),
+            Err(RuntimeError::Io(_))
+        ));
+        assert!(matches!(
+            run_read_file(&p, vec![Expression::Number(1.0)]),
+            Err(RuntimeError::Type(_))
+        ));
+        assert!(run_stat(&p, vec![string("/plang/no/such/file")]).is_err());
+    }
+
+    #[test]
+    fn test4() {
+        let dir = temp("test4");
+        let mut p = Permissions::default();
+        p.read.allow(&dir);
+        p.write.deny(&dir);
+
+        assert!(matches!(
+            run_write_file(&p, vec![string(&format!("{}/x", dir)), string("")]),
+            Err(RuntimeError::Permission(_))
+        ));
+        assert!(matches!(
+            run_read_file(&p, vec![string("/etc/hostname")]),
+            Err(RuntimeError::Permission(_))
+        ));
+    }
+}
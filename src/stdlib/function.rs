@@ -1,48 +1,150 @@
-use std::process::Command;
+use std::env;
 use std::string::String;
 
+use crate::interpreter::{Context, Permissions, RuntimeError};
 use crate::parser::ast::Expression;
 
 pub fn run_print(input: &Expression) {
+    print!("{}", display(input))
+}
+
+/// Formats a value the way `print` shows it.
+pub fn display(input: &Expression) -> String {
     match &input {
-        Expression::Number(e) => print!("{}", e),
-        Expression::String(e) => print!("{}", e),
-        Expression::Boolean(e) => print!("{}", e),
+        Expression::Number(e) => e.to_string(),
+        Expression::String(e) => e.to_string(),
+        Expression::Boolean(e) => e.to_string(),
+        Expression::Nil => String::from("nil"),
         Expression::List(e) => {
-            let len = e.len();
-            print!("[ ");
-            for (i, t) in e.iter().enumerate() {
-                run_print(t);
-                if i + 1 < len {
-                    print!(", ");
-                }
-            }
-            print!(" ]");
+            let items: Vec<String> = e.iter().map(display).collect();
+            format!("[ {} ]", items.join(", "))
+        }
+        Expression::Map(e) => {
+            let entries: Vec<String> = e
+                .iter()
+                .map(|(k, v)| format!("{}: {}", display(k), display(v)))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
         }
-        _ => panic!("Enter proper arguments"),
+        Expression::Function(name, ..) => format!("<fn {}>", name),
+        Expression::Lines(_) => String::from("<lines>"),
+        Expression::Task(_) => String::from("<task>"),
+        Expression::Future(_) => String::from("<future>"),
+        Expression::Channel(c) => format!("<{}>", format!("{:?}", c).to_lowercase()),
+        _ => format!("{:?}", input),
     }
 }
 
-pub fn get_print(input: Expression) -> String {
+pub fn get_print(input: Expression) -> Result<String, RuntimeError> {
     match input {
-        Expression::Number(e) => e.to_string(),
-        Expression::String(e) => e,
-        Expression::Boolean(e) => e.to_string(),
-        _ => panic!("Enter proper arguments"),
+        Expression::Number(e) => Ok(e.to_string()),
+        Expression::String(e) => Ok(e),
+        Expression::Boolean(e) => Ok(e.to_string()),
+        t => Err(RuntimeError::Type(format!(
+            "expected number, string or boolean, found {:?}",
+            t
+        ))),
     }
 }
 
-pub fn run_cmd(input: Vec<Expression>) {
-    let mut t = input.into_iter().map(|i| get_print(i));
+pub fn run_args(context: &Context) -> Expression {
+    Expression::List(
+        context
+            .args
+            .iter()
+            .map(|a| Expression::String(a.to_string()))
+            .collect(),
+    )
+}
+
+pub fn run_env(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let name = match input.as_slice() {
+        [Expression::String(name)] => name,
+        _ => return Err(RuntimeError::Type(String::from("env expects a name"))),
+    };
 
-    let cmd = Command::new(t.next().unwrap()).args(t).spawn();
-    match cmd {
-        Ok(mut c) => {
-            c.wait().expect("loading...");
+    Ok(match env::var(name) {
+        Ok(value) => Expression::String(value),
+        Err(_) => Expression::Nil,
+    })
+}
+
+pub fn run_set_env(
+    permissions: &Permissions,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    if let Some(Expression::String(name)) = input.first() {
+        // The platform rejects these, and std panics rather than fail.
+        if name.is_empty() || name.contains(['=', '\0']) {
+            return Err(RuntimeError::Type(format!(
+                "set_env: invalid variable name {:?}",
+                name
+            )));
         }
-        Err(_e) => println!("command failed!"),
+        permissions.check_env(name)?;
     }
+
+    match input.as_slice() {
+        [Expression::String(name), Expression::Nil] => env::remove_var(name),
+        [Expression::String(name), value] => {
+            let value = get_print(value.clone())?;
+            if value.contains('\0') {
+                return Err(RuntimeError::Type(format!(
+                    "set_env: the value of {} contains a NUL byte",
+                    name
+                )));
+            }
+            env::set_var(name, value)
+        }
+        _ => {
+            return Err(RuntimeError::Type(String::from(
+                "set_env expects a name and a value",
+            )))
+        }
+    }
+    Ok(Expression::Nil)
 }
 
-// TODO
-// pub fn run_http(input: &Expression) {}
+pub fn run_exit(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [] => Err(RuntimeError::Exit(0)),
+        [Expression::Number(code)] if code.fract() == 0.0 => Err(RuntimeError::Exit(*code as i32)),
+        _ => Err(RuntimeError::Type(String::from(
+            "exit expects an integer code",
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Expression {
+        Expression::String(String::from(s))
+    }
+
+    #[test]
+    fn test1() {
+        let permissions = Permissions::default();
+        let set =
+            |name: &str, value: &str| run_set_env(&permissions, vec![string(name), string(value)]);
+
+        assert!(matches!(set("", "x"), Err(RuntimeError::Type(_))));
+        assert!(matches!(set("A=B", "x"), Err(RuntimeError::Type(_))));
+        assert!(matches!(set("A\0B", "x"), Err(RuntimeError::Type(_))));
+        assert!(matches!(
+            set("PLANG_FUNCTION_TEST1", "x\0y"),
+            Err(RuntimeError::Type(_))
+        ));
+        assert!(matches!(
+            run_set_env(&permissions, vec![string("A=B"), Expression::Nil]),
+            Err(RuntimeError::Type(_))
+        ));
+
+        assert_eq!(set("PLANG_FUNCTION_TEST1", "x"), Ok(Expression::Nil));
+        assert_eq!(
+            run_env(vec![string("PLANG_FUNCTION_TEST1")]),
+            Ok(string("x"))
+        );
+    }
+}
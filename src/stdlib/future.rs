@@ -0,0 +1,103 @@
+use super::Runtime;
+use crate::interpreter::RuntimeError;
+use crate::parser::ast::Expression;
+use crate::value::Future;
+
+impl Future {
+    /// Starts the call unless it already has.
+    fn start(&self, runtime: &mut dyn Runtime) {
+        if let Some((f, args)) = self.take_call() {
+            let event_loop = runtime.event_loop();
+            self.task().start(runtime, f, args, Some(event_loop));
+        }
+    }
+}
+
+/// Wraps `f(args...)` in a future without running it, as calling an
+/// `async fn` does.
+pub fn run_async(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let mut input = input.into_iter();
+    match input.next() {
+        Some(f @ Expression::Function(..)) => {
+            Ok(Expression::Future(Future::new(f, input.collect())))
+        }
+        t => Err(RuntimeError::Type(format!(
+            "async expects a function, found {:?}",
+            t
+        ))),
+    }
+}
+
+/// Runs a future to completion and returns its result. Any other value is
+/// returned as it is.
+pub fn run_await(
+    runtime: &mut dyn Runtime,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::Future(future)] => {
+            future.start(runtime);
+            future.task().join(&runtime.scheduler())
+        }
+        [value] => Ok(value.clone()),
+        _ => Err(RuntimeError::Type(String::from("await expects one value"))),
+    }
+}
+
+/// Starts every future in the list, waits for all of them and returns their
+/// results in order. If any fail, the error of the earliest one is reported.
+pub fn run_gather(
+    runtime: &mut dyn Runtime,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let items = match input.as_slice() {
+        [Expression::List(items)] => items,
+        _ => {
+            return Err(RuntimeError::Type(String::from(
+                "gather expects a list of futures",
+            )))
+        }
+    };
+
+    for item in items {
+        if let Expression::Future(future) = item {
+            future.start(runtime);
+        }
+    }
+
+    let out: Vec<_> = items
+        .iter()
+        .map(|item| run_await(runtime, vec![item.clone()]))
+        .collect();
+    Ok(Expression::List(out.into_iter().collect::<Result<_, _>>()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test1() {
+        let mut runtime = crate::stdlib::tests::TestRuntime::default();
+
+        assert!(run_async(vec![Expression::Nil]).is_err());
+        assert!(run_gather(&mut runtime, vec![Expression::Nil]).is_err());
+        assert_eq!(
+            run_await(&mut runtime, vec![Expression::Number(1.0)]),
+            Ok(Expression::Number(1.0))
+        );
+        assert_eq!(
+            run_gather(
+                &mut runtime,
+                vec![Expression::List(vec![
+                    Expression::Nil,
+                    Expression::Number(2.0)
+                ])]
+            ),
+            Ok(Expression::List(vec![
+                Expression::Nil,
+                Expression::Number(2.0)
+            ]))
+        );
+    }
+}
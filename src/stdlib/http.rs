@@ -0,0 +1,803 @@
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use http::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, HOST, LOCATION, TRANSFER_ENCODING,
+};
+use http::{Method, Request, Response, StatusCode, Uri};
+
+use crate::interpreter::{Deadline, Permissions, RuntimeError};
+use crate::parser::ast::Expression;
+
+use super::function::get_print;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_REDIRECTS: usize = 5;
+/// The largest message body either side of a connection will read.
+const MAX_BODY: usize = 64 * 1024 * 1024;
+
+/// Options accepted as a trailing map by the http built-ins.
+#[derive(Debug)]
+struct HttpOptions {
+    timeout: Duration,
+    redirects: usize,
+}
+
+impl HttpOptions {
+    fn parse(input: Option<&Expression>) -> Result<Self, RuntimeError> {
+        let mut options = HttpOptions {
+            timeout: DEFAULT_TIMEOUT,
+            redirects: DEFAULT_REDIRECTS,
+        };
+
+        let input = match input {
+            Some(t @ Expression::Map(_)) => t,
+            Some(Expression::Nil) | None => return Ok(options),
+            Some(t) => return Err(invalid("options", t)),
+        };
+
+        match input.get("timeout") {
+            Some(Expression::Number(ms)) if *ms > 0.0 => {
+                options.timeout = Duration::from_millis(*ms as u64)
+            }
+            Some(Expression::Nil) | None => {}
+            Some(t) => return Err(invalid("timeout", t)),
+        }
+
+        match input.get("redirects") {
+            Some(Expression::Number(n)) if *n >= 0.0 => options.redirects = *n as usize,
+            Some(Expression::Nil) | None => {}
+            Some(t) => return Err(invalid("redirects", t)),
+        }
+
+        Ok(options)
+    }
+}
+
+fn invalid(name: &str, value: &Expression) -> RuntimeError {
+    RuntimeError::Type(format!("invalid http {}: {:?}", name, value))
+}
+
+fn io(e: std::io::Error) -> RuntimeError {
+    RuntimeError::Io(e.to_string())
+}
+
+pub fn headers_to_map(headers: &HeaderMap) -> Expression {
+    let mut entries: Vec<(Expression, Expression)> = Vec::new();
+
+    for (name, value) in headers.iter() {
+        let name = Expression::String(name.as_str().to_string());
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+
+        match entries.iter_mut().find(|(k, _)| *k == name) {
+            Some((_, Expression::String(v))) => {
+                v.push_str(", ");
+                v.push_str(&value);
+            }
+            _ => entries.push((name, Expression::String(value))),
+        }
+    }
+
+    Expression::Map(entries)
+}
+
+pub fn map_to_headers(input: &Expression) -> Result<HeaderMap, RuntimeError> {
+    let mut headers = HeaderMap::new();
+
+    let entries = match input {
+        Expression::Map(entries) => entries,
+        Expression::Nil => return Ok(headers),
+        t => return Err(invalid("headers", t)),
+    };
+
+    for (name, value) in entries {
+        let name = HeaderName::from_bytes(get_print(name.clone())?.as_bytes())
+            .map_err(|_| invalid("header name", name))?;
+        let value = HeaderValue::from_str(&get_print(value.clone())?)
+            .map_err(|_| invalid("header value", value))?;
+        headers.append(name, value);
+    }
+
+    Ok(headers)
+}
+
+fn malformed() -> RuntimeError {
+    RuntimeError::Io(String::from("malformed http message"))
+}
+
+/// Reads a start line, headers and body from an HTTP/1.1 stream.
+///
+/// Without a length or chunked encoding the body runs to the end of the stream
+/// when `until_eof` is set and is empty otherwise.
+pub fn read_message(
+    reader: &mut impl BufRead,
+    until_eof: bool,
+) -> Result<(String, HeaderMap, Vec<u8>), RuntimeError> {
+    let (start, headers) = read_head(reader)?;
+    let body = read_body(reader, &headers, until_eof)?;
+    Ok((start, headers, body))
+}
+
+/// Reads the start line and headers of a message.
+fn read_head(reader: &mut impl BufRead) -> Result<(String, HeaderMap), RuntimeError> {
+    let mut start = String::new();
+    if reader.read_line(&mut start).map_err(io)? == 0 {
+        return Err(malformed());
+    }
+
+    let mut headers = HeaderMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(io)? == 0 {
+            return Err(malformed());
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        let (name, value) = line.split_once(':').ok_or_else(malformed)?;
+        headers.append(
+            HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| malformed())?,
+            HeaderValue::from_str(value.trim()).map_err(|_| malformed())?,
+        );
+    }
+
+    Ok((start.trim_end().to_string(), headers))
+}
+
+/// Reads the body that follows `headers`, as `read_message` describes.
+fn read_body(
+    reader: &mut impl BufRead,
+    headers: &HeaderMap,
+    until_eof: bool,
+) -> Result<Vec<u8>, RuntimeError> {
+    let fits = |length: usize| -> Result<usize, RuntimeError> {
+        match length {
+            n if n <= MAX_BODY => Ok(n),
+            _ => Err(RuntimeError::Io(format!(
+                "http body larger than {} bytes",
+                MAX_BODY
+            ))),
+        }
+    };
+
+    let chunked = headers
+        .get(TRANSFER_ENCODING)
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"chunked"));
+    let length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<usize>().ok());
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).map_err(io)?;
+            let size = size.trim().split(';').next().unwrap_or_default();
+            let size = usize::from_str_radix(size, 16).map_err(|_| malformed())?;
+            fits(body.len().checked_add(size).ok_or_else(malformed)?)?;
+
+            let mut chunk = vec![0; size.checked_add(2).ok_or_else(malformed)?];
+            reader.read_exact(&mut chunk).map_err(io)?;
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    } else if let Some(length) = length {
+        body.resize(fits(length)?, 0);
+        reader.read_exact(&mut body).map_err(io)?;
+    } else if until_eof {
+        reader
+            .take(MAX_BODY as u64 + 1)
+            .read_to_end(&mut body)
+            .map_err(io)?;
+        fits(body.len())?;
+    }
+
+    Ok(body)
+}
+
+/// A connection that fails once `deadline` has passed, however the other
+/// side spaces out what it sends.
+struct Bounded {
+    stream: TcpStream,
+    deadline: Option<Instant>,
+}
+
+impl Bounded {
+    /// Lets the next read or write wait only as long as the time left.
+    fn arm(&self) -> std::io::Result<()> {
+        let left = match self.deadline {
+            Some(t) => t.saturating_duration_since(Instant::now()),
+            None => return Ok(()),
+        };
+        if left.is_zero() {
+            return Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                "http request timed out",
+            ));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.set_write_timeout(Some(left))
+    }
+}
+
+impl Read for Bounded {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.arm()?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Bounded {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.arm()?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Whether a response to `method` with `status` has no body, whatever its
+/// headers say.
+fn bodyless(method: &Method, status: StatusCode) -> bool {
+    method == Method::HEAD
+        || status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+}
+
+fn connect(uri: &Uri, timeout: Duration) -> Result<TcpStream, RuntimeError> {
+    let (host, port) = (uri.host().unwrap_or_default(), uri.port_u16().unwrap_or(80));
+    let mut last = RuntimeError::Io(format!("cannot resolve `{}`", host));
+
+    for address in (host, port).to_socket_addrs().map_err(io)? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout)).map_err(io)?;
+                stream.set_write_timeout(Some(timeout)).map_err(io)?;
+                return Ok(stream);
+            }
+            Err(e) => last = io(e),
+        }
+    }
+
+    Err(last)
+}
+
+/// Sends `request` and reads the response, all within `timeout`.
+fn send(request: &Request<Vec<u8>>, timeout: Duration) -> Result<Response<Vec<u8>>, RuntimeError> {
+    let deadline = Instant::now().checked_add(timeout);
+    let uri = request.uri();
+    let mut stream = Bounded {
+        stream: connect(uri, timeout)?,
+        deadline,
+    };
+
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nConnection: close\r\n",
+        request.method(),
+        uri.path_and_query().map_or("/", |p| p.as_str()),
+    );
+    // Headers the caller set replace the ones made up here.
+    if !request.headers().contains_key(HOST) {
+        head.push_str(&format!(
+            "Host: {}\r\n",
+            uri.authority().map_or("", |a| a.as_str())
+        ));
+    }
+    if !request.headers().contains_key(CONTENT_LENGTH) {
+        head.push_str(&format!("Content-Length: {}\r\n", request.body().len()));
+    }
+    for (name, value) in request.headers() {
+        head.push_str(&format!(
+            "{}: {}\r\n",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        ));
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes()).map_err(io)?;
+    stream.write_all(request.body()).map_err(io)?;
+
+    let mut reader = BufReader::new(stream);
+    let (status, headers) = read_head(&mut reader)?;
+
+    let code = status
+        .split_whitespace()
+        .nth(1)
+        .and_then(|c| StatusCode::from_bytes(c.as_bytes()).ok())
+        .ok_or_else(|| RuntimeError::Io(format!("bad status line `{}`", status)))?;
+
+    let body = if bodyless(request.method(), code) {
+        Vec::new()
+    } else {
+        read_body(&mut reader, &headers, true)?
+    };
+
+    let mut response = Response::new(body);
+    *response.status_mut() = code;
+    *response.headers_mut() = headers;
+    Ok(response)
+}
+
+fn parse_uri(url: &str) -> Result<Uri, RuntimeError> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|_| RuntimeError::Type(format!("invalid url `{}`", url)))?;
+
+    match uri.scheme_str() {
+        Some("http") if uri.host().is_some() => Ok(uri),
+        Some("https") => Err(RuntimeError::Io(String::from("https is not supported"))),
+        _ => Err(RuntimeError::Type(format!("invalid url `{}`", url))),
+    }
+}
+
+/// Resolves a `Location` header against the url that returned it.
+fn redirect_uri(base: &Uri, location: &str) -> Result<Uri, RuntimeError> {
+    if location.parse::<Uri>().is_ok_and(|u| u.scheme().is_some()) {
+        return parse_uri(location);
+    }
+
+    let path = if location.starts_with('/') {
+        location.to_string()
+    } else {
+        let dir = base.path().rsplit_once('/').map_or("", |(d, _)| d);
+        format!("{}/{}", dir, location)
+    };
+
+    parse_uri(&format!(
+        "http://{}{}",
+        base.authority().map_or("", |a| a.as_str()),
+        path
+    ))
+}
+
+fn request(
+    permissions: &Permissions,
+    limit: Option<Deadline>,
+    method: Method,
+    url: &str,
+    mut headers: HeaderMap,
+    body: Vec<u8>,
+    options: HttpOptions,
+) -> Result<Expression, RuntimeError> {
+    let mut uri = parse_uri(url)?;
+    let mut method = method;
+    let mut body = body;
+    let mut redirects = 0;
+
+    loop {
+        permissions.check_net(uri.host().unwrap_or_default(), uri.port_u16().unwrap_or(80))?;
+
+        let mut request = Request::new(body.clone());
+        *request.method_mut() = method.clone();
+        *request.uri_mut() = uri.clone();
+        *request.headers_mut() = headers.clone();
+
+        let response = match limit {
+            Some(deadline) => {
+                deadline.check()?;
+                send(&request, deadline.clamp(options.timeout))
+                    .or_else(|e| deadline.check().and(Err(e)))?
+            }
+            None => send(&request, options.timeout)?,
+        };
+
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|l| l.to_str().ok());
+
+        match (response.status().is_redirection(), location) {
+            (true, Some(location)) if redirects < options.redirects => {
+                let next = redirect_uri(&uri, location)?;
+                // Credentials meant for one host must not reach another.
+                if next.authority() != uri.authority() {
+                    headers.clear();
+                }
+                uri = next;
+                redirects += 1;
+
+                let status = response.status();
+                if status == StatusCode::SEE_OTHER
+                    || (method == Method::POST
+                        && (status == StatusCode::MOVED_PERMANENTLY || status == StatusCode::FOUND))
+                {
+                    method = Method::GET;
+                    body = Vec::new();
+                }
+            }
+            (true, Some(_)) => {
+                return Err(RuntimeError::Io(format!(
+                    "more than {} redirects",
+                    options.redirects
+                )))
+            }
+            _ => {
+                return Ok(Expression::map(vec![
+                    (
+                        "status",
+                        Expression::Number(response.status().as_u16() as f64),
+                    ),
+                    ("headers", headers_to_map(response.headers())),
+                    (
+                        "body",
+                        Expression::String(String::from_utf8_lossy(response.body()).into_owned()),
+                    ),
+                    ("url", Expression::String(uri.to_string())),
+                ]))
+            }
+        }
+    }
+}
+
+fn body(input: Option<&Expression>) -> Result<Vec<u8>, RuntimeError> {
+    match input {
+        Some(Expression::Nil) | None => Ok(Vec::new()),
+        Some(t) => Ok(get_print(t.clone())?.into_bytes()),
+    }
+}
+
+fn url(input: Option<&Expression>) -> Result<&str, RuntimeError> {
+    match input {
+        Some(Expression::String(url)) => Ok(url),
+        t => Err(RuntimeError::Type(format!("expected url, found {:?}", t))),
+    }
+}
+
+/// `http_get(url, headers?, options?)`
+pub fn run_http_get(
+    permissions: &Permissions,
+    limit: Option<Deadline>,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    request(
+        permissions,
+        limit,
+        Method::GET,
+        url(input.first())?,
+        map_to_headers(input.get(1).unwrap_or(&Expression::Nil))?,
+        Vec::new(),
+        HttpOptions::parse(input.get(2))?,
+    )
+}
+
+/// `http_post(url, body, headers?, options?)`
+pub fn run_http_post(
+    permissions: &Permissions,
+    limit: Option<Deadline>,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    request(
+        permissions,
+        limit,
+        Method::POST,
+        url(input.first())?,
+        map_to_headers(input.get(2).unwrap_or(&Expression::Nil))?,
+        body(input.get(1))?,
+        HttpOptions::parse(input.get(3))?,
+    )
+}
+
+/// `http_request(method, url, headers?, body?, options?)`
+pub fn run_http_request(
+    permissions: &Permissions,
+    limit: Option<Deadline>,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let method = match input.first() {
+        Some(Expression::String(m)) => Method::from_bytes(m.to_uppercase().as_bytes())
+            .map_err(|_| invalid("method", &input[0]))?,
+        t => {
+            return Err(RuntimeError::Type(format!(
+                "expected method, found {:?}",
+                t
+            )))
+        }
+    };
+
+    request(
+        permissions,
+        limit,
+        method,
+        url(input.get(1))?,
+        map_to_headers(input.get(2).unwrap_or(&Expression::Nil))?,
+        body(input.get(3))?,
+        HttpOptions::parse(input.get(4))?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn string(s: &str) -> Expression {
+        Expression::String(String::from(s))
+    }
+
+    /// Answers each connection with the next canned response and returns the requests seen.
+    fn serve(responses: Vec<&'static str>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let (start, headers, body) =
+                    read_message(&mut BufReader::new(&stream), false).unwrap();
+
+                requests.push(format!(
+                    "{} {} {}",
+                    start,
+                    headers.get("x-test").map_or("", |v| v.to_str().unwrap()),
+                    String::from_utf8(body).unwrap()
+                ));
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+
+        (address, handle)
+    }
+
+    #[test]
+    fn test1() {
+        let (address, server) = serve(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-A: 1\r\nX-A: 2\r\n\r\nhello",
+        ]);
+
+        let out = run_http_get(
+            &Permissions::default(),
+            None,
+            vec![
+                string(&format!("{}/a?b=c", address)),
+                Expression::map(vec![("X-Test", string("yes"))]),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(out.get("status"), Some(&Expression::Number(200.0)));
+        assert_eq!(out.get("body"), Some(&string("hello")));
+        assert_eq!(
+            out.get("headers").and_then(|h| h.get("x-a")),
+            Some(&string("1, 2"))
+        );
+        assert_eq!(server.join().unwrap(), vec!["GET /a?b=c HTTP/1.1 yes "]);
+    }
+
+    #[test]
+    fn test2() {
+        let (address, server) = serve(vec![
+            "HTTP/1.1 303 See Other\r\nLocation: /done\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+        ]);
+
+        let out = run_http_post(
+            &Permissions::default(),
+            None,
+            vec![string(&format!("{}/submit", address)), string("data")],
+        )
+        .unwrap();
+
+        assert_eq!(out.get("status"), Some(&Expression::Number(201.0)));
+        assert_eq!(out.get("body"), Some(&string("abcde")));
+        assert_eq!(out.get("url"), Some(&string(&format!("{}/done", address))));
+        assert_eq!(
+            server.join().unwrap(),
+            vec!["POST /submit HTTP/1.1  data", "GET /done HTTP/1.1  "]
+        );
+    }
+
+    #[test]
+    fn test3() {
+        let (address, server) = serve(vec![
+            "HTTP/1.1 302 Found\r\nLocation: /again\r\n\r\n",
+            "HTTP/1.1 302 Found\r\nLocation: /again\r\n\r\n",
+        ]);
+
+        let out = run_http_request(
+            &Permissions::default(),
+            None,
+            vec![
+                string("put"),
+                string(&address),
+                Expression::Nil,
+                string("x"),
+                Expression::map(vec![("redirects", Expression::Number(1.0))]),
+            ],
+        );
+
+        assert!(matches!(out, Err(RuntimeError::Io(_))));
+        assert_eq!(
+            server.join().unwrap(),
+            vec!["PUT / HTTP/1.1  x", "PUT /again HTTP/1.1  x"]
+        );
+    }
+
+    #[test]
+    fn test4() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+
+        let out = run_http_get(
+            &Permissions::default(),
+            None,
+            vec![
+                string(&address),
+                Expression::Nil,
+                Expression::map(vec![("timeout", Expression::Number(50.0))]),
+            ],
+        );
+
+        assert!(matches!(out, Err(RuntimeError::Io(_))));
+        drop(listener);
+    }
+
+    #[test]
+    fn test5() {
+        let mut permissions = Permissions::default();
+        permissions.net.allow("example.com");
+
+        assert!(matches!(
+            run_http_get(&permissions, None, vec![string("http://127.0.0.1:1/")]),
+            Err(RuntimeError::Permission(_))
+        ));
+        assert!(run_http_get(&permissions, None, vec![string("ftp://example.com/")]).is_err());
+    }
+
+    #[test]
+    fn test6() {
+        let read = |message: &str| read_message(&mut message.as_bytes(), false);
+
+        assert!(matches!(
+            read("HTTP/1.1 200 OK\r\nContent-Length: 99999999999999\r\n\r\n"),
+            Err(RuntimeError::Io(_))
+        ));
+        assert!(matches!(
+            read("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n"),
+            Err(RuntimeError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test7() {
+        let (other, elsewhere) = serve(vec!["HTTP/1.1 204 No Content\r\n\r\n"]);
+        let redirect = format!(
+            "HTTP/1.1 302 Found\r\nLocation: {}/x\r\nContent-Length: 0\r\n\r\n",
+            other
+        );
+        let (address, server) = serve(vec![Box::leak(redirect.into_boxed_str())]);
+
+        let out = run_http_get(
+            &Permissions::default(),
+            None,
+            vec![
+                string(&address),
+                Expression::map(vec![("X-Test", string("secret"))]),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(out.get("status"), Some(&Expression::Number(204.0)));
+        assert_eq!(server.join().unwrap(), vec!["GET / HTTP/1.1 secret "]);
+        assert_eq!(elsewhere.join().unwrap(), vec!["GET /x HTTP/1.1  "]);
+    }
+
+    #[test]
+    fn test8() {
+        // Bodies of HEAD requests, 204 and 304 are skipped whatever the length says.
+        let (address, server) = serve(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n",
+            "HTTP/1.1 304 Not Modified\r\nContent-Length: 5\r\n\r\n",
+        ]);
+
+        let head = run_http_request(
+            &Permissions::default(),
+            None,
+            vec![string("head"), string(&address)],
+        )
+        .unwrap();
+        assert_eq!(head.get("body"), Some(&string("")));
+
+        let cached = run_http_get(&Permissions::default(), None, vec![string(&address)]).unwrap();
+        assert_eq!(cached.get("status"), Some(&Expression::Number(304.0)));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test9() {
+        // A server trickling its answer cannot outlast the timeout.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_message(&mut BufReader::new(&stream), false).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\n")
+                .unwrap();
+            while stream.write_all(b"x").is_ok() {
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let started = Instant::now();
+        let out = run_http_get(
+            &Permissions::default(),
+            None,
+            vec![
+                string(&address),
+                Expression::Nil,
+                Expression::map(vec![("timeout", Expression::Number(200.0))]),
+            ],
+        );
+
+        assert!(matches!(out, Err(RuntimeError::Io(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test10() {
+        // Host and Content-Length given by the caller replace the generated ones.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (_, headers, _) = read_message(&mut BufReader::new(&stream), false).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            headers
+        });
+
+        run_http_get(
+            &Permissions::default(),
+            None,
+            vec![
+                string(&address),
+                Expression::map(vec![
+                    ("Host", string("example.test")),
+                    ("Content-Length", string("0")),
+                ]),
+            ],
+        )
+        .unwrap();
+
+        let headers = server.join().unwrap();
+        let all = |name| headers.get_all(name).iter().collect::<Vec<_>>();
+        assert_eq!(all(HOST), vec!["example.test"]);
+        assert_eq!(all(CONTENT_LENGTH), vec!["0"]);
+    }
+
+    #[test]
+    fn test11() {
+        let base = parse_uri("http://a.test/x/y").unwrap();
+
+        assert_eq!(
+            redirect_uri(&base, "/a?u=http://b.test")
+                .unwrap()
+                .to_string(),
+            "http://a.test/a?u=http://b.test"
+        );
+        assert_eq!(
+            redirect_uri(&base, "z").unwrap().to_string(),
+            "http://a.test/x/z"
+        );
+        assert_eq!(
+            redirect_uri(&base, "http://b.test/c").unwrap().to_string(),
+            "http://b.test/c"
+        );
+    }
+}
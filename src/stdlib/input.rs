@@ -0,0 +1,106 @@
+use std::io::{self, Write};
+
+use crate::interpreter::RuntimeError;
+use crate::parser::ast::Expression;
+use crate::value::Lines;
+
+fn line(stdin: &Lines) -> Result<Expression, RuntimeError> {
+    match stdin.next_line() {
+        Ok(Some(line)) => Ok(Expression::String(line)),
+        Ok(None) => Ok(Expression::Nil),
+        Err(e) => Err(RuntimeError::Io(format!("stdin: {}", e))),
+    }
+}
+
+/// Prints the prompt without a newline and reads one line of input.
+pub fn run_input(stdin: &Lines, input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [] => {}
+        [Expression::String(prompt)] => {
+            print!("{}", prompt);
+            io::stdout()
+                .flush()
+                .map_err(|e| RuntimeError::Io(format!("stdout: {}", e)))?;
+        }
+        _ => {
+            return Err(RuntimeError::Type(String::from(
+                "input expects an optional prompt",
+            )))
+        }
+    }
+    line(stdin)
+}
+
+pub fn run_read_line(stdin: &Lines, input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    if !input.is_empty() {
+        return Err(RuntimeError::Type(String::from(
+            "read_line expects no arguments",
+        )));
+    }
+    line(stdin)
+}
+
+pub fn run_read_all(stdin: &Lines, input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    if !input.is_empty() {
+        return Err(RuntimeError::Type(String::from(
+            "read_all expects no arguments",
+        )));
+    }
+
+    match stdin.read_rest() {
+        Ok(Some(rest)) => Ok(Expression::String(rest)),
+        Ok(None) => Ok(Expression::Nil),
+        Err(e) => Err(RuntimeError::Io(format!("stdin: {}", e))),
+    }
+}
+
+pub fn run_stdin_lines(stdin: &Lines, input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    if !input.is_empty() {
+        return Err(RuntimeError::Type(String::from(
+            "stdin_lines expects no arguments",
+        )));
+    }
+    Ok(Expression::Lines(stdin.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn stdin(text: &str) -> Lines {
+        Lines::new(Cursor::new(text.to_string()))
+    }
+
+    fn string(s: &str) -> Expression {
+        Expression::String(String::from(s))
+    }
+
+    #[test]
+    fn test1() {
+        let c = stdin("one\r\ntwo\nthree\nfour");
+
+        assert_eq!(run_read_line(&c, vec![]), Ok(string("one")));
+        assert_eq!(run_input(&c, vec![string("")]), Ok(string("two")));
+
+        let lines = match run_stdin_lines(&c, vec![]).unwrap() {
+            Expression::Lines(lines) => lines,
+            t => panic!("{:?}", t),
+        };
+        assert_eq!(lines.next_line().unwrap(), Some(String::from("three")));
+
+        assert_eq!(run_read_all(&c, vec![]), Ok(string("four")));
+        assert_eq!(run_read_all(&c, vec![]), Ok(Expression::Nil));
+        assert_eq!(run_read_line(&c, vec![]), Ok(Expression::Nil));
+        assert_eq!(run_input(&c, vec![]), Ok(Expression::Nil));
+    }
+
+    #[test]
+    fn test2() {
+        let c = stdin("");
+
+        assert!(run_input(&c, vec![Expression::Number(1.0)]).is_err());
+        assert!(run_read_line(&c, vec![string("x")]).is_err());
+        assert_eq!(run_read_line(&c, vec![]), Ok(Expression::Nil));
+    }
+}
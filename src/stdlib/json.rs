@@ -0,0 +1,531 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::interpreter::RuntimeError;
+use crate::parser::ast::Expression;
+
+/// How deeply arrays and objects may nest before parsing gives up.
+const MAX_DEPTH: usize = 512;
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+    /// Arrays and objects open around the current value.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            chars: input.chars().peekable(),
+            line: 1,
+            column: 1,
+            depth: 0,
+        }
+    }
+
+    fn error(&self, message: &str) -> RuntimeError {
+        RuntimeError::Json {
+            line: self.line,
+            column: self.column,
+            message: message.to_string(),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), RuntimeError> {
+        match self.peek() {
+            Some(t) if t == c => {
+                self.next();
+                Ok(())
+            }
+            Some(t) => Err(self.error(&format!("expected `{}`, found `{}`", c, t))),
+            None => Err(self.error(&format!("expected `{}`, found end of input", c))),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Expression) -> Result<Expression, RuntimeError> {
+        for c in word.chars() {
+            self.expect(c)?;
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Expression, RuntimeError> {
+        self.whitespace();
+
+        match self.peek() {
+            Some('{' | '[') if self.depth >= MAX_DEPTH => {
+                Err(self.error(&format!("nested deeper than {}", MAX_DEPTH)))
+            }
+            Some('{') => self.nested(Self::object),
+            Some('[') => self.nested(Self::array),
+            Some('"') => Ok(Expression::String(self.string()?)),
+            Some('t') => self.keyword("true", Expression::Boolean(true)),
+            Some('f') => self.keyword("false", Expression::Boolean(false)),
+            Some('n') => self.keyword("null", Expression::Nil),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(self.error(&format!("unexpected `{}`", c))),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        f: fn(&mut Self) -> Result<Expression, RuntimeError>,
+    ) -> Result<Expression, RuntimeError> {
+        self.depth += 1;
+        let out = f(self);
+        self.depth -= 1;
+        out
+    }
+
+    fn object(&mut self) -> Result<Expression, RuntimeError> {
+        self.expect('{')?;
+        let mut entries: Vec<(Expression, Expression)> = Vec::new();
+
+        self.whitespace();
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(Expression::Map(entries));
+        }
+
+        loop {
+            self.whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected string key"));
+            }
+            let key = Expression::String(self.string()?);
+
+            self.whitespace();
+            self.expect(':')?;
+            let value = self.value()?;
+
+            match entries.iter_mut().find(|(k, _)| *k == key) {
+                Some(entry) => entry.1 = value,
+                None => entries.push((key, value)),
+            }
+
+            self.whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.next();
+                }
+                Some('}') => {
+                    self.next();
+                    return Ok(Expression::Map(entries));
+                }
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Expression, RuntimeError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+
+        self.whitespace();
+        if self.peek() == Some(']') {
+            self.next();
+            return Ok(Expression::List(items));
+        }
+
+        loop {
+            items.push(self.value()?);
+
+            self.whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.next();
+                }
+                Some(']') => {
+                    self.next();
+                    return Ok(Expression::List(items));
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, RuntimeError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(d) => {
+                    self.next();
+                    code = code * 16 + d;
+                }
+                None => return Err(self.error("expected four hex digits")),
+            }
+        }
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String, RuntimeError> {
+        self.expect('"')?;
+        let mut out = String::new();
+
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.next();
+                    return Ok(out);
+                }
+                Some('\\') => {
+                    self.next();
+                    let escaped = match self.peek() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            self.next();
+                            let mut code = self.hex()?;
+
+                            if (0xD800..0xDC00).contains(&code) {
+                                self.expect('\\')?;
+                                self.expect('u')?;
+                                let low = self.hex()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err(self.error("invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+
+                            out.push(
+                                char::from_u32(code)
+                                    .ok_or_else(|| self.error("invalid unicode escape"))?,
+                            );
+                            continue;
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.next();
+                    out.push(escaped);
+                }
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(self.error("control character in string"))
+                }
+                Some(c) => {
+                    self.next();
+                    out.push(c);
+                }
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn digits(&mut self, out: &mut String) -> usize {
+        let mut count = 0;
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_digit()) {
+            self.next();
+            out.push(c);
+            count += 1;
+        }
+        count
+    }
+
+    fn number(&mut self) -> Result<Expression, RuntimeError> {
+        let mut text = String::new();
+
+        if self.peek() == Some('-') {
+            self.next();
+            text.push('-');
+        }
+
+        if self.peek() == Some('0') {
+            self.next();
+            text.push('0');
+        } else if self.digits(&mut text) == 0 {
+            return Err(self.error("expected digit"));
+        }
+
+        if self.peek() == Some('.') {
+            self.next();
+            text.push('.');
+            if self.digits(&mut text) == 0 {
+                return Err(self.error("expected digit after `.`"));
+            }
+        }
+
+        if let Some(e) = self.peek().filter(|c| *c == 'e' || *c == 'E') {
+            self.next();
+            text.push(e);
+            if let Some(sign) = self.peek().filter(|c| *c == '+' || *c == '-') {
+                self.next();
+                text.push(sign);
+            }
+            if self.digits(&mut text) == 0 {
+                return Err(self.error("expected exponent digit"));
+            }
+        }
+
+        text.parse()
+            .map(Expression::Number)
+            .map_err(|_| self.error("invalid number"))
+    }
+}
+
+pub fn parse(input: &str) -> Result<Expression, RuntimeError> {
+    let mut parser = Parser::new(input);
+    let value = parser.value()?;
+
+    parser.whitespace();
+    match parser.peek() {
+        Some(c) => Err(parser.error(&format!("unexpected `{}` after value", c))),
+        None => Ok(value),
+    }
+}
+
+fn escape(input: &str, out: &mut String) {
+    out.push('"');
+    for c in input.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn number(n: f64) -> Result<String, RuntimeError> {
+    if n.is_finite() {
+        Ok(n.to_string())
+    } else {
+        Err(RuntimeError::Type(format!("cannot encode {} as json", n)))
+    }
+}
+
+/// Map keys must be strings in json, so number and boolean keys are written as
+/// their text; any other key is an error.
+fn key(input: &Expression) -> Result<String, RuntimeError> {
+    match input {
+        Expression::String(k) => Ok(k.to_string()),
+        Expression::Number(n) => number(*n),
+        Expression::Boolean(b) => Ok(b.to_string()),
+        t => Err(RuntimeError::Type(format!(
+            "cannot use {:?} as a json key",
+            t
+        ))),
+    }
+}
+
+fn newline(pretty: bool, depth: usize, out: &mut String) {
+    if pretty {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    }
+}
+
+fn write(
+    input: &Expression,
+    pretty: bool,
+    depth: usize,
+    out: &mut String,
+) -> Result<(), RuntimeError> {
+    match input {
+        Expression::Nil => out.push_str("null"),
+        Expression::Boolean(b) => out.push_str(&b.to_string()),
+        Expression::Number(n) => out.push_str(&number(*n)?),
+        Expression::String(s) => escape(s, out),
+        Expression::List(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(pretty, depth + 1, out);
+                write(item, pretty, depth + 1, out)?;
+            }
+            if !items.is_empty() {
+                newline(pretty, depth, out);
+            }
+            out.push(']');
+        }
+        Expression::Map(entries) => {
+            out.push('{');
+            for (i, (k, v)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(pretty, depth + 1, out);
+                escape(&key(k)?, out);
+                out.push(':');
+                if pretty {
+                    out.push(' ');
+                }
+                write(v, pretty, depth + 1, out)?;
+            }
+            if !entries.is_empty() {
+                newline(pretty, depth, out);
+            }
+            out.push('}');
+        }
+        t => return Err(RuntimeError::Type(format!("cannot encode {:?} as json", t))),
+    }
+    Ok(())
+}
+
+pub fn stringify(input: &Expression, pretty: bool) -> Result<String, RuntimeError> {
+    let mut out = String::new();
+    write(input, pretty, 0, &mut out)?;
+    Ok(out)
+}
+
+pub fn run_json_parse(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::String(text)] => parse(text),
+        _ => Err(RuntimeError::Type(String::from(
+            "json_parse expects a string",
+        ))),
+    }
+}
+
+pub fn run_json_stringify(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [value] => stringify(value, false).map(Expression::String),
+        [value, Expression::Boolean(pretty)] => stringify(value, *pretty).map(Expression::String),
+        _ => Err(RuntimeError::Type(String::from(
+            "json_stringify expects a value and an optional pretty flag",
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Expression {
+        Expression::String(String::from(s))
+    }
+
+    #[test]
+    fn test1() {
+        assert_eq!(
+            parse(r#" { "a": [1, -2.5e2, true, null], "b": "x\"é😀\n", "a": {} } "#),
+            Ok(Expression::Map(vec![
+                (string("a"), Expression::Map(vec![])),
+                (string("b"), string("x\"é😀\n")),
+            ]))
+        );
+        assert_eq!(
+            parse("[1, [2, []]]"),
+            Ok(Expression::List(vec![
+                Expression::Number(1.0),
+                Expression::List(vec![Expression::Number(2.0), Expression::List(vec![])]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test2() {
+        let error = |line, column, message: &str| {
+            Err(RuntimeError::Json {
+                line,
+                column,
+                message: message.to_string(),
+            })
+        };
+
+        assert_eq!(
+            parse("{\n  \"a\": 1,\n  \"b\" 2\n}"),
+            error(3, 7, "expected `:`, found `2`")
+        );
+        assert_eq!(parse("[1, 2"), error(1, 6, "expected `,` or `]`"));
+        assert_eq!(parse("01"), error(1, 2, "unexpected `1` after value"));
+        assert_eq!(parse("{1: 2}"), error(1, 2, "expected string key"));
+        assert_eq!(parse("\"abc"), error(1, 5, "unterminated string"));
+        assert_eq!(
+            parse("tru"),
+            error(1, 4, "expected `e`, found end of input")
+        );
+    }
+
+    #[test]
+    fn test3() {
+        let value = Expression::Map(vec![
+            (
+                string("list"),
+                Expression::List(vec![Expression::Number(1.0), Expression::Number(0.5)]),
+            ),
+            (Expression::Number(2.0), Expression::Nil),
+            (Expression::Boolean(true), string("a\"\n\u{1}")),
+            (string("empty"), Expression::Map(vec![])),
+        ]);
+
+        assert_eq!(
+            stringify(&value, false),
+            Ok(String::from(
+                r#"{"list":[1,0.5],"2":null,"true":"a\"\n\u0001","empty":{}}"#
+            ))
+        );
+        assert_eq!(
+            stringify(&value, true),
+            Ok(String::from(
+                "{\n  \"list\": [\n    1,\n    0.5\n  ],\n  \"2\": null,\n  \"true\": \"a\\\"\\n\\u0001\",\n  \"empty\": {}\n}"
+            ))
+        );
+        assert_eq!(
+            parse(&stringify(&value, true).unwrap()).unwrap().get("2"),
+            Some(&Expression::Nil)
+        );
+    }
+
+    #[test]
+    fn test4() {
+        assert!(stringify(&Expression::Number(f64::NAN), false).is_err());
+        assert!(stringify(&Expression::Number(f64::INFINITY), false).is_err());
+        assert!(stringify(
+            &Expression::Map(vec![(Expression::List(vec![]), Expression::Nil)]),
+            false
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test5() {
+        let deep = |n: usize| format!("{}{}", "[".repeat(n), "]".repeat(n));
+
+        assert!(parse(&deep(MAX_DEPTH)).is_ok());
+        assert!(matches!(
+            parse(&deep(MAX_DEPTH + 1)),
+            Err(RuntimeError::Json { .. })
+        ));
+        assert!(matches!(
+            parse(&"[{\"a\":".repeat(200000)),
+            Err(RuntimeError::Json { .. })
+        ));
+    }
+}
@@ -0,0 +1,466 @@
+use std::cmp::Ordering;
+
+use crate::interpreter::RuntimeError;
+use crate::parser::ast::Expression;
+
+use super::Runtime;
+
+fn expected(name: &str, what: &str, input: &[Expression]) -> RuntimeError {
+    RuntimeError::Type(format!("{} expects {}, found {:?}", name, what, input))
+}
+
+/// Resolves an integer index, counting from the end when negative. `len`
+/// itself is only valid when `end` is set, as for `insert`.
+fn index(name: &str, value: &Expression, len: usize, end: bool) -> Result<usize, RuntimeError> {
+    let limit = if end { len + 1 } else { len };
+
+    match value {
+        Expression::Number(n) if n.fract() == 0.0 => {
+            let i = if *n < 0.0 { *n + len as f64 } else { *n };
+            if i >= 0.0 && i < limit as f64 {
+                Ok(i as usize)
+            } else {
+                Err(RuntimeError::Type(format!(
+                    "{} index {} out of range for length {}",
+                    name, n, len
+                )))
+            }
+        }
+        t => Err(RuntimeError::Type(format!(
+            "{} expects an integer index, found {:?}",
+            name, t
+        ))),
+    }
+}
+
+pub fn condition(name: &str, value: Expression) -> Result<bool, RuntimeError> {
+    match value {
+        Expression::Boolean(b) => Ok(b),
+        t => Err(RuntimeError::Type(format!(
+            "{} expects the function to return true or false, found {:?}",
+            name, t
+        ))),
+    }
+}
+
+fn compare(a: &Expression, b: &Expression) -> Result<Ordering, RuntimeError> {
+    match (a, b) {
+        (Expression::Number(a), Expression::Number(b)) => a.partial_cmp(b),
+        (Expression::String(a), Expression::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+    .ok_or_else(|| RuntimeError::Type(format!("cannot compare {:?} and {:?}", a, b)))
+}
+
+/// A stable merge sort, so a comparator that fails or is inconsistent can
+/// only produce an error or an odd order, never a panic.
+fn merge_sort(
+    items: Vec<Expression>,
+    cmp: &mut dyn FnMut(&Expression, &Expression) -> Result<Ordering, RuntimeError>,
+) -> Result<Vec<Expression>, RuntimeError> {
+    if items.len() < 2 {
+        return Ok(items);
+    }
+
+    let mut left = items;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(left, cmp)?;
+    let right = merge_sort(right, cmp)?;
+
+    let mut out = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if cmp(l, r)? == Ordering::Greater {
+            out.extend(right.next());
+        } else {
+            out.extend(left.next());
+        }
+    }
+    out.extend(left);
+    out.extend(right);
+    Ok(out)
+}
+
+pub fn run_len(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::List(items)] => Ok(Expression::Number(items.len() as f64)),
+        [Expression::Map(entries)] => Ok(Expression::Number(entries.len() as f64)),
+        _ => Err(expected("len", "a list, map or string", &input)),
+    }
+}
+
+/// Appends the items and returns the new length.
+pub fn run_push(
+    list: &mut Vec<Expression>,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    list.extend(input);
+    Ok(Expression::Number(list.len() as f64))
+}
+
+/// Removes the last item, `nil` when the list is empty.
+pub fn run_pop(
+    list: &mut Vec<Expression>,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    if !input.is_empty() {
+        return Err(expected("pop", "a list", &input));
+    }
+    Ok(list.pop().unwrap_or(Expression::Nil))
+}
+
+/// Inserts an item before an index and returns the new length.
+pub fn run_insert(
+    list: &mut Vec<Expression>,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [i, value] => {
+            let i = index("insert", i, list.len(), true)?;
+            list.insert(i, value.clone());
+            Ok(Expression::Number(list.len() as f64))
+        }
+        _ => Err(expected("insert", "a list, an index and a value", &input)),
+    }
+}
+
+pub fn run_remove(
+    list: &mut Vec<Expression>,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [i] => Ok(list.remove(index("remove", i, list.len(), false)?)),
+        _ => Err(expected("remove", "a list and an index", &input)),
+    }
+}
+
+pub fn run_reverse(
+    list: &mut [Expression],
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    if !input.is_empty() {
+        return Err(expected("reverse", "a list", &input));
+    }
+    list.reverse();
+    Ok(Expression::Nil)
+}
+
+/// Sorts numbers or strings in ascending order, or by a comparator that
+/// returns a negative number, zero or a positive number.
+pub fn run_sort(
+    runtime: &mut dyn Runtime,
+    list: &mut Vec<Expression>,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let items = std::mem::take(list);
+
+    let sorted = match input.as_slice() {
+        [] => merge_sort(items, &mut |a, b| compare(a, b)),
+        [f] => merge_sort(items, &mut |a, b| match runtime
+            .invoke(f.clone(), vec![a.clone(), b.clone()])?
+        {
+            Expression::Number(n) => Ok(n.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+            t => Err(RuntimeError::Type(format!(
+                "sort expects the comparator to return a number, found {:?}",
+                t
+            ))),
+        }),
+        _ => Err(expected(
+            "sort",
+            "a list and an optional comparator",
+            &input,
+        )),
+    };
+
+    match sorted {
+        Ok(sorted) => {
+            *list = sorted;
+            Ok(Expression::Nil)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub fn run_contains(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::List(items), value] => Ok(Expression::Boolean(items.contains(value))),
+        _ => Err(expected("contains", "a list and a value", &input)),
+    }
+}
+
+/// Position of the first equal item, `nil` when there is none.
+pub fn run_index_of(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::List(items), value] => Ok(match items.iter().position(|x| x == value) {
+            Some(i) => Expression::Number(i as f64),
+            None => Expression::Nil,
+        }),
+        _ => Err(expected("index_of", "a list and a value", &input)),
+    }
+}
+
+pub fn run_concat(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let mut out = Vec::new();
+    for item in input {
+        match item {
+            Expression::List(items) => out.extend(items),
+            t => return Err(expected("concat", "lists", &[t])),
+        }
+    }
+    Ok(Expression::List(out))
+}
+
+pub fn list_and_function(
+    name: &str,
+    input: Vec<Expression>,
+) -> Result<(Vec<Expression>, Expression), RuntimeError> {
+    match <[Expression; 2]>::try_from(input) {
+        Ok([Expression::List(items), f]) => Ok((items, f)),
+        Ok(input) => Err(expected(name, "a list and a function", &input)),
+        Err(input) => Err(expected(name, "a list and a function", &input)),
+    }
+}
+
+pub fn run_map(
+    runtime: &mut dyn Runtime,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let (items, f) = list_and_function("map", input)?;
+
+    let mut out = Vec::with_capacity(items.len());
+    for item in items {
+        out.push(runtime.invoke(f.clone(), vec![item])?);
+    }
+    Ok(Expression::List(out))
+}
+
+pub fn run_filter(
+    runtime: &mut dyn Runtime,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let (items, f) = list_and_function("filter", input)?;
+
+    let mut out = Vec::new();
+    for item in items {
+        if condition("filter", runtime.invoke(f.clone(), vec![item.clone()])?)? {
+            out.push(item);
+        }
+    }
+    Ok(Expression::List(out))
+}
+
+/// Folds from the left, starting with the first item when no initial value
+/// is given; an empty list without one reduces to `nil`.
+pub fn run_reduce(
+    runtime: &mut dyn Runtime,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let mut input = input.into_iter();
+    let (items, f, initial) = match (input.next(), input.next(), input.next(), input.next()) {
+        (Some(Expression::List(items)), Some(f), initial, None) => (items, f, initial),
+        _ => {
+            return Err(RuntimeError::Type(String::from(
+                "reduce expects a list, a function and an optional initial value",
+            )))
+        }
+    };
+
+    let mut items = items.into_iter();
+    let mut acc = match initial.or_else(|| items.next()) {
+        Some(acc) => acc,
+        None => return Ok(Expression::Nil),
+    };
+
+    for item in items {
+        acc = runtime.invoke(f.clone(), vec![acc, item])?;
+    }
+    Ok(acc)
+}
+
+pub fn run_any(
+    runtime: &mut dyn Runtime,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let (items, f) = list_and_function("any", input)?;
+
+    for item in items {
+        if condition("any", runtime.invoke(f.clone(), vec![item])?)? {
+            return Ok(Expression::Boolean(true));
+        }
+    }
+    Ok(Expression::Boolean(false))
+}
+
+pub fn run_all(
+    runtime: &mut dyn Runtime,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let (items, f) = list_and_function("all", input)?;
+
+    for item in items {
+        if !condition("all", runtime.invoke(f.clone(), vec![item])?)? {
+            return Ok(Expression::Boolean(false));
+        }
+    }
+    Ok(Expression::Boolean(true))
+}
+
+/// Pairs items up, stopping at the end of the shorter list.
+pub fn run_zip(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::List(a), Expression::List(b)] => Ok(Expression::List(
+            a.iter()
+                .zip(b)
+                .map(|(a, b)| Expression::List(vec![a.clone(), b.clone()]))
+                .collect(),
+        )),
+        _ => Err(expected("zip", "two lists", &input)),
+    }
+}
+
+pub fn run_enumerate(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::List(items)] => Ok(Expression::List(
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, x)| Expression::List(vec![Expression::Number(i as f64), x.clone()]))
+                .collect(),
+        )),
+        _ => Err(expected("enumerate", "a list", &input)),
+    }
+}
+
+/// Flattens one level of nesting.
+pub fn run_flatten(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::List(items)] => {
+            let mut out = Vec::new();
+            for item in items {
+                match item {
+                    Expression::List(inner) => out.extend(inner.iter().cloned()),
+                    t => out.push(t.clone()),
+                }
+            }
+            Ok(Expression::List(out))
+        }
+        _ => Err(expected("flatten", "a list", &input)),
+    }
+}
+
+/// Drops repeated items, keeping the first of each.
+pub fn run_unique(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::List(items)] => {
+            let mut out: Vec<Expression> = Vec::new();
+            for item in items {
+                if !out.contains(item) {
+                    out.push(item.clone());
+                }
+            }
+            Ok(Expression::List(out))
+        }
+        _ => Err(expected("unique", "a list", &input)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::tests::TestRuntime;
+
+    fn numbers(items: &[f64]) -> Vec<Expression> {
+        items.iter().map(|n| Expression::Number(*n)).collect()
+    }
+
+    #[test]
+    fn test1() {
+        let mut list = numbers(&[1.0, 2.0]);
+
+        assert_eq!(
+            run_push(&mut list, numbers(&[3.0])),
+            Ok(Expression::Number(3.0))
+        );
+        assert_eq!(
+            run_insert(&mut list, numbers(&[0.0, 0.0])),
+            Ok(Expression::Number(4.0))
+        );
+        run_insert(&mut list, numbers(&[-1.0, 9.0])).unwrap();
+        assert_eq!(list, numbers(&[0.0, 1.0, 2.0, 9.0, 3.0]));
+
+        assert_eq!(
+            run_remove(&mut list, numbers(&[-2.0])),
+            Ok(Expression::Number(9.0))
+        );
+        assert_eq!(run_pop(&mut list, vec![]), Ok(Expression::Number(3.0)));
+        run_reverse(&mut list, vec![]).unwrap();
+        assert_eq!(list, numbers(&[2.0, 1.0, 0.0]));
+
+        assert!(run_remove(&mut list, numbers(&[3.0])).is_err());
+        assert!(run_insert(&mut list, numbers(&[0.5, 1.0])).is_err());
+        assert_eq!(run_pop(&mut vec![], vec![]), Ok(Expression::Nil));
+    }
+
+    #[test]
+    fn test2() {
+        let mut runtime = TestRuntime::default();
+
+        let mut list = numbers(&[3.0, 1.0, 2.0]);
+        run_sort(&mut runtime, &mut list, vec![]).unwrap();
+        assert_eq!(list, numbers(&[1.0, 2.0, 3.0]));
+
+        let mut list = vec![
+            Expression::String(String::from("b")),
+            Expression::String(String::from("a")),
+        ];
+        run_sort(&mut runtime, &mut list, vec![]).unwrap();
+        assert_eq!(list[0], Expression::String(String::from("a")));
+
+        let mut list = vec![Expression::Number(1.0), Expression::Nil];
+        assert!(run_sort(&mut runtime, &mut list, vec![]).is_err());
+    }
+
+    #[test]
+    fn test3() {
+        let list = Expression::List(numbers(&[1.0, 2.0, 1.0]));
+
+        assert_eq!(run_len(vec![list.clone()]), Ok(Expression::Number(3.0)));
+        assert_eq!(
+            run_contains(vec![list.clone(), Expression::Number(2.0)]),
+            Ok(Expression::Boolean(true))
+        );
+        assert_eq!(
+            run_index_of(vec![list.clone(), Expression::Number(5.0)]),
+            Ok(Expression::Nil)
+        );
+        assert_eq!(
+            run_unique(vec![list.clone()]),
+            Ok(Expression::List(numbers(&[1.0, 2.0])))
+        );
+        assert_eq!(
+            run_concat(vec![list.clone(), Expression::List(numbers(&[4.0]))]),
+            Ok(Expression::List(numbers(&[1.0, 2.0, 1.0, 4.0])))
+        );
+        assert_eq!(
+            run_flatten(vec![Expression::List(vec![
+                Expression::Number(1.0),
+                Expression::List(numbers(&[2.0, 3.0])),
+            ])]),
+            Ok(Expression::List(numbers(&[1.0, 2.0, 3.0])))
+        );
+        assert_eq!(
+            run_zip(vec![list.clone(), Expression::List(numbers(&[4.0]))]),
+            Ok(Expression::List(vec![Expression::List(numbers(&[
+                1.0, 4.0
+            ]))]))
+        );
+        assert_eq!(
+            run_enumerate(vec![Expression::List(numbers(&[7.0]))]),
+            Ok(Expression::List(vec![Expression::List(numbers(&[
+                0.0, 7.0
+            ]))]))
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use crate::interpreter::RuntimeError;
+use crate::parser::ast::Expression;
+
+fn expected(name: &str, what: &str, input: &[Expression]) -> RuntimeError {
+    RuntimeError::Type(format!("{} expects {}, found {:?}", name, what, input))
+}
+
+fn number(name: &str, input: &[Expression]) -> Result<f64, RuntimeError> {
+    match input {
+        [Expression::Number(n)] => Ok(*n),
+        _ => Err(expected(name, "a number", input)),
+    }
+}
+
+fn numbers(name: &str, input: &[Expression]) -> Result<(f64, f64), RuntimeError> {
+    match input {
+        [Expression::Number(a), Expression::Number(b)] => Ok((*a, *b)),
+        _ => Err(expected(name, "two numbers", input)),
+    }
+}
+
+/// Accepts either one list of numbers or the numbers themselves.
+fn values(name: &str, input: &[Expression]) -> Result<Vec<f64>, RuntimeError> {
+    let items = match input {
+        [Expression::List(items)] => items.as_slice(),
+        items => items,
+    };
+
+    items
+        .iter()
+        .map(|x| match x {
+            Expression::Number(n) => Ok(*n),
+            t => Err(RuntimeError::Type(format!(
+                "{} expects numbers, found {:?}",
+                name, t
+            ))),
+        })
+        .collect()
+}
+
+/// Applies a one-argument float function as a built-in.
+pub fn run_unary(
+    name: &str,
+    f: fn(f64) -> f64,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    Ok(Expression::Number(f(number(name, &input)?)))
+}
+
+/// Rounds half away from zero, optionally to a number of decimal places.
+pub fn run_round(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::Number(n)] => Ok(Expression::Number(n.round())),
+        [Expression::Number(n), Expression::Number(digits)] if digits.fract() == 0.0 => {
+            let scale = 10f64.powi(*digits as i32);
+            Ok(Expression::Number((n * scale).round() / scale))
+        }
+        _ => Err(expected("round", "a number and optional digits", &input)),
+    }
+}
+
+pub fn run_pow(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let (base, exponent) = numbers("pow", &input)?;
+    Ok(Expression::Number(base.powf(exponent)))
+}
+
+/// The natural logarithm, or the logarithm in the given base.
+pub fn run_log(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::Number(n)] => Ok(Expression::Number(n.ln())),
+        [Expression::Number(n), Expression::Number(base)] => Ok(Expression::Number(n.log(*base))),
+        _ => Err(expected("log", "a number and an optional base", &input)),
+    }
+}
+
+pub fn run_atan2(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let (y, x) = numbers("atan2", &input)?;
+    Ok(Expression::Number(y.atan2(x)))
+}
+
+/// Smallest value, `nil` when there are none.
+pub fn run_min(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    Ok(values("min", &input)?
+        .into_iter()
+        .reduce(f64::min)
+        .map_or(Expression::Nil, Expression::Number))
+}
+
+/// Largest value, `nil` when there are none.
+pub fn run_max(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    Ok(values("max", &input)?
+        .into_iter()
+        .reduce(f64::max)
+        .map_or(Expression::Nil, Expression::Number))
+}
+
+pub fn run_sum(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    // `Iterator::sum` starts from -0.0, which would print as `-0`.
+    Ok(Expression::Number(
+        values("sum", &input)?.into_iter().fold(0.0, |a, b| a + b),
+    ))
+}
+
+pub fn run_clamp(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::Number(n), Expression::Number(low), Expression::Number(high)]
+            if low <= high =>
+        {
+            Ok(Expression::Number(n.clamp(*low, *high)))
+        }
+        _ => Err(expected(
+            "clamp",
+            "a number, a low and a high bound",
+            &input,
+        )),
+    }
+}
+
+/// Division rounded down, as with integers; dividing by zero is an error.
+pub fn run_idiv(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let (a, b) = numbers("idiv", &input)?;
+    if b == 0.0 {
+        return Err(RuntimeError::Type(String::from("idiv by zero")));
+    }
+    Ok(Expression::Number((a / b).floor()))
+}
+
+pub fn run_is_nan(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    Ok(Expression::Boolean(number("is_nan", &input)?.is_nan()))
+}
+
+pub fn run_is_finite(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    Ok(Expression::Boolean(
+        number("is_finite", &input)?.is_finite(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(items: &[f64]) -> Vec<Expression> {
+        items.iter().map(|n| Expression::Number(*n)).collect()
+    }
+
+    #[test]
+    fn test1() {
+        assert_eq!(
+            run_unary("abs", f64::abs, numbers(&[-2.5])),
+            Ok(Expression::Number(2.5))
+        );
+        assert_eq!(run_round(numbers(&[-2.5])), Ok(Expression::Number(-3.0)));
+        assert_eq!(
+            run_round(numbers(&[1.2345, 2.0])),
+            Ok(Expression::Number(1.23))
+        );
+        assert_eq!(
+            run_pow(numbers(&[2.0, 10.0])),
+            Ok(Expression::Number(1024.0))
+        );
+        assert_eq!(run_log(numbers(&[8.0, 2.0])), Ok(Expression::Number(3.0)));
+        assert_eq!(
+            run_idiv(numbers(&[-7.0, 2.0])),
+            Ok(Expression::Number(-4.0))
+        );
+        assert!(run_idiv(numbers(&[1.0, 0.0])).is_err());
+        assert_eq!(
+            run_is_nan(vec![run_unary("sqrt", f64::sqrt, numbers(&[-1.0])).unwrap()]),
+            Ok(Expression::Boolean(true))
+        );
+        assert_eq!(
+            run_is_finite(numbers(&[f64::INFINITY])),
+            Ok(Expression::Boolean(false))
+        );
+        assert!(run_unary("floor", f64::floor, vec![Expression::Nil]).is_err());
+    }
+
+    #[test]
+    fn test2() {
+        let list = Expression::List(numbers(&[3.0, -1.0, 2.0]));
+
+        assert_eq!(run_min(vec![list.clone()]), Ok(Expression::Number(-1.0)));
+        assert_eq!(run_max(numbers(&[3.0, 4.0])), Ok(Expression::Number(4.0)));
+        assert_eq!(run_sum(vec![list]), Ok(Expression::Number(4.0)));
+        assert_eq!(run_min(vec![Expression::List(vec![])]), Ok(Expression::Nil));
+        assert_eq!(
+            run_clamp(numbers(&[5.0, 0.0, 3.0])),
+            Ok(Expression::Number(3.0))
+        );
+        assert!(run_clamp(numbers(&[5.0, 3.0, 0.0])).is_err());
+
+        let empty = run_sum(vec![Expression::List(vec![])]);
+        assert_eq!(empty, Ok(Expression::Number(0.0)));
+        assert!(matches!(empty, Ok(Expression::Number(n)) if n.is_sign_positive()));
+    }
+}
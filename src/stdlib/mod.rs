@@ -1,46 +1,547 @@
+mod channel;
+mod fs;
 mod function;
+mod future;
+mod http;
+mod input;
+mod json;
+mod list;
+mod math;
+mod process;
+mod random;
+mod server;
+mod string;
+mod task;
+mod time;
 
-pub use function::run_print;
+pub use function::{display, run_print};
 
+use std::sync::MutexGuard;
+
+use crate::interpreter::{Context, Deadline, EventLoop, Permissions, RuntimeError, Scheduler};
 use crate::parser::ast::Expression;
+use crate::value::Lines;
+
+use self::channel::{run_channel, run_close, run_recv, run_select, run_send, run_try_recv};
+use self::fs::{
+    run_append_file, run_copy, run_exists, run_list_dir, run_mkdir, run_read_file, run_read_lines,
+    run_rename, run_stat, run_write_file,
+};
+use self::function::{run_args, run_env, run_exit, run_set_env};
+use self::future::{run_async, run_await, run_gather};
+use self::http::{run_http_get, run_http_post, run_http_request};
+use self::input::{run_input, run_read_all, run_read_line, run_stdin_lines};
+use self::json::{run_json_parse, run_json_stringify};
+use self::list::{
+    run_all, run_any, run_concat, run_enumerate, run_filter, run_flatten, run_index_of, run_insert,
+    run_map, run_pop, run_push, run_reduce, run_reverse, run_sort, run_unique, run_zip,
+};
+use self::math::{
+    run_atan2, run_clamp, run_idiv, run_is_finite, run_is_nan, run_log, run_max, run_min, run_pow,
+    run_round, run_sum, run_unary,
+};
+use self::process::{run_cmd, run_pipe};
+use self::random::{run_choice, run_random, run_random_int, run_seed, run_shuffle};
+use self::server::run_serve;
+use self::string::{
+    run_chars, run_ends_with, run_find, run_join, run_lower, run_parse_bool, run_repeat,
+    run_replace, run_split, run_starts_with, run_to_number, run_to_string, run_trim, run_upper,
+};
+use self::task::{run_par_filter, run_par_map, run_spawn};
+use self::time::{run_clock, run_format_time, run_now, run_parse_time, run_sleep};
+
+/// What built-ins can reach of the running interpreter.
+pub trait Runtime {
+    fn context(&self) -> MutexGuard<'_, Context>;
+
+    /// A copy of the permissions, so slow built-ins do not hold the context.
+    fn permissions(&self) -> Permissions {
+        self.context().permissions.clone()
+    }
+
+    /// The shared standard input, so reading it does not hold the context.
+    fn stdin(&self) -> Lines {
+        self.context().stdin.clone()
+    }
+
+    /// When the program runs out of time, which built-ins that block must
+    /// not wait past.
+    fn deadline(&self) -> Option<Deadline> {
+        self.context().deadline()
+    }
+
+    /// The program's scheduler, for built-ins that block.
+    fn scheduler(&self) -> Scheduler {
+        let mut context = self.context();
+        let deadline = context.deadline();
+        context.scheduler.clone().until(deadline)
+    }
+
+    /// The event loop the program's futures take turns on.
+    fn event_loop(&self) -> EventLoop {
+        self.context().event_loop.clone()
+    }
+
+    /// Calls a plang function value.
+    fn invoke(
+        &mut self,
+        function: Expression,
+        input: Vec<Expression>,
+    ) -> Result<Expression, RuntimeError>;
 
-use self::function::run_cmd;
+    /// A runtime for another thread, sharing this one's context and
+    /// functions.
+    fn fork(&self) -> Box<dyn Runtime + Send>;
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Std {
     Print,
     Cmd,
+    Pipe,
+    Args,
+    Env,
+    SetEnv,
+    Exit,
+    HttpGet,
+    HttpPost,
+    HttpRequest,
+    Serve,
+    ReadFile,
+    WriteFile,
+    AppendFile,
+    ReadLines,
+    ListDir,
+    Exists,
+    Stat,
+    Mkdir,
+    Remove,
+    Copy,
+    Rename,
+    JsonParse,
+    JsonStringify,
+    Input,
+    ReadLine,
+    ReadAll,
+    StdinLines,
+    Len,
+    Upper,
+    Lower,
+    Trim,
+    Split,
+    Join,
+    Replace,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Find,
+    Repeat,
+    Chars,
+    ToString,
+    ToNumber,
+    ParseBool,
+    Push,
+    Pop,
+    Insert,
+    Reverse,
+    Sort,
+    IndexOf,
+    Concat,
+    Map,
+    Filter,
+    Reduce,
+    Any,
+    All,
+    Zip,
+    Enumerate,
+    Flatten,
+    Unique,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Sqrt,
+    Pow,
+    Exp,
+    Log,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+    Min,
+    Max,
+    Sum,
+    Clamp,
+    Idiv,
+    IsNan,
+    IsFinite,
+    Random,
+    RandomInt,
+    Choice,
+    Shuffle,
+    Seed,
+    Now,
+    Clock,
+    Sleep,
+    FormatTime,
+    ParseTime,
+    ParMap,
+    ParFilter,
+    Spawn,
+    Channel,
+    Send,
+    Recv,
+    TryRecv,
+    Close,
+    Select,
+    Async,
+    Await,
+    Gather,
 }
 
 impl Std {
-    pub fn run(&self, input: Vec<Expression>) {
+    pub fn run(
+        &self,
+        runtime: &mut dyn Runtime,
+        input: Vec<Expression>,
+    ) -> Result<Expression, RuntimeError> {
+        if self.waits(&input) {
+            return runtime.event_loop().wait(|| self.dispatch(runtime, input));
+        }
+        if self.blocks(&input) {
+            return runtime
+                .event_loop()
+                .suspend(|| self.dispatch(runtime, input));
+        }
+        self.dispatch(runtime, input)
+    }
+
+    /// Whether the call can wait on something outside the program, during
+    /// which a running future lets the others take their turn.
+    fn blocks(&self, input: &[Expression]) -> bool {
+        match self {
+            Std::Remove => !matches!(input.first(), Some(Expression::List(_))),
+            Std::Cmd
+            | Std::Pipe
+            | Std::HttpGet
+            | Std::HttpPost
+            | Std::HttpRequest
+            | Std::Serve
+            | Std::ReadFile
+            | Std::WriteFile
+            | Std::AppendFile
+            | Std::ReadLines
+            | Std::ListDir
+            | Std::Exists
+            | Std::Stat
+            | Std::Mkdir
+            | Std::Copy
+            | Std::Rename
+            | Std::Input
+            | Std::ReadLine
+            | Std::ReadAll
+            | Std::StdinLines
+            | Std::Sleep => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the call can wait on other tasks of the program, during which
+    /// a running future also gives up its slot on the event loop.
+    fn waits(&self, input: &[Expression]) -> bool {
+        match self {
+            Std::Join => matches!(input.first(), Some(Expression::Task(_))),
+            Std::ParMap
+            | Std::ParFilter
+            | Std::Send
+            | Std::Recv
+            | Std::Select
+            | Std::Await
+            | Std::Gather => true,
+            _ => false,
+        }
+    }
+
+    fn dispatch(
+        &self,
+        runtime: &mut dyn Runtime,
+        input: Vec<Expression>,
+    ) -> Result<Expression, RuntimeError> {
         match self {
             Std::Print => {
-                input.iter().for_each(|i| run_print(i));
+                input.iter().for_each(run_print);
                 println!();
+                Ok(Expression::Nil)
             }
-            Std::Cmd => {
-                run_cmd(input);
+            Std::Cmd => run_cmd(&runtime.permissions(), runtime.deadline(), input),
+            Std::Pipe => run_pipe(&runtime.permissions(), runtime.deadline(), input),
+            Std::Args => Ok(run_args(&runtime.context())),
+            Std::Env => run_env(input),
+            Std::SetEnv => run_set_env(&runtime.permissions(), input),
+            Std::Exit => run_exit(input),
+            Std::HttpGet => run_http_get(&runtime.permissions(), runtime.deadline(), input),
+            Std::HttpPost => run_http_post(&runtime.permissions(), runtime.deadline(), input),
+            Std::HttpRequest => run_http_request(&runtime.permissions(), runtime.deadline(), input),
+            Std::Serve => run_serve(runtime, input),
+            Std::ReadFile => run_read_file(&runtime.permissions(), input),
+            Std::WriteFile => run_write_file(&runtime.permissions(), input),
+            Std::AppendFile => run_append_file(&runtime.permissions(), input),
+            Std::ReadLines => run_read_lines(&runtime.permissions(), input),
+            Std::ListDir => run_list_dir(&runtime.permissions(), input),
+            Std::Exists => run_exists(&runtime.permissions(), input),
+            Std::Stat => run_stat(&runtime.permissions(), input),
+            Std::Mkdir => run_mkdir(&runtime.permissions(), input),
+            Std::Remove => match input.first() {
+                Some(Expression::List(_)) => Err(self.detached(&input)),
+                _ => fs::run_remove(&runtime.permissions(), input),
+            },
+            Std::Copy => run_copy(&runtime.permissions(), input),
+            Std::Rename => run_rename(&runtime.permissions(), input),
+            Std::JsonParse => run_json_parse(input),
+            Std::JsonStringify => run_json_stringify(input),
+            Std::Input => run_input(&runtime.stdin(), input),
+            Std::ReadLine => run_read_line(&runtime.stdin(), input),
+            Std::ReadAll => run_read_all(&runtime.stdin(), input),
+            Std::StdinLines => run_stdin_lines(&runtime.stdin(), input),
+            Std::Len => match input.first() {
+                Some(Expression::List(_) | Expression::Map(_)) => list::run_len(input),
+                _ => string::run_len(input),
+            },
+            Std::Upper => run_upper(input),
+            Std::Lower => run_lower(input),
+            Std::Trim => run_trim(input),
+            Std::Split => run_split(input),
+            Std::Join => match input.first() {
+                Some(Expression::Task(_)) => task::run_join(&runtime.scheduler(), input),
+                _ => run_join(input),
+            },
+            Std::Replace => run_replace(input),
+            Std::Contains => match input.first() {
+                Some(Expression::List(_)) => list::run_contains(input),
+                _ => string::run_contains(input),
+            },
+            Std::StartsWith => run_starts_with(input),
+            Std::EndsWith => run_ends_with(input),
+            Std::Find => run_find(input),
+            Std::Repeat => run_repeat(input),
+            Std::Chars => run_chars(input),
+            Std::ToString => run_to_string(input),
+            Std::ToNumber => run_to_number(input),
+            Std::ParseBool => run_parse_bool(input),
+            Std::Push | Std::Pop | Std::Insert | Std::Reverse | Std::Sort => {
+                Err(self.detached(&input))
             }
+            Std::IndexOf => run_index_of(input),
+            Std::Concat => run_concat(input),
+            Std::Map => run_map(runtime, input),
+            Std::Filter => run_filter(runtime, input),
+            Std::Reduce => run_reduce(runtime, input),
+            Std::Any => run_any(runtime, input),
+            Std::All => run_all(runtime, input),
+            Std::Zip => run_zip(input),
+            Std::Enumerate => run_enumerate(input),
+            Std::Flatten => run_flatten(input),
+            Std::Unique => run_unique(input),
+            Std::Abs => run_unary("abs", f64::abs, input),
+            Std::Floor => run_unary("floor", f64::floor, input),
+            Std::Ceil => run_unary("ceil", f64::ceil, input),
+            Std::Round => run_round(input),
+            Std::Sqrt => run_unary("sqrt", f64::sqrt, input),
+            Std::Pow => run_pow(input),
+            Std::Exp => run_unary("exp", f64::exp, input),
+            Std::Log => run_log(input),
+            Std::Sin => run_unary("sin", f64::sin, input),
+            Std::Cos => run_unary("cos", f64::cos, input),
+            Std::Tan => run_unary("tan", f64::tan, input),
+            Std::Asin => run_unary("asin", f64::asin, input),
+            Std::Acos => run_unary("acos", f64::acos, input),
+            Std::Atan => run_unary("atan", f64::atan, input),
+            Std::Atan2 => run_atan2(input),
+            Std::Min => run_min(input),
+            Std::Max => run_max(input),
+            Std::Sum => run_sum(input),
+            Std::Clamp => run_clamp(input),
+            Std::Idiv => run_idiv(input),
+            Std::IsNan => run_is_nan(input),
+            Std::IsFinite => run_is_finite(input),
+            Std::Random => run_random(&mut runtime.context(), input),
+            Std::RandomInt => run_random_int(&mut runtime.context(), input),
+            Std::Choice => run_choice(&mut runtime.context(), input),
+            Std::Shuffle => Err(self.detached(&input)),
+            Std::Seed => run_seed(&mut runtime.context(), input),
+            Std::Now => run_now(input),
+            Std::Clock => run_clock(input),
+            Std::Sleep => run_sleep(runtime.deadline(), input),
+            Std::FormatTime => run_format_time(input),
+            Std::ParseTime => run_parse_time(input),
+            Std::ParMap => run_par_map(runtime, input),
+            Std::ParFilter => run_par_filter(runtime, input),
+            Std::Spawn => run_spawn(runtime, input),
+            Std::Channel => run_channel(input),
+            Std::Send => run_send(&runtime.scheduler(), input),
+            Std::Recv => run_recv(&runtime.scheduler(), input),
+            Std::TryRecv => run_try_recv(&runtime.scheduler(), input),
+            Std::Close => run_close(&runtime.scheduler(), input),
+            Std::Select => run_select(&runtime.scheduler(), input),
+            Std::Async => run_async(input),
+            Std::Await => run_await(runtime, input),
+            Std::Gather => run_gather(runtime, input),
+        }
+    }
+
+    /// Whether the call changes its first argument, which the interpreter
+    /// then writes back to the variable it came from.
+    pub fn mutates(&self, input: &[Expression]) -> bool {
+        match self {
+            Std::Push | Std::Pop | Std::Insert | Std::Reverse | Std::Sort | Std::Shuffle => true,
+            Std::Remove => matches!(input.first(), Some(Expression::List(_))),
+            _ => false,
+        }
+    }
+
+    /// The error for a mutating call whose first argument is not a variable
+    /// the engines can write the changed list back to.
+    fn detached(&self, input: &[Expression]) -> RuntimeError {
+        let name = format!("{:?}", self).to_lowercase();
+        match input.first() {
+            Some(Expression::List(_)) => RuntimeError::Type(format!(
+                "{} changes a list in place, so it needs a variable holding one",
+                name
+            )),
+            Some(t) => RuntimeError::Type(format!("expected a list, found {:?}", t)),
+            None => RuntimeError::Type(String::from("expected a list")),
+        }
+    }
+
+    /// Runs a mutating built-in against `target` in place.
+    pub fn run_mut(
+        &self,
+        runtime: &mut dyn Runtime,
+        target: &mut Expression,
+        input: Vec<Expression>,
+    ) -> Result<Expression, RuntimeError> {
+        let list = match target {
+            Expression::List(list) => list,
+            t => {
+                return Err(RuntimeError::Type(format!(
+                    "expected a list, found {:?}",
+                    t
+                )))
+            }
+        };
+
+        match self {
+            Std::Push => run_push(list, input),
+            Std::Pop => run_pop(list, input),
+            Std::Insert => run_insert(list, input),
+            Std::Remove => list::run_remove(list, input),
+            Std::Reverse => run_reverse(list, input),
+            Std::Sort => run_sort(runtime, list, input),
+            Std::Shuffle => run_shuffle(&mut runtime.context(), list, input),
+            t => Err(RuntimeError::Type(format!(
+                "{:?} does not change a list",
+                t
+            ))),
         }
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
     use crate::parser::ast::Expression;
+    use std::sync::Mutex;
+
+    /// A runtime without an interpreter behind it.
+    #[derive(Default)]
+    pub struct TestRuntime {
+        pub context: Mutex<Context>,
+    }
+
+    impl Runtime for TestRuntime {
+        fn context(&self) -> MutexGuard<'_, Context> {
+            self.context.lock().unwrap()
+        }
+
+        fn invoke(
+            &mut self,
+            function: Expression,
+            _input: Vec<Expression>,
+        ) -> Result<Expression, RuntimeError> {
+            Err(RuntimeError::Type(format!("cannot call {:?}", function)))
+        }
+
+        fn fork(&self) -> Box<dyn Runtime + Send> {
+            Box::new(TestRuntime::default())
+        }
+    }
 
     #[test]
     fn test1() {
-        Std::Print.run(vec![
-            Expression::String(String::from("Hello ")),
-            Expression::List(vec![
-                Expression::Number(1.0),
-                Expression::Number(2.0),
-                Expression::Number(3.0),
-            ]),
-            Expression::String(String::from("\n")),
-        ]);
+        Std::Print
+            .run(
+                &mut TestRuntime::default(),
+                vec![
+                    Expression::String(String::from("Hello ")),
+                    Expression::List(vec![
+                        Expression::Number(1.0),
+                        Expression::Number(2.0),
+                        Expression::Number(3.0),
+                    ]),
+                    Expression::String(String::from("\n")),
+                ],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test2() {
+        let mut runtime = TestRuntime {
+            context: Mutex::new(Context {
+                args: vec![String::from("a"), String::from("b")],
+                ..Context::default()
+            }),
+        };
+
+        assert_eq!(
+            Std::Args.run(&mut runtime, vec![]),
+            Ok(Expression::List(vec![
+                Expression::String(String::from("a")),
+                Expression::String(String::from("b")),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test3() {
+        let mut runtime = TestRuntime::default();
+        let name = Expression::String(String::from("PLANG_STD_TEST3"));
+
+        Std::SetEnv
+            .run(
+                &mut runtime,
+                vec![name.clone(), Expression::String(String::from("1"))],
+            )
+            .unwrap();
+        assert_eq!(
+            Std::Env.run(&mut runtime, vec![name.clone()]),
+            Ok(Expression::String(String::from("1")))
+        );
+
+        Std::SetEnv
+            .run(&mut runtime, vec![name.clone(), Expression::Nil])
+            .unwrap();
+        assert_eq!(Std::Env.run(&mut runtime, vec![name]), Ok(Expression::Nil));
+    }
+
+    #[test]
+    fn test4() {
+        assert_eq!(
+            Std::Exit.run(&mut TestRuntime::default(), vec![Expression::Number(3.0)]),
+            Err(RuntimeError::Exit(3))
+        );
     }
 }
@@ -0,0 +1,577 @@
+use std::io::{Read, Write};
+use std::process::{Child, ChildStderr, ChildStdout, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::interpreter::{Deadline, Permissions, RuntimeError};
+use crate::parser::ast::Expression;
+
+use super::function::get_print;
+
+/// Options accepted as a trailing map by `cmd`.
+#[derive(Debug, Default)]
+struct CmdOptions {
+    stdin: Option<String>,
+    cwd: Option<String>,
+    env: Vec<(String, Option<String>)>,
+    timeout: Option<Duration>,
+    stream: bool,
+}
+
+impl CmdOptions {
+    fn parse(input: &Expression) -> Result<Self, RuntimeError> {
+        let invalid = |key: &str, value: &Expression| {
+            RuntimeError::Type(format!("invalid cmd option {}: {:?}", key, value))
+        };
+        let option = |key: &str| input.get(key).filter(|v| **v != Expression::Nil);
+
+        let mut options = CmdOptions::default();
+
+        if let Some(v) = option("stdin") {
+            options.stdin = Some(get_print(v.clone())?);
+        }
+
+        if let Some(v) = option("cwd") {
+            options.cwd = Some(get_print(v.clone())?);
+        }
+
+        match option("env") {
+            Some(Expression::Map(vars)) => {
+                for (name, value) in vars {
+                    let value = match value {
+                        Expression::Nil => None,
+                        t => Some(get_print(t.clone())?),
+                    };
+                    options.env.push((get_print(name.clone())?, value));
+                }
+            }
+            Some(t) => return Err(invalid("env", t)),
+            None => {}
+        }
+
+        match option("timeout") {
+            Some(Expression::Number(ms)) if *ms >= 0.0 => {
+                options.timeout = Some(Duration::from_millis(*ms as u64))
+            }
+            Some(t) => return Err(invalid("timeout", t)),
+            None => {}
+        }
+
+        match option("stream") {
+            Some(Expression::Boolean(b)) => options.stream = *b,
+            Some(t) => return Err(invalid("stream", t)),
+            None => {}
+        }
+
+        Ok(options)
+    }
+
+    fn check(&self, permissions: &Permissions) -> Result<(), RuntimeError> {
+        if let Some(cwd) = &self.cwd {
+            permissions.check_cwd(cwd)?;
+        }
+        self.env
+            .iter()
+            .try_for_each(|(name, _)| permissions.check_env(name))
+    }
+
+    fn apply(&self, command: &mut Command) {
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+
+        for (name, value) in &self.env {
+            match value {
+                Some(value) => command.env(name, value),
+                None => command.env_remove(name),
+            };
+        }
+    }
+
+    /// The earlier of the timeout and the program's `limit`.
+    fn deadline(&self, limit: Option<Deadline>) -> Option<Instant> {
+        let timeout = self.timeout.and_then(|t| Instant::now().checked_add(t));
+        timeout.into_iter().chain(limit.map(|d| d.at)).min()
+    }
+}
+
+/// What a finished process left behind.
+struct Output {
+    stdout: Option<String>,
+    stderr: Option<String>,
+    code: Option<i32>,
+    timed_out: bool,
+}
+
+impl Output {
+    fn entries(self) -> Vec<(&'static str, Expression)> {
+        vec![
+            (
+                "stdout",
+                self.stdout.map_or(Expression::Nil, Expression::String),
+            ),
+            (
+                "stderr",
+                self.stderr.map_or(Expression::Nil, Expression::String),
+            ),
+            (
+                "code",
+                self.code
+                    .map_or(Expression::Nil, |c| Expression::Number(c as f64)),
+            ),
+            ("timed_out", Expression::Boolean(self.timed_out)),
+        ]
+    }
+}
+
+fn read_to_string<R: Read + Send + 'static>(reader: Option<R>) -> JoinHandle<Option<String>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        reader?.read_to_end(&mut buf).ok()?;
+        Some(String::from_utf8_lossy(&buf).into_owned())
+    })
+}
+
+fn write_stdin(child: &mut Child, stdin: Option<String>) {
+    if let (Some(mut pipe), Some(data)) = (child.stdin.take(), stdin) {
+        thread::spawn(move || {
+            // the child may exit without reading its input
+            pipe.write_all(data.as_bytes()).ok();
+        });
+    }
+}
+
+/// Waits for `child`, killing it once `deadline` has passed.
+fn wait(child: &mut Child, deadline: Option<Instant>) -> Result<(Option<i32>, bool), RuntimeError> {
+    let io = |e: std::io::Error| RuntimeError::Io(e.to_string());
+
+    let deadline = match deadline {
+        Some(t) => t,
+        None => return Ok((child.wait().map_err(io)?.code(), false)),
+    };
+
+    loop {
+        if let Some(status) = child.try_wait().map_err(io)? {
+            return Ok((status.code(), false));
+        }
+
+        if Instant::now() >= deadline {
+            child.kill().ok();
+            child.wait().map_err(io)?;
+            return Ok((None, true));
+        }
+
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn collect(output: JoinHandle<Option<String>>) -> Option<String> {
+    output.join().ok().flatten()
+}
+
+fn spawn(command: &mut Command, program: &str) -> Result<Child, RuntimeError> {
+    command
+        .spawn()
+        .map_err(|e| RuntimeError::Io(format!("cannot run `{}`: {}", program, e)))
+}
+
+fn split_options(input: &mut Vec<Expression>) -> Result<CmdOptions, RuntimeError> {
+    match input.last() {
+        Some(Expression::Map(_)) => CmdOptions::parse(&input.pop().unwrap()),
+        _ => Ok(CmdOptions::default()),
+    }
+}
+
+fn command_line(input: Vec<Expression>) -> Result<(String, Vec<String>), RuntimeError> {
+    let mut t = input
+        .into_iter()
+        .map(get_print)
+        .collect::<Result<Vec<String>, RuntimeError>>()?
+        .into_iter();
+
+    match t.next() {
+        Some(program) => Ok((program, t.collect())),
+        None => Err(RuntimeError::Type(String::from("cmd needs a program"))),
+    }
+}
+
+pub fn run_cmd(
+    permissions: &Permissions,
+    limit: Option<Deadline>,
+    mut input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let options = split_options(&mut input)?;
+    let (program, args) = command_line(input)?;
+    let path = permissions.check_cmd(&program)?;
+    options.check(permissions)?;
+
+    let mut command = Command::new(path);
+    command.args(args);
+    options.apply(&mut command);
+
+    if options.stdin.is_some() {
+        command.stdin(Stdio::piped());
+    }
+
+    if !options.stream {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+
+    let deadline = options.deadline(limit);
+    let mut child = spawn(&mut command, &program)?;
+    write_stdin(&mut child, options.stdin);
+
+    let stdout = read_to_string::<ChildStdout>(child.stdout.take());
+    let stderr = read_to_string::<ChildStderr>(child.stderr.take());
+
+    let (code, timed_out) = wait(&mut child, deadline)?;
+    if timed_out {
+        limit.map_or(Ok(()), |d| d.check())?;
+    }
+
+    Ok(Expression::map(
+        Output {
+            stdout: collect(stdout),
+            stderr: collect(stderr),
+            code,
+            timed_out,
+        }
+        .entries(),
+    ))
+}
+
+/// Connects every stage's stdout to the next stage's stdin, without a shell.
+pub fn run_pipe(
+    permissions: &Permissions,
+    limit: Option<Deadline>,
+    mut input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let options = split_options(&mut input)?;
+
+    let stages = match input.as_slice() {
+        [Expression::List(stages)] if !stages.is_empty() => stages
+            .iter()
+            .map(|stage| match stage {
+                Expression::List(stage) => command_line(stage.clone()),
+                t => Err(RuntimeError::Type(format!(
+                    "pipe stage must be a list, found {:?}",
+                    t
+                ))),
+            })
+            .collect::<Result<Vec<(String, Vec<String>)>, RuntimeError>>()?,
+        _ => {
+            return Err(RuntimeError::Type(String::from(
+                "pipe expects a list of commands",
+            )))
+        }
+    };
+
+    let paths = stages
+        .iter()
+        .map(|(program, _)| permissions.check_cmd(program))
+        .collect::<Result<Vec<_>, RuntimeError>>()?;
+    options.check(permissions)?;
+
+    let deadline = options.deadline(limit);
+    let len = stages.len();
+    let mut children: Vec<Child> = Vec::new();
+    let mut stderrs = Vec::new();
+    let mut previous: Option<ChildStdout> = None;
+
+    for (i, (stage, path)) in stages.into_iter().zip(paths).enumerate() {
+        let last = i + 1 == len;
+
+        let (program, args) = stage;
+
+        let mut command = Command::new(path);
+        command.args(args);
+        options.apply(&mut command);
+
+        match previous.take() {
+            Some(out) => {
+                command.stdin(Stdio::from(out));
+            }
+            None if options.stdin.is_some() => {
+                command.stdin(Stdio::piped());
+            }
+            None => {}
+        }
+
+        if !last || !options.stream {
+            command.stdout(Stdio::piped());
+        }
+
+        if !options.stream {
+            command.stderr(Stdio::piped());
+        }
+
+        let mut child = match spawn(&mut command, &program) {
+            Ok(child) => child,
+            Err(e) => {
+                for mut child in children {
+                    child.kill().ok();
+                    child.wait().ok();
+                }
+                return Err(e);
+            }
+        };
+
+        if i == 0 {
+            write_stdin(&mut child, options.stdin.clone());
+        }
+
+        stderrs.push(read_to_string::<ChildStderr>(child.stderr.take()));
+        if !last {
+            previous = child.stdout.take();
+        }
+        children.push(child);
+    }
+
+    let stdout = read_to_string::<ChildStdout>(children[len - 1].stdout.take());
+
+    let mut codes = Vec::new();
+    let mut timed_out = false;
+    for child in children.iter_mut() {
+        let (code, t) = wait(child, deadline)?;
+        codes.push(code);
+        timed_out |= t;
+    }
+    if timed_out {
+        limit.map_or(Ok(()), |d| d.check())?;
+    }
+
+    let stderr = stderrs
+        .into_iter()
+        .map(collect)
+        .collect::<Option<Vec<String>>>()
+        .map(|s| s.concat());
+
+    let mut entries = Output {
+        stdout: collect(stdout),
+        stderr,
+        code: codes[len - 1],
+        timed_out,
+    }
+    .entries();
+
+    entries.push((
+        "codes",
+        Expression::List(
+            codes
+                .into_iter()
+                .map(|c| c.map_or(Expression::Nil, |c| Expression::Number(c as f64)))
+                .collect(),
+        ),
+    ));
+
+    Ok(Expression::map(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Expression {
+        Expression::String(String::from(s))
+    }
+
+    #[test]
+    fn test1() {
+        let out = run_cmd(
+            &Permissions::default(),
+            None,
+            vec![
+                string("sh"),
+                string("-c"),
+                string("echo hi; echo no >&2; exit 3"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(out.get("stdout"), Some(&string("hi\n")));
+        assert_eq!(out.get("stderr"), Some(&string("no\n")));
+        assert_eq!(out.get("code"), Some(&Expression::Number(3.0)));
+        assert_eq!(out.get("timed_out"), Some(&Expression::Boolean(false)));
+    }
+
+    #[test]
+    fn test2() {
+        let out = run_cmd(
+            &Permissions::default(),
+            None,
+            vec![
+                string("sh"),
+                string("-c"),
+                string("pwd; cat; echo $PLANG_CMD_TEST2"),
+                Expression::map(vec![
+                    ("stdin", string("data\n")),
+                    ("cwd", string("/")),
+                    (
+                        "env",
+                        Expression::map(vec![("PLANG_CMD_TEST2", string("set"))]),
+                    ),
+                ]),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(out.get("stdout"), Some(&string("/\ndata\nset\n")));
+        assert_eq!(out.get("code"), Some(&Expression::Number(0.0)));
+    }
+
+    #[test]
+    fn test3() {
+        let out = run_cmd(
+            &Permissions::default(),
+            None,
+            vec![
+                string("sleep"),
+                string("5"),
+                Expression::map(vec![("timeout", Expression::Number(50.0))]),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(out.get("code"), Some(&Expression::Nil));
+        assert_eq!(out.get("timed_out"), Some(&Expression::Boolean(true)));
+    }
+
+    #[test]
+    fn test4() {
+        assert!(matches!(
+            run_cmd(
+                &Permissions::default(),
+                None,
+                vec![string("plang-no-such-program")]
+            ),
+            Err(RuntimeError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test5() {
+        let stage = |args: &[&str]| Expression::List(args.iter().map(|a| string(a)).collect());
+
+        let out = run_pipe(
+            &Permissions::default(),
+            None,
+            vec![
+                Expression::List(vec![
+                    stage(&["cat"]),
+                    stage(&["sort"]),
+                    stage(&["uniq"]),
+                    stage(&["sh", "-c", "cat; exit 4"]),
+                ]),
+                Expression::map(vec![("stdin", string("b\na\nb\n"))]),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(out.get("stdout"), Some(&string("a\nb\n")));
+        assert_eq!(out.get("code"), Some(&Expression::Number(4.0)));
+        assert_eq!(
+            out.get("codes"),
+            Some(&Expression::List(vec![
+                Expression::Number(0.0),
+                Expression::Number(0.0),
+                Expression::Number(0.0),
+                Expression::Number(4.0),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test6() {
+        let stage = |args: &[&str]| Expression::List(args.iter().map(|a| string(a)).collect());
+
+        assert!(matches!(
+            run_pipe(
+                &Permissions::default(),
+                None,
+                vec![Expression::List(vec![
+                    stage(&["sleep", "5"]),
+                    stage(&["plang-no-such-program"]),
+                ])]
+            ),
+            Err(RuntimeError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test7() {
+        let mut permissions = Permissions::default();
+        permissions.cmd.allow("echo");
+
+        assert!(run_cmd(&permissions, None, vec![string("echo")]).is_ok());
+        assert!(matches!(
+            run_cmd(&permissions, None, vec![string("ls")]),
+            Err(RuntimeError::Permission(_))
+        ));
+        assert!(matches!(
+            run_pipe(
+                &permissions,
+                None,
+                vec![Expression::List(vec![
+                    Expression::List(vec![string("echo")]),
+                    Expression::List(vec![string("rm"), string("-rf"), string("/tmp/x")]),
+                ])]
+            ),
+            Err(RuntimeError::Permission(_))
+        ));
+    }
+
+    #[test]
+    fn test8() {
+        let mut permissions = Permissions::default();
+        permissions.cmd.allow("env");
+        let path = |options| {
+            run_cmd(
+                &permissions,
+                None,
+                vec![string("env"), Expression::map(vec![("env", options)])],
+            )
+        };
+
+        assert!(matches!(
+            path(Expression::map(vec![("PATH", string("/tmp"))])),
+            Err(RuntimeError::Permission(_))
+        ));
+        assert!(matches!(
+            path(Expression::map(vec![("PATH", Expression::Nil)])),
+            Err(RuntimeError::Permission(_))
+        ));
+        assert!(matches!(
+            run_cmd(&permissions, None, vec![string("/usr/bin/env")]),
+            Err(RuntimeError::Permission(_))
+        ));
+    }
+
+    #[test]
+    fn test9() {
+        let mut permissions = Permissions::default();
+        permissions.cmd.allow("env");
+        permissions.read.allow("/tmp");
+        permissions.write.allow("/tmp");
+        permissions.sandbox();
+        let run = |options| {
+            run_cmd(
+                &permissions,
+                None,
+                vec![string("env"), Expression::map(options)],
+            )
+        };
+
+        assert!(matches!(
+            run(vec![("cwd", string("/"))]),
+            Err(RuntimeError::Permission(_))
+        ));
+        assert!(matches!(
+            run(vec![(
+                "env",
+                Expression::map(vec![("LD_PRELOAD", string("/tmp/x.so"))])
+            )]),
+            Err(RuntimeError::Permission(_))
+        ));
+        assert!(run(vec![("cwd", string("/tmp"))]).is_ok());
+    }
+}
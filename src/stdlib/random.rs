@@ -0,0 +1,145 @@
+use crate::interpreter::{Context, Rng, RuntimeError};
+use crate::parser::ast::Expression;
+
+fn integer(name: &str, value: &Expression) -> Result<i64, RuntimeError> {
+    match value {
+        Expression::Number(n) if n.fract() == 0.0 && n.abs() < 2f64.powi(53) => Ok(*n as i64),
+        t => Err(RuntimeError::Type(format!(
+            "{} expects integers, found {:?}",
+            name, t
+        ))),
+    }
+}
+
+pub fn run_random(
+    context: &mut Context,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    if !input.is_empty() {
+        return Err(RuntimeError::Type(String::from(
+            "random expects no arguments",
+        )));
+    }
+    Ok(Expression::Number(context.rng.next_f64()))
+}
+
+/// An integer between both bounds, inclusive.
+pub fn run_random_int(
+    context: &mut Context,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let (low, high) = match input.as_slice() {
+        [low, high] => (integer("random_int", low)?, integer("random_int", high)?),
+        _ => {
+            return Err(RuntimeError::Type(String::from(
+                "random_int expects two integers",
+            )))
+        }
+    };
+
+    if low > high {
+        return Err(RuntimeError::Type(format!(
+            "random_int expects {} <= {}",
+            low, high
+        )));
+    }
+
+    let offset = context.rng.below((high - low) as u64 + 1);
+    Ok(Expression::Number((low + offset as i64) as f64))
+}
+
+/// A random item, `nil` when the list is empty.
+pub fn run_choice(
+    context: &mut Context,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::List(items)] if items.is_empty() => Ok(Expression::Nil),
+        [Expression::List(items)] => {
+            Ok(items[context.rng.below(items.len() as u64) as usize].clone())
+        }
+        _ => Err(RuntimeError::Type(format!(
+            "choice expects a list, found {:?}",
+            input
+        ))),
+    }
+}
+
+/// Shuffles in place with Fisher-Yates.
+pub fn run_shuffle(
+    context: &mut Context,
+    list: &mut [Expression],
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    if !input.is_empty() {
+        return Err(RuntimeError::Type(String::from("shuffle expects a list")));
+    }
+
+    for i in (1..list.len()).rev() {
+        let j = context.rng.below(i as u64 + 1) as usize;
+        list.swap(i, j);
+    }
+    Ok(Expression::Nil)
+}
+
+pub fn run_seed(context: &mut Context, input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [seed] => {
+            context.rng = Rng::new(integer("seed", seed)? as u64);
+            Ok(Expression::Nil)
+        }
+        _ => Err(RuntimeError::Type(String::from("seed expects an integer"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seeded(seed: u64) -> Context {
+        Context {
+            rng: Rng::new(seed),
+            ..Default::default()
+        }
+    }
+
+    fn numbers(items: &[f64]) -> Vec<Expression> {
+        items.iter().map(|n| Expression::Number(*n)).collect()
+    }
+
+    #[test]
+    fn test1() {
+        let mut a = seeded(7);
+        let mut b = Context::default();
+        run_seed(&mut b, numbers(&[7.0])).unwrap();
+
+        for _ in 0..100 {
+            let x = run_random_int(&mut a, numbers(&[-2.0, 2.0])).unwrap();
+            assert_eq!(run_random_int(&mut b, numbers(&[-2.0, 2.0])), Ok(x.clone()));
+            assert!(matches!(x, Expression::Number(n) if (-2.0..=2.0).contains(&n)));
+        }
+        assert_eq!(run_random(&mut a, vec![]), run_random(&mut b, vec![]));
+        assert!(run_random_int(&mut a, numbers(&[3.0, 1.0])).is_err());
+    }
+
+    #[test]
+    fn test2() {
+        let mut context = seeded(1);
+        let mut list = numbers(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+
+        run_shuffle(&mut context, &mut list, vec![]).unwrap();
+        let mut sorted = list.clone();
+        sorted.sort_by(|a, b| match (a, b) {
+            (Expression::Number(a), Expression::Number(b)) => a.total_cmp(b),
+            _ => unreachable!(),
+        });
+        assert_eq!(sorted, numbers(&[1.0, 2.0, 3.0, 4.0, 5.0]));
+
+        let item = run_choice(&mut context, vec![Expression::List(list.clone())]).unwrap();
+        assert!(list.contains(&item));
+        assert_eq!(
+            run_choice(&mut context, vec![Expression::List(vec![])]),
+            Ok(Expression::Nil)
+        );
+    }
+}
@@ -0,0 +1,452 @@
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use http::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+
+use crate::interpreter::{Deadline, RuntimeError};
+use crate::parser::ast::Expression;
+
+use super::function::get_print;
+use super::http::{headers_to_map, map_to_headers, read_message};
+use super::Runtime;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Options accepted as a trailing map by `serve`.
+#[derive(Debug)]
+struct ServeOptions {
+    host: String,
+    /// Stop after answering this many requests.
+    requests: Option<usize>,
+    /// How long a client may take to send its request or read the response.
+    timeout: Duration,
+    /// Called with the bound port once the server listens.
+    ready: Option<Expression>,
+}
+
+impl ServeOptions {
+    fn parse(input: Option<&Expression>) -> Result<Self, RuntimeError> {
+        let mut options = ServeOptions {
+            host: String::from("127.0.0.1"),
+            requests: None,
+            timeout: DEFAULT_TIMEOUT,
+            ready: None,
+        };
+
+        let input = match input {
+            Some(t @ Expression::Map(_)) => t,
+            None => return Ok(options),
+            Some(t) => return Err(invalid("options", t)),
+        };
+
+        match input.get("host") {
+            Some(Expression::String(host)) => options.host = host.to_string(),
+            Some(Expression::Nil) | None => {}
+            Some(t) => return Err(invalid("host", t)),
+        }
+
+        match input.get("requests") {
+            Some(Expression::Number(n)) if *n >= 0.0 => options.requests = Some(*n as usize),
+            Some(Expression::Nil) | None => {}
+            Some(t) => return Err(invalid("requests", t)),
+        }
+
+        match input.get("timeout") {
+            Some(Expression::Number(ms)) if *ms > 0.0 => {
+                options.timeout = Duration::from_millis(*ms as u64)
+            }
+            Some(Expression::Nil) | None => {}
+            Some(t) => return Err(invalid("timeout", t)),
+        }
+
+        options.ready = input
+            .get("ready")
+            .filter(|f| **f != Expression::Nil)
+            .cloned();
+
+        Ok(options)
+    }
+}
+
+fn invalid(name: &str, value: &Expression) -> RuntimeError {
+    RuntimeError::Type(format!("invalid serve {}: {:?}", name, value))
+}
+
+fn io(e: std::io::Error) -> RuntimeError {
+    RuntimeError::Io(e.to_string())
+}
+
+/// Decodes `+` and `%XX` escapes from a query string component.
+fn decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 3 <= bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(b) => {
+                        out.push(b);
+                        i += 2;
+                    }
+                    Err(_) => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// A reader that remembers whether a read timed out.
+struct Timed<R> {
+    inner: R,
+    timed_out: bool,
+}
+
+impl<R: Read> Read for Timed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let out = self.inner.read(buf);
+        if let Err(e) = &out {
+            self.timed_out |= matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            );
+        }
+        out
+    }
+}
+
+fn read_request(reader: impl Read) -> Result<Request<Vec<u8>>, RuntimeError> {
+    let (start, headers, body) = read_message(&mut BufReader::new(reader), false)?;
+
+    let mut parts = start.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return Err(RuntimeError::Io(format!("bad request line `{}`", start))),
+    };
+
+    let mut request = Request::builder()
+        .method(method)
+        .uri(target)
+        .body(body)
+        .map_err(|e| RuntimeError::Io(e.to_string()))?;
+    *request.headers_mut() = headers;
+    Ok(request)
+}
+
+fn request_to_map(request: &Request<Vec<u8>>) -> Expression {
+    let query = request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (k, v) = p.split_once('=').unwrap_or((p, ""));
+            (Expression::String(decode(k)), Expression::String(decode(v)))
+        })
+        .collect();
+
+    Expression::map(vec![
+        ("method", Expression::String(request.method().to_string())),
+        ("path", Expression::String(request.uri().path().to_string())),
+        ("query", Expression::Map(query)),
+        ("headers", headers_to_map(request.headers())),
+        (
+            "body",
+            Expression::String(String::from_utf8_lossy(request.body()).into_owned()),
+        ),
+    ])
+}
+
+fn text(status: StatusCode, body: &str) -> Response<Vec<u8>> {
+    let mut response = Response::new(body.as_bytes().to_vec());
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+}
+
+/// Turns a handler's return value into a response.
+///
+/// A string becomes a `200` text body, `nil` a `204` and a map may set
+/// `status`, `headers` and `body`.
+fn value_to_response(value: Expression) -> Result<Response<Vec<u8>>, RuntimeError> {
+    match value {
+        Expression::String(body) => Ok(text(StatusCode::OK, &body)),
+        Expression::Nil => Ok(text(StatusCode::NO_CONTENT, "")),
+        t @ Expression::Map(_) => {
+            let status = match t.get("status") {
+                Some(Expression::Number(n)) => StatusCode::from_u16(*n as u16)
+                    .map_err(|_| invalid("status", &Expression::Number(*n)))?,
+                Some(Expression::Nil) | None => StatusCode::OK,
+                Some(s) => return Err(invalid("status", s)),
+            };
+
+            let body = match t.get("body") {
+                Some(Expression::Nil) | None => String::new(),
+                Some(b) => get_print(b.clone())?,
+            };
+
+            let mut response = text(status, &body);
+            for (name, value) in map_to_headers(t.get("headers").unwrap_or(&Expression::Nil))? {
+                if let Some(name) = name {
+                    response.headers_mut().insert(name, value);
+                }
+            }
+            Ok(response)
+        }
+        t => Err(invalid("response", &t)),
+    }
+}
+
+fn write_response(stream: &mut TcpStream, response: Response<Vec<u8>>) {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nConnection: close\r\n",
+        response.status().as_u16(),
+        response.status().canonical_reason().unwrap_or_default()
+    );
+    for (name, value) in response.headers() {
+        if name != CONTENT_LENGTH {
+            head.push_str(&format!(
+                "{}: {}\r\n",
+                name,
+                String::from_utf8_lossy(value.as_bytes())
+            ));
+        }
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\n\r\n",
+        response.body().len()
+    ));
+
+    // the client may already be gone
+    stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(response.body()))
+        .ok();
+}
+
+/// Picks the handler for `path`; a map routes by exact path with `*` as fallback.
+fn route<'a>(handler: &'a Expression, path: &str) -> Option<&'a Expression> {
+    match handler {
+        Expression::Map(routes) => routes
+            .iter()
+            .find(|(k, _)| matches!(k, Expression::String(k) if k == path))
+            .or_else(|| {
+                routes
+                    .iter()
+                    .find(|(k, _)| matches!(k, Expression::String(k) if k == "*"))
+            })
+            .map(|(_, v)| v),
+        t => Some(t),
+    }
+}
+
+fn handle(
+    runtime: &mut dyn Runtime,
+    handler: &Expression,
+    mut stream: TcpStream,
+    timeout: Duration,
+) -> Result<(), RuntimeError> {
+    // An idle client must not hold up the ones behind it.
+    stream.set_read_timeout(Some(timeout)).map_err(io)?;
+    stream.set_write_timeout(Some(timeout)).map_err(io)?;
+
+    let mut reader = Timed {
+        inner: &stream,
+        timed_out: false,
+    };
+    let request = match read_request(&mut reader) {
+        Ok(request) => request,
+        Err(_) if reader.timed_out => {
+            write_response(
+                &mut stream,
+                text(StatusCode::REQUEST_TIMEOUT, "request timeout"),
+            );
+            return Ok(());
+        }
+        Err(_) => {
+            write_response(&mut stream, text(StatusCode::BAD_REQUEST, "bad request"));
+            return Ok(());
+        }
+    };
+
+    let response = match route(handler, request.uri().path()) {
+        Some(f) => match runtime
+            .invoke(f.clone(), vec![request_to_map(&request)])
+            .and_then(value_to_response)
+        {
+            Ok(response) => response,
+            Err(e) if !e.catchable() => return Err(e),
+            Err(e) => text(StatusCode::INTERNAL_SERVER_ERROR, &e.report(&[])),
+        },
+        None => text(StatusCode::NOT_FOUND, "not found"),
+    };
+
+    write_response(&mut stream, response);
+    Ok(())
+}
+
+/// The next connection, or the program's `limit` running out first.
+fn accept(listener: &TcpListener, limit: Option<Deadline>) -> Result<TcpStream, RuntimeError> {
+    let deadline = match limit {
+        Some(t) => t,
+        None => return listener.accept().map(|(stream, _)| stream).map_err(io),
+    };
+
+    listener.set_nonblocking(true).map_err(io)?;
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false).map_err(io)?;
+                return Ok(stream);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                deadline.check()?;
+                thread::sleep(Duration::from_millis(5));
+            }
+            Err(e) => return Err(io(e)),
+        }
+    }
+}
+
+/// `serve(port, handler, options?)`
+pub fn run_serve(
+    runtime: &mut dyn Runtime,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let (port, handler) = match input.as_slice() {
+        [Expression::Number(port), handler, ..] if (0.0..=65535.0).contains(port) => {
+            (*port as u16, handler.clone())
+        }
+        _ => {
+            return Err(RuntimeError::Type(String::from(
+                "serve expects a port and a handler",
+            )))
+        }
+    };
+    let options = ServeOptions::parse(input.get(2))?;
+
+    runtime
+        .context()
+        .permissions
+        .check_net(&options.host, port)?;
+
+    let listener = TcpListener::bind((options.host.as_str(), port)).map_err(io)?;
+
+    if let Some(ready) = options.ready {
+        let port = listener.local_addr().map_err(io)?.port();
+        runtime.invoke(ready, vec![Expression::Number(port as f64)])?;
+    }
+
+    let mut served = 0;
+    while options.requests != Some(served) {
+        let limit = runtime.deadline();
+        let stream = accept(&listener, limit)?;
+        let timeout = limit.map_or(options.timeout, |d| d.clamp(options.timeout));
+        handle(runtime, &handler, stream, timeout)?;
+        served += 1;
+    }
+
+    Ok(Expression::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Expression {
+        Expression::String(String::from(s))
+    }
+
+    #[test]
+    fn test1() {
+        assert_eq!(decode("a+b%20c%2"), "a b c%2");
+        assert_eq!(decode("%E2%9C%93"), "✓");
+    }
+
+    #[test]
+    fn test2() {
+        let routes = Expression::map(vec![("/a", string("a")), ("*", string("any"))]);
+
+        assert_eq!(route(&routes, "/a"), Some(&string("a")));
+        assert_eq!(route(&routes, "/b"), Some(&string("any")));
+        assert_eq!(
+            route(&Expression::map(vec![("/a", string("a"))]), "/b"),
+            None
+        );
+    }
+
+    #[test]
+    fn test3() {
+        let response = value_to_response(Expression::map(vec![
+            ("status", Expression::Number(201.0)),
+            ("headers", Expression::map(vec![("X-Id", string("7"))])),
+            ("body", string("made")),
+        ]))
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["x-id"], "7");
+        assert_eq!(response.body(), b"made");
+        assert!(value_to_response(Expression::Number(1.0)).is_err());
+    }
+
+    #[test]
+    fn test4() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut idle = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        handle(
+            &mut super::super::tests::TestRuntime::default(),
+            &string("hi"),
+            stream,
+            Duration::from_millis(50),
+        )
+        .unwrap();
+
+        let mut response = String::new();
+        idle.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 "), "{}", response);
+    }
+
+    #[test]
+    fn test5() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+
+        handle(
+            &mut super::super::tests::TestRuntime::default(),
+            &string("hi"),
+            stream,
+            Duration::from_secs(1),
+        )
+        .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 500 "), "{}", response);
+        assert!(
+            response.ends_with("\r\n\r\nerror: type error: cannot call String(\"hi\")\n"),
+            "{}",
+            response
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use crate::interpreter::RuntimeError;
+use crate::parser::ast::Expression;
+
+use super::function::display;
+
+/// The longest string `repeat` builds, in bytes.
+const MAX_REPEAT: usize = 1 << 28;
+
+fn expected(name: &str, what: &str, input: &[Expression]) -> RuntimeError {
+    RuntimeError::Type(format!("{} expects {}, found {:?}", name, what, input))
+}
+
+fn string<'a>(name: &str, input: &'a [Expression]) -> Result<&'a str, RuntimeError> {
+    match input {
+        [Expression::String(s)] => Ok(s),
+        _ => Err(expected(name, "a string", input)),
+    }
+}
+
+fn strings<'a>(name: &str, input: &'a [Expression]) -> Result<(&'a str, &'a str), RuntimeError> {
+    match input {
+        [Expression::String(a), Expression::String(b)] => Ok((a, b)),
+        _ => Err(expected(name, "two strings", input)),
+    }
+}
+
+fn list(items: impl Iterator<Item = String>) -> Expression {
+    Expression::List(items.map(Expression::String).collect())
+}
+
+/// Length in characters, not bytes.
+pub fn run_len(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let s = string("len", &input)?;
+    Ok(Expression::Number(s.chars().count() as f64))
+}
+
+pub fn run_upper(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    Ok(Expression::String(string("upper", &input)?.to_uppercase()))
+}
+
+pub fn run_lower(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    Ok(Expression::String(string("lower", &input)?.to_lowercase()))
+}
+
+pub fn run_trim(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    Ok(Expression::String(
+        string("trim", &input)?.trim().to_string(),
+    ))
+}
+
+/// Splits on a separator, or on runs of whitespace when none is given.
+pub fn run_split(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::String(s)] => Ok(list(s.split_whitespace().map(String::from))),
+        [Expression::String(s), Expression::String(sep)] if sep.is_empty() => {
+            Ok(list(s.chars().map(String::from)))
+        }
+        [Expression::String(s), Expression::String(sep)] => {
+            Ok(list(s.split(sep.as_str()).map(String::from)))
+        }
+        _ => Err(expected(
+            "split",
+            "a string and an optional separator",
+            &input,
+        )),
+    }
+}
+
+pub fn run_join(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let (items, sep) = match input.as_slice() {
+        [Expression::List(items)] => (items, ""),
+        [Expression::List(items), Expression::String(sep)] => (items, sep.as_str()),
+        _ => return Err(expected("join", "a list and a separator", &input)),
+    };
+
+    let items: Vec<String> = items.iter().map(display).collect();
+    Ok(Expression::String(items.join(sep)))
+}
+
+pub fn run_replace(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::String(s), Expression::String(from), Expression::String(to)] => {
+            Ok(Expression::String(s.replace(from.as_str(), to)))
+        }
+        _ => Err(expected("replace", "three strings", &input)),
+    }
+}
+
+pub fn run_contains(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let (s, part) = strings("contains", &input)?;
+    Ok(Expression::Boolean(s.contains(part)))
+}
+
+pub fn run_starts_with(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let (s, part) = strings("starts_with", &input)?;
+    Ok(Expression::Boolean(s.starts_with(part)))
+}
+
+pub fn run_ends_with(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let (s, part) = strings("ends_with", &input)?;
+    Ok(Expression::Boolean(s.ends_with(part)))
+}
+
+/// Character index of the first match, `nil` when there is none.
+pub fn run_find(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let (s, part) = strings("find", &input)?;
+    Ok(match s.find(part) {
+        Some(byte) => Expression::Number(s[..byte].chars().count() as f64),
+        None => Expression::Nil,
+    })
+}
+
+pub fn run_repeat(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::String(s), Expression::Number(n)] if *n >= 0.0 && n.fract() == 0.0 => {
+            match s.len().checked_mul(*n as usize) {
+                Some(len) if len <= MAX_REPEAT => Ok(Expression::String(s.repeat(*n as usize))),
+                _ => Err(RuntimeError::Type(format!(
+                    "repeat would build more than {} bytes",
+                    MAX_REPEAT
+                ))),
+            }
+        }
+        _ => Err(expected("repeat", "a string and a count", &input)),
+    }
+}
+
+pub fn run_chars(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    Ok(list(string("chars", &input)?.chars().map(String::from)))
+}
+
+pub fn run_to_string(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [value] => Ok(Expression::String(display(value))),
+        _ => Err(expected("to_string", "one value", &input)),
+    }
+}
+
+pub fn run_to_number(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::Number(n)] => Ok(Expression::Number(*n)),
+        [Expression::String(s)] => s
+            .trim()
+            .parse()
+            .map(Expression::Number)
+            .map_err(|_| RuntimeError::Type(format!("cannot convert {:?} to a number", s))),
+        _ => Err(expected("to_number", "a string", &input)),
+    }
+}
+
+pub fn run_parse_bool(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match string("parse_bool", &input)?.trim() {
+        "true" => Ok(Expression::Boolean(true)),
+        "false" => Ok(Expression::Boolean(false)),
+        s => Err(RuntimeError::Type(format!(
+            "cannot convert {:?} to a boolean",
+            s
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Expression {
+        Expression::String(String::from(s))
+    }
+
+    fn strings(items: &[&str]) -> Expression {
+        Expression::List(items.iter().map(|s| string(s)).collect())
+    }
+
+    #[test]
+    fn test1() {
+        assert_eq!(
+            run_len(vec![string("héllo😀")]),
+            Ok(Expression::Number(6.0))
+        );
+        assert_eq!(run_upper(vec![string("straße")]), Ok(string("STRASSE")));
+        assert_eq!(run_lower(vec![string("ÀB")]), Ok(string("àb")));
+        assert_eq!(run_trim(vec![string("  a b \n")]), Ok(string("a b")));
+        assert_eq!(
+            run_chars(vec![string("aé😀")]),
+            Ok(strings(&["a", "é", "😀"]))
+        );
+        assert_eq!(
+            run_find(vec![string("日本語です"), string("語")]),
+            Ok(Expression::Number(2.0))
+        );
+        assert_eq!(
+            run_find(vec![string("abc"), string("x")]),
+            Ok(Expression::Nil)
+        );
+        assert!(run_len(vec![Expression::Number(1.0)]).is_err());
+    }
+
+    #[test]
+    fn test2() {
+        assert_eq!(
+            run_split(vec![string(" a  b\tc ")]),
+            Ok(strings(&["a", "b", "c"]))
+        );
+        assert_eq!(
+            run_split(vec![string("a,b,,c"), string(",")]),
+            Ok(strings(&["a", "b", "", "c"]))
+        );
+        assert_eq!(
+            run_split(vec![string("añ"), string("")]),
+            Ok(strings(&["a", "ñ"]))
+        );
+        assert_eq!(
+            run_join(vec![
+                Expression::List(vec![string("a"), Expression::Number(1.0)]),
+                string("-")
+            ]),
+            Ok(string("a-1"))
+        );
+        assert_eq!(
+            run_replace(vec![string("a.b.c"), string("."), string("::")]),
+            Ok(string("a::b::c"))
+        );
+        assert_eq!(
+            run_repeat(vec![string("ab"), Expression::Number(3.0)]),
+            Ok(string("ababab"))
+        );
+        assert!(run_repeat(vec![string("ab"), Expression::Number(-1.0)]).is_err());
+        assert_eq!(
+            run_starts_with(vec![string("plang"), string("pl")]),
+            Ok(Expression::Boolean(true))
+        );
+        assert_eq!(
+            run_ends_with(vec![string("plang"), string("pl")]),
+            Ok(Expression::Boolean(false))
+        );
+        assert_eq!(
+            run_contains(vec![string("plang"), string("an")]),
+            Ok(Expression::Boolean(true))
+        );
+    }
+
+    #[test]
+    fn test3() {
+        assert_eq!(
+            run_to_number(vec![string(" 2.5 ")]),
+            Ok(Expression::Number(2.5))
+        );
+        assert_eq!(
+            run_to_number(vec![string("abc")]),
+            Err(RuntimeError::Type(String::from(
+                "cannot convert \"abc\" to a number"
+            )))
+        );
+        assert_eq!(
+            run_parse_bool(vec![string("true")]),
+            Ok(Expression::Boolean(true))
+        );
+        assert!(run_parse_bool(vec![string("yes")]).is_err());
+        assert_eq!(
+            run_to_string(vec![Expression::List(vec![
+                Expression::Number(1.0),
+                Expression::Nil
+            ])]),
+            Ok(string("[ 1, nil ]"))
+        );
+    }
+
+    #[test]
+    fn test4() {
+        let count = |n: f64| vec![string("ab"), Expression::Number(n)];
+
+        assert_eq!(run_repeat(count(3.0)), Ok(string("ababab")));
+        assert!(matches!(
+            run_repeat(count(1e19)),
+            Err(RuntimeError::Type(_))
+        ));
+        assert!(matches!(run_repeat(count(1e9)), Err(RuntimeError::Type(_))));
+        assert_eq!(
+            run_repeat(vec![string(""), Expression::Number(1e19)]),
+            Ok(string(""))
+        );
+    }
+}
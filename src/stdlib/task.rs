@@ -0,0 +1,162 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+use rayon::prelude::*;
+
+use super::list::{condition, list_and_function};
+use super::Runtime;
+use crate::interpreter::{EventLoop, RuntimeError, Scheduler};
+use crate::parser::ast::Expression;
+use crate::value::{Outcome, Task};
+
+impl Task {
+    /// Runs `f(args...)` on a thread of its own and stores the result here.
+    /// Given an event loop, the call runs as a future once the loop has a
+    /// slot for it, taking turns with the other futures of the program.
+    pub fn start(
+        &self,
+        runtime: &mut dyn Runtime,
+        f: Expression,
+        args: Vec<Expression>,
+        event_loop: Option<EventLoop>,
+    ) {
+        let mut worker = runtime.fork();
+        let scheduler = runtime.scheduler();
+
+        scheduler.start(1);
+        let done = self.clone();
+        let turns = event_loop.clone();
+        let job = move || {
+            let out = panic::catch_unwind(AssertUnwindSafe(|| {
+                let _turn = turns.as_ref().map(EventLoop::enter);
+                worker.invoke(f, args)
+            }))
+            .unwrap_or_else(|_| Err(RuntimeError::Type(String::from("spawned task panicked"))));
+
+            done.finish(out);
+            scheduler.finish(1);
+        };
+
+        match event_loop {
+            Some(event_loop) => event_loop.spawn(Box::new(job)),
+            None => {
+                thread::spawn(job);
+            }
+        }
+    }
+
+    /// Waits for the function to finish. Joining again gives the same result.
+    pub fn join(&self, scheduler: &Scheduler) -> Outcome {
+        scheduler.wait(|| self.outcome())?
+    }
+}
+
+/// Calls `f` on every item across the thread pool. Results keep the order
+/// of the list, and the error of the first failing item wins.
+fn par_each(
+    runtime: &mut dyn Runtime,
+    items: &[Expression],
+    f: &Expression,
+) -> Result<Vec<Expression>, RuntimeError> {
+    let base = Mutex::new(runtime.fork());
+    let scheduler = runtime.scheduler();
+
+    // Every item counts as a task, and the caller as blocked until they are
+    // all done.
+    scheduler.start(items.len());
+    scheduler.block();
+
+    let out: Vec<Outcome> = items
+        .par_iter()
+        .map_init(
+            || base.lock().unwrap_or_else(PoisonError::into_inner).fork(),
+            |worker, item| {
+                let out = worker.invoke(f.clone(), vec![item.clone()]);
+                scheduler.finish(1);
+                out
+            },
+        )
+        .collect();
+
+    scheduler.unblock();
+    out.into_iter().collect()
+}
+
+pub fn run_par_map(
+    runtime: &mut dyn Runtime,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let (items, f) = list_and_function("par_map", input)?;
+
+    Ok(Expression::List(par_each(runtime, &items, &f)?))
+}
+
+pub fn run_par_filter(
+    runtime: &mut dyn Runtime,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let (items, f) = list_and_function("par_filter", input)?;
+    let keep = par_each(runtime, &items, &f)?;
+
+    let mut out = Vec::new();
+    for (item, keep) in items.into_iter().zip(keep) {
+        if condition("par_filter", keep)? {
+            out.push(item);
+        }
+    }
+    Ok(Expression::List(out))
+}
+
+/// Starts `f(args...)` on a new thread and returns a handle for `join`.
+pub fn run_spawn(
+    runtime: &mut dyn Runtime,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    let mut input = input.into_iter();
+    let f = match input.next() {
+        Some(f @ Expression::Function(..)) => f,
+        t => {
+            return Err(RuntimeError::Type(format!(
+                "spawn expects a function, found {:?}",
+                t
+            )))
+        }
+    };
+
+    let task = Task::default();
+    task.start(runtime, f, input.collect(), None);
+    Ok(Expression::Task(task))
+}
+
+pub fn run_join(scheduler: &Scheduler, input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::Task(task)] => task.join(scheduler),
+        _ => Err(RuntimeError::Type(format!(
+            "join expects a task, found {:?}",
+            input
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test1() {
+        let mut runtime = crate::stdlib::tests::TestRuntime::default();
+
+        assert!(run_spawn(&mut runtime, vec![Expression::Number(1.0)]).is_err());
+        assert!(run_join(&runtime.scheduler(), vec![Expression::Nil]).is_err());
+        assert!(run_par_map(&mut runtime, vec![Expression::Nil]).is_err());
+
+        let scheduler = Scheduler::default();
+        let task = Task::default();
+        assert_eq!(task.join(&scheduler), Err(RuntimeError::Deadlock));
+
+        task.finish(Err(RuntimeError::Exit(2)));
+        assert_eq!(task.join(&scheduler), Err(RuntimeError::Exit(2)));
+        assert_eq!(task.clone().join(&scheduler), Err(RuntimeError::Exit(2)));
+    }
+}
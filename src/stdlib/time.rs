@@ -0,0 +1,523 @@
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::interpreter::{Deadline, RuntimeError};
+use crate::parser::ast::Expression;
+
+const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const MS_PER_DAY: i64 = 86_400_000;
+/// The furthest timestamp from the epoch that can be formatted, 100 million
+/// days either way.
+const MAX_MILLIS: i64 = 100_000_000 * MS_PER_DAY;
+
+/// A broken-down time at some fixed offset from UTC.
+#[derive(Debug, PartialEq)]
+struct Civil {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    millis: i64,
+    /// Offset from UTC in minutes.
+    offset: i64,
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The date of a day counted from 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    days_from_civil(year + month / 12, month % 12 + 1, 1) - days_from_civil(year, month, 1)
+}
+
+impl Civil {
+    fn from_millis(ms: i64, offset: i64) -> Option<Self> {
+        let local = offset
+            .checked_mul(60_000)
+            .and_then(|offset| ms.checked_add(offset))
+            .filter(|t| ms.abs() <= MAX_MILLIS && t.abs() <= MAX_MILLIS)?;
+        let (year, month, day) = civil_from_days(local.div_euclid(MS_PER_DAY));
+        let rest = local.rem_euclid(MS_PER_DAY);
+
+        Some(Civil {
+            year,
+            month,
+            day,
+            hour: rest / 3_600_000,
+            minute: rest / 60_000 % 60,
+            second: rest / 1000 % 60,
+            millis: rest % 1000,
+            offset,
+        })
+    }
+
+    fn to_millis(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * MS_PER_DAY
+            + self.hour * 3_600_000
+            + self.minute * 60_000
+            + self.second * 1000
+            + self.millis
+            - self.offset * 60_000
+    }
+
+    fn weekday(&self) -> usize {
+        // 1970-01-01 was a Thursday.
+        (days_from_civil(self.year, self.month, self.day) + 3).rem_euclid(7) as usize
+    }
+}
+
+/// Parses `Z`, `UTC`, `+hh:mm`, `+hhmm` or `+hh` into minutes east of UTC.
+fn parse_offset(text: &str) -> Option<i64> {
+    if text == "Z" || text == "UTC" {
+        return Some(0);
+    }
+
+    let sign = match text.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits: String = text[1..].chars().filter(|c| *c != ':').collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i64>().ok()?, 0),
+        4 => (
+            digits[..2].parse::<i64>().ok()?,
+            digits[2..].parse::<i64>().ok()?,
+        ),
+        _ => return None,
+    };
+    (hours < 24 && minutes < 60).then_some(sign * (hours * 60 + minutes))
+}
+
+fn offset_text(offset: i64) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!("{}{:02}{:02}", sign, offset.abs() / 60, offset.abs() % 60)
+}
+
+fn format(civil: &Civil, pattern: &str) -> Result<String, RuntimeError> {
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", civil.year)),
+            Some('m') => out.push_str(&format!("{:02}", civil.month)),
+            Some('d') => out.push_str(&format!("{:02}", civil.day)),
+            Some('H') => out.push_str(&format!("{:02}", civil.hour)),
+            Some('M') => out.push_str(&format!("{:02}", civil.minute)),
+            Some('S') => out.push_str(&format!("{:02}", civil.second)),
+            Some('L') => out.push_str(&format!("{:03}", civil.millis)),
+            Some('a') => out.push_str(DAYS[civil.weekday()]),
+            Some('b') => out.push_str(MONTHS[civil.month as usize - 1]),
+            Some('z') => out.push_str(&offset_text(civil.offset)),
+            Some('%') => out.push('%'),
+            c => {
+                return Err(RuntimeError::Type(format!(
+                    "format_time: unknown directive %{}",
+                    c.map_or(String::new(), String::from)
+                )))
+            }
+        }
+    }
+    Ok(out)
+}
+
+struct Scanner<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn error(&self, what: &str) -> RuntimeError {
+        RuntimeError::Type(format!(
+            "parse_time: expected {} at position {} of {:?}",
+            what, self.pos, self.text
+        ))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn number(&mut self, width: usize, what: &str) -> Result<i64, RuntimeError> {
+        let digits: String = self
+            .rest()
+            .chars()
+            .take(width)
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+
+        if digits.len() != width {
+            return Err(self.error(what));
+        }
+        self.pos += width;
+        Ok(digits.parse().unwrap_or(0))
+    }
+
+    fn name(&mut self, names: &[&str], what: &str) -> Result<usize, RuntimeError> {
+        let rest = self.rest();
+        let i = names
+            .iter()
+            .position(|n| rest.get(..3).is_some_and(|r| r.eq_ignore_ascii_case(n)))
+            .ok_or_else(|| self.error(what))?;
+        self.pos += 3;
+        Ok(i)
+    }
+
+    fn offset(&mut self) -> Result<i64, RuntimeError> {
+        let rest = self.rest();
+        let len = if rest.starts_with('Z') {
+            1
+        } else {
+            rest.char_indices()
+                .skip(1)
+                .find(|(_, c)| !c.is_ascii_digit() && *c != ':')
+                .map_or(rest.len(), |(i, _)| i)
+        };
+
+        let offset = parse_offset(&rest[..len]).ok_or_else(|| self.error("an offset"))?;
+        self.pos += len;
+        Ok(offset)
+    }
+}
+
+fn parse(text: &str, pattern: &str, offset: i64) -> Result<i64, RuntimeError> {
+    let mut civil = Civil {
+        year: 1970,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+        millis: 0,
+        offset,
+    };
+    let mut scanner = Scanner { text, pos: 0 };
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            if !scanner.rest().starts_with(c) {
+                return Err(scanner.error(&format!("{:?}", c)));
+            }
+            scanner.pos += c.len_utf8();
+            continue;
+        }
+
+        match chars.next() {
+            Some('Y') => civil.year = scanner.number(4, "a year")?,
+            Some('m') => civil.month = scanner.number(2, "a month")?,
+            Some('d') => civil.day = scanner.number(2, "a day")?,
+            Some('H') => civil.hour = scanner.number(2, "an hour")?,
+            Some('M') => civil.minute = scanner.number(2, "minutes")?,
+            Some('S') => civil.second = scanner.number(2, "seconds")?,
+            Some('L') => civil.millis = scanner.number(3, "milliseconds")?,
+            Some('a') => {
+                scanner.name(&DAYS, "a weekday")?;
+            }
+            Some('b') => civil.month = scanner.name(&MONTHS, "a month name")? as i64 + 1,
+            Some('z') => civil.offset = scanner.offset()?,
+            Some('%') => {
+                if !scanner.rest().starts_with('%') {
+                    return Err(scanner.error("'%'"));
+                }
+                scanner.pos += 1;
+            }
+            c => {
+                return Err(RuntimeError::Type(format!(
+                    "parse_time: unknown directive %{}",
+                    c.map_or(String::new(), String::from)
+                )))
+            }
+        }
+    }
+
+    if !scanner.rest().is_empty() {
+        return Err(scanner.error("the end of input"));
+    }
+
+    let valid = (1..=12).contains(&civil.month)
+        && (1..=days_in_month(civil.year, civil.month)).contains(&civil.day)
+        && civil.hour < 24
+        && civil.minute < 60
+        && civil.second < 60;
+    if !valid {
+        return Err(RuntimeError::Type(format!(
+            "parse_time: {:?} is not a valid time",
+            text
+        )));
+    }
+
+    Ok(civil.to_millis())
+}
+
+fn offset_argument(name: &str, input: Option<&Expression>) -> Result<i64, RuntimeError> {
+    match input {
+        None => Ok(0),
+        Some(Expression::String(text)) => parse_offset(text)
+            .ok_or_else(|| RuntimeError::Type(format!("{}: invalid offset {:?}", name, text))),
+        Some(t) => Err(RuntimeError::Type(format!(
+            "{} expects an offset such as \"+02:00\", found {:?}",
+            name, t
+        ))),
+    }
+}
+
+/// Milliseconds since the Unix epoch.
+pub fn run_now(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    if !input.is_empty() {
+        return Err(RuntimeError::Type(String::from("now expects no arguments")));
+    }
+
+    let ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_millis() as f64);
+    Ok(Expression::Number(ms))
+}
+
+/// Milliseconds on a monotonic clock, only meaningful as a difference.
+pub fn run_clock(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    static START: OnceLock<Instant> = OnceLock::new();
+
+    if !input.is_empty() {
+        return Err(RuntimeError::Type(String::from(
+            "clock expects no arguments",
+        )));
+    }
+
+    let start = START.get_or_init(Instant::now);
+    Ok(Expression::Number(start.elapsed().as_secs_f64() * 1000.0))
+}
+
+pub fn run_sleep(
+    limit: Option<Deadline>,
+    input: Vec<Expression>,
+) -> Result<Expression, RuntimeError> {
+    match input.as_slice() {
+        [Expression::Number(ms)] if *ms >= 0.0 => match Duration::try_from_secs_f64(ms / 1000.0) {
+            Ok(t) => {
+                match limit {
+                    Some(deadline) => {
+                        thread::sleep(deadline.clamp(t));
+                        deadline.check()?;
+                    }
+                    None => thread::sleep(t),
+                }
+                Ok(Expression::Nil)
+            }
+            Err(_) => Err(RuntimeError::Type(format!("sleep: {}ms is too long", ms))),
+        },
+        _ => Err(RuntimeError::Type(String::from(
+            "sleep expects a number of milliseconds",
+        ))),
+    }
+}
+
+/// `format_time(ms, pattern, offset?)`, in UTC unless an offset is given.
+pub fn run_format_time(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let (ms, pattern) = match input.as_slice() {
+        [Expression::Number(ms), Expression::String(pattern), ..] if ms.is_finite() => {
+            (*ms as i64, pattern)
+        }
+        _ => {
+            return Err(RuntimeError::Type(String::from(
+                "format_time expects a timestamp, a pattern and an optional offset",
+            )))
+        }
+    };
+    if input.len() > 3 {
+        return Err(RuntimeError::Arity {
+            name: String::from("format_time"),
+            expected: 3,
+            found: input.len(),
+        });
+    }
+
+    let offset = offset_argument("format_time", input.get(2))?;
+    let civil = Civil::from_millis(ms, offset)
+        .ok_or_else(|| RuntimeError::Type(String::from("format_time: timestamp out of range")))?;
+    format(&civil, pattern).map(Expression::String)
+}
+
+/// `parse_time(text, pattern, offset?)`; `%z` in the text wins over the offset.
+pub fn run_parse_time(input: Vec<Expression>) -> Result<Expression, RuntimeError> {
+    let (text, pattern) = match input.as_slice() {
+        [Expression::String(text), Expression::String(pattern), ..] => (text, pattern),
+        _ => {
+            return Err(RuntimeError::Type(String::from(
+                "parse_time expects a text, a pattern and an optional offset",
+            )))
+        }
+    };
+    if input.len() > 3 {
+        return Err(RuntimeError::Arity {
+            name: String::from("parse_time"),
+            expected: 3,
+            found: input.len(),
+        });
+    }
+
+    let offset = offset_argument("parse_time", input.get(2))?;
+    parse(text, pattern, offset).map(|ms| Expression::Number(ms as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test1() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(1900, 2), 28);
+        assert_eq!(days_in_month(2023, 12), 31);
+
+        for days in [-800_000, -1, 0, 59, 10_957, 2_932_896] {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+    }
+
+    #[test]
+    fn test2() {
+        let ms = 1_709_210_096_789;
+        let utc = Civil::from_millis(ms, 0).unwrap();
+
+        assert_eq!(
+            format(&utc, "%a %d %b %Y %H:%M:%S.%L %z %%"),
+            Ok(String::from("Thu 29 Feb 2024 12:34:56.789 +0000 %"))
+        );
+        assert_eq!(
+            format(&Civil::from_millis(ms, -330).unwrap(), "%Y-%m-%dT%H:%M%z"),
+            Ok(String::from("2024-02-29T07:04-0530"))
+        );
+        assert!(format(&utc, "%Q").is_err());
+        assert_eq!(utc.to_millis(), ms);
+    }
+
+    #[test]
+    fn test3() {
+        assert_eq!(parse("1970-01-02", "%Y-%m-%d", 0), Ok(MS_PER_DAY));
+        assert_eq!(
+            parse("2024-02-29T14:34:56.789+02:00", "%Y-%m-%dT%H:%M:%S.%L%z", 0),
+            Ok(1_709_210_096_789)
+        );
+        assert_eq!(parse("01 Jan 1970 01:00", "%d %b %Y %H:%M", 60), Ok(0));
+        assert_eq!(
+            parse("2023-02-29", "%Y-%m-%d", 0),
+            Err(RuntimeError::Type(String::from(
+                "parse_time: \"2023-02-29\" is not a valid time"
+            )))
+        );
+        assert_eq!(
+            parse("2024-1-01", "%Y-%m-%d", 0),
+            Err(RuntimeError::Type(String::from(
+                "parse_time: expected a month at position 5 of \"2024-1-01\""
+            )))
+        );
+        assert!(parse("2024-01-01 extra", "%Y-%m-%d", 0).is_err());
+    }
+
+    #[test]
+    fn test4() {
+        let string = |s: &str| Expression::String(String::from(s));
+
+        assert_eq!(
+            run_format_time(vec![
+                Expression::Number(0.0),
+                string("%Y-%m-%d %H:%M"),
+                string("+05:30")
+            ]),
+            Ok(string("1970-01-01 05:30"))
+        );
+        assert_eq!(
+            run_parse_time(vec![
+                string("1970-01-01 05:30"),
+                string("%Y-%m-%d %H:%M"),
+                string("+0530")
+            ]),
+            Ok(Expression::Number(0.0))
+        );
+        assert!(
+            run_format_time(vec![Expression::Number(0.0), string("%Y"), string("CET")]).is_err()
+        );
+
+        let before = run_clock(vec![]).unwrap();
+        run_sleep(None, vec![Expression::Number(5.0)]).unwrap();
+        match (before, run_clock(vec![]).unwrap()) {
+            (Expression::Number(a), Expression::Number(b)) => assert!(b - a >= 5.0),
+            t => panic!("{:?}", t),
+        }
+        assert!(matches!(run_now(vec![]), Ok(Expression::Number(n)) if n > 1.7e12));
+    }
+
+    #[test]
+    fn test5() {
+        assert!(matches!(
+            run_sleep(None, vec![Expression::Number(1e300)]),
+            Err(RuntimeError::Type(_))
+        ));
+        assert!(run_sleep(None, vec![Expression::Number(f64::INFINITY)]).is_err());
+        assert!(run_sleep(None, vec![Expression::Number(f64::NAN)]).is_err());
+
+        let format = |ms: f64, offset: &str| {
+            run_format_time(vec![
+                Expression::Number(ms),
+                Expression::String(String::from("%Y")),
+                Expression::String(String::from(offset)),
+            ])
+        };
+        assert!(matches!(
+            format(1e300, "+01:00"),
+            Err(RuntimeError::Type(_))
+        ));
+        assert!(matches!(
+            format(-1e300, "-01:00"),
+            Err(RuntimeError::Type(_))
+        ));
+        assert_eq!(
+            format(0.0, "-01:00"),
+            Ok(Expression::String(String::from("1969")))
+        );
+    }
+}
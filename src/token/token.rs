@@ -3,6 +3,7 @@ pub const FN: &str = "fn";
 pub const IF: &str = "if";
 pub const ELSE: &str = "else";
 pub const LOOP: &str = "loop";
+pub const NIL: &str = "nil";
 
 pub const EQUAL: &str = "==";
 pub const NOT_EQUAL: &str = "!=";
@@ -35,9 +36,20 @@ pub const LEFT_BRACKET: &str = "[";
 pub const RIGHT_BRACKET: &str = "]";
 
 pub const COMMA: &str = ",";
-// TODO
-pub const _DOT: &str = ".";
+pub const DOT: &str = ".";
 pub const COLON: &str = ":";
 
 pub const RETURN: &str = "return";
 pub const BREAK: &str = "break";
+pub const GO: &str = "go";
+pub const ASYNC: &str = "async";
+pub const AWAIT: &str = "await";
+pub const TRY: &str = "try";
+pub const CATCH: &str = "catch";
+pub const FINALLY: &str = "finally";
+pub const THROW: &str = "throw";
+pub const IMPORT: &str = "import";
+pub const FROM: &str = "from";
+pub const AS: &str = "as";
+pub const EXPORT: &str = "export";
+pub const PUB: &str = "pub";
@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::parser::ast::Expression;
+
+#[derive(Debug)]
+pub struct Queue {
+    pub items: VecDeque<Expression>,
+    /// Most items held before `send` blocks; unbounded when `None`.
+    pub capacity: Option<usize>,
+    pub closed: bool,
+}
+
+/// One end of a channel. Both ends share the queue, which is only touched
+/// while holding the scheduler's lock.
+#[derive(Clone)]
+pub struct Channel {
+    queue: Arc<Mutex<Queue>>,
+    sender: bool,
+}
+
+impl Channel {
+    /// The sending and receiving ends of a new channel.
+    pub fn pair(capacity: Option<usize>) -> (Channel, Channel) {
+        let queue = Arc::new(Mutex::new(Queue {
+            items: VecDeque::new(),
+            capacity,
+            closed: false,
+        }));
+        let sender = Channel {
+            queue: queue.clone(),
+            sender: true,
+        };
+        let receiver = Channel {
+            queue,
+            sender: false,
+        };
+        (sender, receiver)
+    }
+
+    pub fn is_sender(&self) -> bool {
+        self.sender
+    }
+
+    pub fn queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The next item, `nil` once the channel is closed and drained, or
+    /// `None` while the sender may still send.
+    pub fn take(&self) -> Option<Expression> {
+        let mut queue = self.queue();
+        match queue.items.pop_front() {
+            Some(item) => Some(item),
+            None if queue.closed => Some(Expression::Nil),
+            None => None,
+        }
+    }
+}
+
+impl PartialEq for Channel {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.queue, &other.queue) && self.sender == other.sender
+    }
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", if self.sender { "Sender" } else { "Receiver" })
+    }
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use super::Task;
+use crate::parser::ast::Expression;
+
+pub type Call = (Expression, Vec<Expression>);
+
+/// A call that starts on the event loop the first time it is awaited; every
+/// copy refers to the same call.
+#[derive(Clone)]
+pub struct Future {
+    call: Arc<Mutex<Option<Call>>>,
+    task: Task,
+}
+
+impl Future {
+    pub fn new(f: Expression, args: Vec<Expression>) -> Self {
+        Future {
+            call: Arc::new(Mutex::new(Some((f, args)))),
+            task: Task::default(),
+        }
+    }
+
+    /// The call, the first time it is asked for; `None` once it has started.
+    pub fn take_call(&self) -> Option<Call> {
+        self.call
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// The task the call runs as once started.
+    pub fn task(&self) -> &Task {
+        &self.task
+    }
+}
+
+impl PartialEq for Future {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.call, &other.call)
+    }
+}
+
+impl fmt::Debug for Future {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Future")
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::sync::{Arc, Mutex};
+
+/// A lazily read stream of lines, shared by every copy of the value.
+#[derive(Clone)]
+pub struct Lines(Arc<Mutex<Box<dyn BufRead + Send>>>);
+
+impl Lines {
+    pub fn new(reader: impl BufRead + Send + 'static) -> Self {
+        Lines(Arc::new(Mutex::new(Box::new(reader))))
+    }
+
+    /// Reads the next line without its line ending, `None` at the end of input.
+    pub fn next_line(&self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        let mut reader = self.0.lock().unwrap_or_else(|e| e.into_inner());
+
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+
+    /// Reads everything left, `None` if nothing was left to read.
+    pub fn read_rest(&self) -> io::Result<Option<String>> {
+        let mut rest = String::new();
+        let mut reader = self.0.lock().unwrap_or_else(|e| e.into_inner());
+
+        if reader.read_to_string(&mut rest)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(rest))
+    }
+}
+
+impl Iterator for Lines {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_line().transpose()
+    }
+}
+
+impl PartialEq for Lines {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Lines {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Lines")
+    }
+}
@@ -0,0 +1,13 @@
+//! Values that only exist while a program runs: handles to tasks, futures,
+//! channels and streams of lines. The built-ins that make and use them are in
+//! `stdlib`.
+
+mod channel;
+mod future;
+mod lines;
+mod task;
+
+pub use channel::Channel;
+pub use future::Future;
+pub use lines::Lines;
+pub use task::{Outcome, Task};
@@ -0,0 +1,38 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::interpreter::RuntimeError;
+use crate::parser::ast::Expression;
+
+pub type Outcome = Result<Expression, RuntimeError>;
+
+/// A function running on its own thread; every copy refers to the same run.
+#[derive(Clone, Default)]
+pub struct Task(Arc<Mutex<Option<Outcome>>>);
+
+impl Task {
+    fn lock(&self) -> MutexGuard<'_, Option<Outcome>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The function's result, once it has finished.
+    pub fn outcome(&self) -> Option<Outcome> {
+        self.lock().clone()
+    }
+
+    pub fn finish(&self, out: Outcome) {
+        *self.lock() = Some(out);
+    }
+}
+
+impl PartialEq for Task {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Task")
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use crate::parser::ast::{Block, Expression, Op, Span};
+use crate::stdlib::Std;
+
+/// One VM instruction. Operands index into the owning [`Proto`]'s tables.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    /// Pushes `constants[i]`.
+    Constant(usize),
+    Nil,
+    /// Pushes a local; an unset slot falls back to a function of the same name.
+    Load(usize),
+    /// Pushes the function called `names[i]`.
+    LoadName(usize),
+    /// Pops into a local.
+    Store(usize),
+    /// Pops into a local, which must already be set, then pushes nil.
+    Assign(usize),
+    Pop,
+    Infix(Op),
+    /// Collects the top `n` values into a list.
+    List(usize),
+    /// Collects the top `n` key/value pairs into a map.
+    Map(usize),
+    Index,
+    Jump(usize),
+    /// Pops a boolean and jumps when it is false.
+    JumpUnless(usize),
+    /// Pops a list or lines value and starts iterating it.
+    Iterate,
+    /// Stores the next item in a local, or jumps when the iterator is done.
+    Next(usize, usize),
+    /// Drops the innermost iterator.
+    EndIterate,
+    /// Opens the loop scope `scopes[i]`.
+    Enter(usize),
+    /// Closes the innermost loop scope, unsetting the locals it declared.
+    Exit,
+    /// Defines the function compiled as proto `i` of the program.
+    Define(usize),
+    /// Calls a built-in with `n` arguments, writing a changed first argument back to a local.
+    CallStd(Std, usize, Option<usize>),
+    /// Calls the function named `names[i]` with `n` arguments, or the function value in a local.
+    Call(usize, usize, Option<usize>),
+    /// Like `Call`, but the callee takes over the running frame; emitted for `return f(x)`.
+    TailCall(usize, usize, Option<usize>),
+    Return,
+    /// Installs a handler for catchable errors, which jumps to `i` with the
+    /// error's value pushed.
+    Catch(usize),
+    /// Installs a handler for any error, which jumps to `i` and keeps the
+    /// error for `Rethrow`.
+    Finally(usize),
+    /// Removes the innermost handler.
+    EndTry,
+    /// Pops a value and raises it.
+    Throw,
+    /// Raises the error the innermost `Finally` handler kept.
+    Rethrow,
+    /// Drops the error the innermost `Finally` handler kept, or raises it if
+    /// it cannot be caught; emitted when `break` or `return` leaves the block.
+    Settle,
+}
+
+/// A compiled function body, or the top level of a program.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Proto {
+    pub name: String,
+    /// The source form, kept so the function can still be passed around as a value.
+    pub params: Vec<Expression>,
+    pub body: Arc<Block>,
+    pub code: Vec<Instruction>,
+    /// The span of the statement each instruction was compiled from.
+    pub spans: Vec<Span>,
+    pub constants: Vec<Expression>,
+    pub names: Vec<String>,
+    /// Local slot names; parameters come first.
+    pub locals: Vec<String>,
+    /// The locals each loop may declare.
+    pub scopes: Vec<Vec<usize>>,
+}
@@ -0,0 +1,690 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+
+use crate::interpreter::RuntimeError;
+use crate::parser::ast::{Block, Expression, Span, Statement};
+use crate::stdlib::Std;
+
+use super::code::{Instruction, Proto};
+
+/// Bookkeeping for the loop being compiled.
+struct Loop {
+    scope: usize,
+    breaks: Vec<usize>,
+}
+
+/// Bookkeeping for a `try` statement whose body is being compiled.
+#[derive(Clone)]
+struct Try {
+    /// How many handlers it has installed at this point.
+    handlers: usize,
+    finally: Option<Block>,
+    /// How many loops enclose it.
+    loops: usize,
+}
+
+/// Compiles one function body into a [`Proto`]; nested functions go to `protos`.
+struct Compiler<'a> {
+    proto: Proto,
+    globals: &'a HashMap<String, Std>,
+    protos: &'a mut Vec<Proto>,
+    base: usize,
+    loops: Vec<Loop>,
+    tries: Vec<Try>,
+    /// For each `finally` block being compiled for the error path, how many
+    /// loops enclose it.
+    settles: Vec<usize>,
+    /// Whether `return f(x)` may hand the frame to `f`; not at the top level.
+    tail: bool,
+    /// The statement being compiled.
+    span: Span,
+}
+
+fn identifier(input: &Expression) -> Result<&str, RuntimeError> {
+    match input {
+        Expression::Identifier(t) | Expression::Local(t, ..) => Ok(t),
+        t => Err(RuntimeError::Type(format!(
+            "expected identifier, found {:?}",
+            t
+        ))),
+    }
+}
+
+/// Collects the names a body binds, without looking into nested functions.
+fn bindings(input: &Block, out: &mut Vec<String>) {
+    fn expression(input: &Expression, out: &mut Vec<String>) {
+        match input {
+            Expression::Assign(name, value) => {
+                if let Ok(t) = identifier(name) {
+                    out.push(t.to_string());
+                }
+                expression(value, out);
+            }
+            Expression::Call(_, params)
+            | Expression::List(params)
+            | Expression::Async(_, params) => params.iter().for_each(|p| expression(p, out)),
+            Expression::Await(t) => expression(t, out),
+            Expression::Infix(l, _, r) | Expression::Index(l, r) => {
+                expression(l, out);
+                expression(r, out);
+            }
+            Expression::Map(entries) => entries.iter().for_each(|(k, v)| {
+                expression(k, out);
+                expression(v, out);
+            }),
+            _ => {}
+        }
+    }
+
+    for statement in input {
+        match statement.inner() {
+            Statement::Let { name, initial } => {
+                if let Ok(t) = identifier(name) {
+                    out.push(t.to_string());
+                }
+                expression(initial, out);
+            }
+            Statement::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                expression(condition, out);
+                bindings(then, out);
+                if let Some(o) = otherwise {
+                    bindings(o, out);
+                }
+            }
+            Statement::Loop {
+                iterable,
+                value,
+                then,
+            } => {
+                if let Some(Ok(t)) = iterable.as_ref().map(identifier) {
+                    out.push(t.to_string());
+                }
+                if let Some(v) = value {
+                    expression(v, out);
+                }
+                bindings(then, out);
+            }
+            Statement::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                bindings(body, out);
+                if let Some((name, c)) = catch {
+                    if let Ok(t) = identifier(name) {
+                        out.push(t.to_string());
+                    }
+                    bindings(c, out);
+                }
+                if let Some(f) = finally {
+                    bindings(f, out);
+                }
+            }
+            Statement::Expr { expression: e } => expression(e, out),
+            Statement::Return { value: Some(v) } | Statement::Throw { value: v } => {
+                expression(v, out)
+            }
+            Statement::Fn { .. }
+            | Statement::Return { value: None }
+            | Statement::Break
+            | Statement::At(..)
+            | Statement::Import { .. }
+            | Statement::Export(_) => {}
+        }
+    }
+}
+
+impl<'a> Compiler<'a> {
+    fn new(
+        name: &str,
+        params: &[Expression],
+        body: &Arc<Block>,
+        globals: &'a HashMap<String, Std>,
+        protos: &'a mut Vec<Proto>,
+        base: usize,
+    ) -> Result<Self, RuntimeError> {
+        let mut locals = Vec::new();
+        for p in params {
+            locals.push(identifier(p)?.to_string());
+        }
+
+        let mut bound = Vec::new();
+        bindings(body, &mut bound);
+        for name in bound {
+            if !locals.contains(&name) {
+                locals.push(name);
+            }
+        }
+
+        Ok(Self {
+            proto: Proto {
+                name: name.to_string(),
+                params: params.to_vec(),
+                body: body.clone(),
+                locals,
+                ..Proto::default()
+            },
+            globals,
+            protos,
+            base,
+            loops: Vec::new(),
+            tries: Vec::new(),
+            settles: Vec::new(),
+            tail: false,
+            span: Span::default(),
+        })
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        self.proto.code.push(instruction);
+        self.proto.spans.push(self.span);
+        self.proto.code.len() - 1
+    }
+
+    fn here(&self) -> usize {
+        self.proto.code.len()
+    }
+
+    fn patch(&mut self, at: usize, target: usize) {
+        match &mut self.proto.code[at] {
+            Instruction::Jump(t)
+            | Instruction::JumpUnless(t)
+            | Instruction::Next(_, t)
+            | Instruction::Catch(t)
+            | Instruction::Finally(t) => *t = target,
+            t => unreachable!("cannot patch {:?}", t),
+        }
+    }
+
+    fn constant(&mut self, value: Expression) -> usize {
+        self.proto.constants.push(value);
+        self.proto.constants.len() - 1
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        match self.proto.names.iter().position(|n| n == name) {
+            Some(i) => i,
+            None => {
+                self.proto.names.push(name.to_string());
+                self.proto.names.len() - 1
+            }
+        }
+    }
+
+    fn local(&self, name: &str) -> Option<usize> {
+        self.proto.locals.iter().position(|n| n == name)
+    }
+
+    /// The slot for a name being bound, recorded in every enclosing loop scope.
+    fn bind(&mut self, name: &str) -> usize {
+        let slot = self.local(name).expect("bindings are collected up front");
+
+        for l in &self.loops {
+            let scope = &mut self.proto.scopes[l.scope];
+            if !scope.contains(&slot) {
+                scope.push(slot);
+            }
+        }
+        slot
+    }
+
+    fn block(&mut self, input: &Block) -> Result<(), RuntimeError> {
+        input.iter().try_for_each(|s| self.statement(s))
+    }
+
+    /// Leaves the `try` statements from `from` on, innermost first: removes
+    /// their handlers and runs their `finally` blocks.
+    fn unwind(&mut self, from: usize) -> Result<(), RuntimeError> {
+        let tries = self.tries.clone();
+
+        while self.tries.len() > from {
+            let t = self.tries.pop().expect("checked above");
+            for _ in 0..t.handlers {
+                self.emit(Instruction::EndTry);
+            }
+            if let Some(f) = &t.finally {
+                self.block(f)?;
+            }
+        }
+
+        self.tries = tries;
+        Ok(())
+    }
+
+    /// Settles the errors kept by the `finally` blocks being left, which are
+    /// those enclosed by at least `loops` loops.
+    fn settle(&mut self, loops: usize) {
+        let n = self.settles.iter().filter(|l| **l >= loops).count();
+        for _ in 0..n {
+            self.emit(Instruction::Settle);
+        }
+    }
+
+    fn statement(&mut self, input: &Statement) -> Result<(), RuntimeError> {
+        match input {
+            Statement::At(span, t) => {
+                let outer = mem::replace(&mut self.span, *span);
+                self.statement(t)?;
+                self.span = outer;
+            }
+
+            Statement::Let { name, initial } => {
+                self.expression(initial)?;
+                let slot = self.bind(identifier(name)?);
+                self.emit(Instruction::Store(slot));
+            }
+
+            Statement::Fn { name, params, body } => {
+                let name = identifier(name)?;
+                let body = Arc::new(body.clone());
+                let index =
+                    compile_function(name, params, &body, self.globals, self.protos, self.base)?;
+                self.emit(Instruction::Define(index));
+            }
+
+            Statement::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.expression(condition)?;
+                let skip = self.emit(Instruction::JumpUnless(0));
+                self.block(then)?;
+
+                match otherwise {
+                    Some(otherwise) => {
+                        let end = self.emit(Instruction::Jump(0));
+                        let here = self.here();
+                        self.patch(skip, here);
+                        self.block(otherwise)?;
+                        let here = self.here();
+                        self.patch(end, here);
+                    }
+                    None => {
+                        let here = self.here();
+                        self.patch(skip, here);
+                    }
+                }
+            }
+
+            Statement::Loop {
+                iterable,
+                value,
+                then,
+            } => {
+                let scope = self.proto.scopes.len();
+                self.proto.scopes.push(Vec::new());
+                self.emit(Instruction::Enter(scope));
+
+                let variable = match (iterable, value) {
+                    (Some(iterable), Some(value)) => {
+                        let name = identifier(iterable)?;
+                        self.expression(value)?;
+                        self.emit(Instruction::Iterate);
+                        Some(name)
+                    }
+                    (None, _) => None,
+                    (Some(_), None) => {
+                        return Err(RuntimeError::Type(String::from("value cannot be None")))
+                    }
+                };
+
+                self.loops.push(Loop {
+                    scope,
+                    breaks: Vec::new(),
+                });
+
+                let top = self.here();
+                let next = match variable {
+                    Some(name) => {
+                        let slot = self.bind(name);
+                        Some(self.emit(Instruction::Next(slot, 0)))
+                    }
+                    None => None,
+                };
+
+                self.block(then)?;
+                self.emit(Instruction::Jump(top));
+
+                let end = self.here();
+                let l = self.loops.pop().expect("loop was pushed above");
+                for at in l.breaks.into_iter().chain(next) {
+                    self.patch(at, end);
+                }
+
+                if variable.is_some() {
+                    self.emit(Instruction::EndIterate);
+                }
+                self.emit(Instruction::Exit);
+            }
+
+            Statement::Return { value } => {
+                match value {
+                    Some(value) => self.expression(value)?,
+                    None => {
+                        self.emit(Instruction::Nil);
+                    }
+                }
+
+                self.settle(0);
+                self.unwind(0)?;
+
+                match self.proto.code.last() {
+                    Some(&Instruction::Call(n, argc, local))
+                        if self.tail && matches!(value, Some(Expression::Call(..))) =>
+                    {
+                        self.proto.code.pop();
+                        self.emit(Instruction::TailCall(n, argc, local));
+                    }
+                    _ => {
+                        self.emit(Instruction::Return);
+                    }
+                }
+            }
+
+            Statement::Break => match self.loops.last() {
+                Some(_) => {
+                    let inside = self.loops.len();
+                    self.settle(inside);
+                    let from = self.tries.iter().position(|t| t.loops == inside);
+                    self.unwind(from.unwrap_or(self.tries.len()))?;
+
+                    let at = self.emit(Instruction::Jump(0));
+                    if let Some(l) = self.loops.last_mut() {
+                        l.breaks.push(at);
+                    }
+                }
+                // Outside a loop, break leaves the function with nil.
+                None => {
+                    self.settle(0);
+                    self.unwind(0)?;
+                    self.emit(Instruction::Nil);
+                    self.emit(Instruction::Return);
+                }
+            },
+
+            Statement::Expr { expression } => {
+                self.expression(expression)?;
+                self.emit(Instruction::Pop);
+            }
+
+            Statement::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                // A call in tail position would run after the handlers are gone.
+                let tail = mem::replace(&mut self.tail, false);
+
+                let guard = finally.as_ref().map(|_| self.emit(Instruction::Finally(0)));
+                let handler = catch.as_ref().map(|_| self.emit(Instruction::Catch(0)));
+                self.tries.push(Try {
+                    handlers: usize::from(guard.is_some()) + usize::from(handler.is_some()),
+                    finally: finally.clone(),
+                    loops: self.loops.len(),
+                });
+
+                self.block(body)?;
+
+                if let (Some(handler), Some((name, then))) = (handler, catch) {
+                    self.emit(Instruction::EndTry);
+                    let skip = self.emit(Instruction::Jump(0));
+
+                    // The machine removes the handler before jumping to it.
+                    let here = self.here();
+                    self.patch(handler, here);
+                    if let Some(t) = self.tries.last_mut() {
+                        t.handlers -= 1;
+                    }
+
+                    self.tail = tail && finally.is_none();
+                    let slot = self.bind(identifier(name)?);
+                    self.emit(Instruction::Store(slot));
+                    self.block(then)?;
+
+                    let here = self.here();
+                    self.patch(skip, here);
+                }
+
+                self.tail = tail;
+                self.tries.pop();
+
+                if let (Some(guard), Some(finally)) = (guard, finally) {
+                    self.emit(Instruction::EndTry);
+                    self.block(finally)?;
+                    let end = self.emit(Instruction::Jump(0));
+
+                    let here = self.here();
+                    self.patch(guard, here);
+                    self.settles.push(self.loops.len());
+                    self.block(finally)?;
+                    self.settles.pop();
+                    self.emit(Instruction::Rethrow);
+
+                    let here = self.here();
+                    self.patch(end, here);
+                }
+            }
+
+            Statement::Throw { value } => {
+                self.expression(value)?;
+                self.emit(Instruction::Throw);
+            }
+
+            Statement::Import { .. } | Statement::Export(_) => {
+                unreachable!("the resolver rejects imports and exports")
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, input: &Expression) -> Result<(), RuntimeError> {
+        match input {
+            Expression::Number(_)
+            | Expression::String(_)
+            | Expression::Boolean(_)
+            | Expression::Function(..) => {
+                let c = self.constant(input.clone());
+                self.emit(Instruction::Constant(c));
+            }
+            Expression::Nil => {
+                self.emit(Instruction::Nil);
+            }
+            Expression::Identifier(t) | Expression::Local(t, ..) => {
+                let instruction = match self.local(t) {
+                    Some(slot) => Instruction::Load(slot),
+                    None => Instruction::LoadName(self.name(t)),
+                };
+                self.emit(instruction);
+            }
+            Expression::Infix(left, op, right) => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.emit(Instruction::Infix(op.clone()));
+            }
+            Expression::List(items) => {
+                for item in items {
+                    self.expression(item)?;
+                }
+                self.emit(Instruction::List(items.len()));
+            }
+            Expression::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                self.emit(Instruction::Map(entries.len()));
+            }
+            Expression::Index(value, index) => {
+                self.expression(value)?;
+                self.expression(index)?;
+                self.emit(Instruction::Index);
+            }
+            Expression::Assign(name, value) => {
+                self.expression(value)?;
+                let slot = self.bind(identifier(name)?);
+                self.emit(Instruction::Assign(slot));
+            }
+            Expression::Call(name, params) => {
+                let name = identifier(name)?;
+
+                for param in params {
+                    self.expression(param)?;
+                }
+
+                let instruction = match self.globals.get(name) {
+                    Some(std) => {
+                        let target = match params.first() {
+                            Some(Expression::Identifier(t) | Expression::Local(t, ..))
+                                if self.local(t).is_some() =>
+                            {
+                                Some(self.bind(t))
+                            }
+                            _ => None,
+                        };
+                        Instruction::CallStd(std.clone(), params.len(), target)
+                    }
+                    None => Instruction::Call(self.name(name), params.len(), self.local(name)),
+                };
+                self.emit(instruction);
+            }
+            Expression::Async(function, args) => {
+                let (name, params, body) = match &**function {
+                    Statement::Fn { name, params, body } => (identifier(name)?, params, body),
+                    t => return Err(RuntimeError::Type(format!("cannot evaluate {:?}", t))),
+                };
+                let body = Arc::new(body.clone());
+                compile_function(name, params, &body, self.globals, self.protos, self.base)?;
+
+                let f = Expression::Function(name.to_string(), params.clone(), body);
+                let c = self.constant(f);
+                self.emit(Instruction::Constant(c));
+                for arg in args {
+                    self.expression(arg)?;
+                }
+                self.emit(Instruction::CallStd(Std::Async, args.len() + 1, None));
+            }
+            Expression::Await(value) => {
+                self.expression(value)?;
+                self.emit(Instruction::CallStd(Std::Await, 1, None));
+            }
+            t => return Err(RuntimeError::Type(format!("cannot evaluate {:?}", t))),
+        }
+        Ok(())
+    }
+}
+
+/// Compiles a function into `protos`, returning its index in the program.
+fn compile_function(
+    name: &str,
+    params: &[Expression],
+    body: &Arc<Block>,
+    globals: &HashMap<String, Std>,
+    protos: &mut Vec<Proto>,
+    base: usize,
+) -> Result<usize, RuntimeError> {
+    let index = protos.len();
+    protos.push(Proto::default());
+
+    let mut compiler = Compiler::new(name, params, body, globals, protos, base)?;
+    compiler.tail = base + index != 0;
+    compiler.block(body)?;
+    compiler.emit(Instruction::Nil);
+    compiler.emit(Instruction::Return);
+
+    let proto = compiler.proto;
+    protos[index] = proto;
+    Ok(base + index)
+}
+
+/// Compiles a program; the top level is proto 0.
+pub fn compile(input: &Block, globals: &HashMap<String, Std>) -> Result<Vec<Proto>, RuntimeError> {
+    let mut protos = Vec::new();
+    compile_function(
+        "main",
+        &[],
+        &Arc::new(input.clone()),
+        globals,
+        &mut protos,
+        0,
+    )?;
+    Ok(protos)
+}
+
+/// Compiles a function value that is not part of the program yet, numbering
+/// its protos from `base`.
+pub fn compile_value(
+    name: &str,
+    params: &[Expression],
+    body: &Arc<Block>,
+    globals: &HashMap<String, Std>,
+    base: usize,
+) -> Result<Vec<Proto>, RuntimeError> {
+    let mut protos = Vec::new();
+    compile_function(name, params, body, globals, &mut protos, base)?;
+    Ok(protos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::globals;
+    use crate::parser::ast::Op;
+
+    fn compile_source(source: &str) -> Vec<Proto> {
+        let (_, input) = crate::parser::parse(source).unwrap();
+        compile(&input, &globals()).unwrap()
+    }
+
+    #[test]
+    fn test1() {
+        let protos = compile_source("let a = 1 + 2\nprint(a)");
+
+        assert_eq!(protos[0].locals, vec!["a"]);
+        assert_eq!(
+            protos[0].code,
+            vec![
+                Instruction::Constant(0),
+                Instruction::Constant(1),
+                Instruction::Infix(Op::Add),
+                Instruction::Store(0),
+                Instruction::Load(0),
+                Instruction::CallStd(Std::Print, 1, Some(0)),
+                Instruction::Pop,
+                Instruction::Nil,
+                Instruction::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn test2() {
+        let protos = compile_source(
+            "fn f(x) { return g(x) }
+            let n = 0
+            loop i : [1, 2] {
+                let t = i
+                if i == 2 { break }
+            }",
+        );
+
+        assert_eq!(protos.len(), 2);
+        assert_eq!(protos[1].locals, vec!["x"]);
+        assert_eq!(
+            protos[1].code,
+            vec![
+                Instruction::Load(0),
+                Instruction::TailCall(0, 1, None),
+                Instruction::Nil,
+                Instruction::Return,
+            ]
+        );
+        assert_eq!(protos[0].code[0], Instruction::Define(1));
+        assert_eq!(protos[0].scopes, vec![vec![1, 2]]);
+    }
+}
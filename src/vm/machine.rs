@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::interpreter::{index_value, infix, insert, size_of, Context, RuntimeError};
+use crate::parser::ast::{Block, Expression};
+use crate::stdlib::{Runtime, Std};
+
+use super::code::{Instruction, Proto};
+use super::compiler::compile_value;
+
+/// Functions visible to a frame, by name. Shared until a frame defines one.
+type Functions = Arc<HashMap<String, usize>>;
+
+type Items = Box<dyn Iterator<Item = Result<Expression, RuntimeError>>>;
+
+/// How a frame finished.
+enum Outcome {
+    Return(Expression),
+    /// A tail call: the proto to run next in the same native frame, its
+    /// arguments and the functions it sees.
+    Tail(usize, Vec<Expression>, Functions),
+}
+
+/// The state of one running call.
+struct Frame {
+    proto: Arc<Proto>,
+    slots: Vec<Option<Expression>>,
+    stack: Vec<Expression>,
+    iterators: Vec<Items>,
+    /// For each open loop: the locals to unset on exit and the functions to restore.
+    scopes: Vec<(Vec<usize>, Functions)>,
+    functions: Functions,
+    /// Installed `catch` and `finally` handlers, innermost last.
+    handlers: Vec<Handler>,
+    /// Errors kept by `finally` handlers until their block has run.
+    pending: Vec<RuntimeError>,
+}
+
+/// Where an error goes, and the frame state to return to first.
+struct Handler {
+    target: usize,
+    /// Whether it takes every error, as `finally` does, or only catchable ones.
+    all: bool,
+    stack: usize,
+    iterators: usize,
+    scopes: usize,
+    pending: usize,
+}
+
+impl Handler {
+    fn new(frame: &Frame, target: usize, all: bool) -> Self {
+        Self {
+            target,
+            all,
+            stack: frame.stack.len(),
+            iterators: frame.iterators.len(),
+            scopes: frame.scopes.len(),
+            pending: frame.pending.len(),
+        }
+    }
+}
+
+/// Drops the errors kept by `finally` blocks that `error` leaves early,
+/// unless one of them cannot be caught, in which case it wins.
+fn abandon(frame: &mut Frame, from: usize, error: RuntimeError) -> RuntimeError {
+    let kept = frame.pending.split_off(from.min(frame.pending.len()));
+    kept.into_iter().find(|e| !e.catchable()).unwrap_or(error)
+}
+
+impl Frame {
+    fn pop(&mut self) -> Expression {
+        self.stack.pop().expect("compiler keeps the stack balanced")
+    }
+
+    fn pop_n(&mut self, n: usize) -> Vec<Expression> {
+        let at = self.stack.len() - n;
+        self.stack.split_off(at)
+    }
+}
+
+pub struct Machine {
+    protos: Vec<Arc<Proto>>,
+    /// Each proto by the address of its body, which the function values
+    /// made from it share.
+    bodies: HashMap<usize, usize>,
+    globals: HashMap<String, Std>,
+    context: Arc<Mutex<Context>>,
+    /// Functions of the frame that last called a built-in, for `invoke`.
+    functions: Functions,
+    budgeted: bool,
+    metered: bool,
+    /// How many calls deep this runs on its thread.
+    depth: usize,
+}
+
+fn address(body: &Arc<Block>) -> usize {
+    Arc::as_ptr(body) as usize
+}
+
+/// Indexes `protos`, numbered from `base`, by their bodies.
+fn bodies(protos: &[Arc<Proto>], base: usize) -> HashMap<usize, usize> {
+    protos
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (address(&p.body), base + i))
+        .collect()
+}
+
+impl Machine {
+    pub fn new(protos: Vec<Proto>, globals: HashMap<String, Std>, context: Context) -> Self {
+        let budgeted = context.limits.steps.is_some() || context.limits.time.is_some();
+        let metered = context.limits.memory.is_some();
+
+        let protos: Vec<_> = protos.into_iter().map(Arc::new).collect();
+        Self {
+            bodies: bodies(&protos, 0),
+            protos,
+            globals,
+            context: Arc::new(Mutex::new(context)),
+            functions: Functions::default(),
+            budgeted,
+            metered,
+            depth: 0,
+        }
+    }
+
+    /// Runs the top level of the program.
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        self.execute(0, Vec::new(), Functions::default())?;
+        Ok(())
+    }
+
+    fn step(&self) -> Result<(), RuntimeError> {
+        let mut context = self.context();
+        let context = &mut *context;
+
+        context.usage.step(&context.limits)
+    }
+
+    fn store(&self, frame: &mut Frame, slot: usize, value: Expression) -> Result<(), RuntimeError> {
+        if self.metered {
+            let name = &frame.proto.locals[slot];
+            let old = frame.slots[slot]
+                .as_ref()
+                .map_or(0, |old| size_of(name, old));
+
+            let mut context = self.context();
+            let context = &mut *context;
+            context
+                .usage
+                .replace(&context.limits, old, size_of(name, &value))?;
+        }
+
+        frame.slots[slot] = Some(value);
+        Ok(())
+    }
+
+    fn unset(&self, frame: &mut Frame, slot: usize) {
+        if let Some(old) = frame.slots[slot].take() {
+            if self.metered {
+                let bytes = size_of(&frame.proto.locals[slot], &old);
+                self.context().usage.free(bytes);
+            }
+        }
+    }
+
+    fn function(&self, index: usize) -> Expression {
+        let proto = &self.protos[index];
+        Expression::Function(proto.name.clone(), proto.params.clone(), proto.body.clone())
+    }
+
+    /// Resolves a name that is not a set local to a function.
+    fn lookup(&self, frame: &Frame, name: &str) -> Result<Expression, RuntimeError> {
+        match frame.functions.get(name) {
+            Some(index) => Ok(self.function(*index)),
+            None => Err(RuntimeError::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    /// Finds the proto behind a function value, compiling it if it came from elsewhere.
+    fn proto_of(
+        &mut self,
+        name: &str,
+        params: &[Expression],
+        body: &Arc<Block>,
+    ) -> Result<usize, RuntimeError> {
+        if let Some(index) = self.bodies.get(&address(body)) {
+            return Ok(*index);
+        }
+
+        let base = self.protos.len();
+        let protos: Vec<_> = compile_value(name, params, body, &self.globals, base)?
+            .into_iter()
+            .map(Arc::new)
+            .collect();
+        self.bodies.extend(bodies(&protos, base));
+        self.protos.extend(protos);
+        Ok(base)
+    }
+
+    fn call(
+        &mut self,
+        index: usize,
+        args: Vec<Expression>,
+        functions: Functions,
+    ) -> Result<Expression, RuntimeError> {
+        self.context().limits.enter(self.depth)?;
+
+        self.depth += 1;
+        let out = self.execute(index, args, functions);
+        self.depth -= 1;
+        out
+    }
+
+    fn call_value(
+        &mut self,
+        function: Expression,
+        args: Vec<Expression>,
+        functions: Functions,
+    ) -> Result<Expression, RuntimeError> {
+        match function {
+            Expression::Function(name, params, body) => {
+                let index = self.proto_of(&name, &params, &body)?;
+                self.call(index, args, functions)
+            }
+            t => Err(RuntimeError::Type(format!("cannot call {:?}", t))),
+        }
+    }
+
+    fn execute(
+        &mut self,
+        mut index: usize,
+        mut args: Vec<Expression>,
+        mut functions: Functions,
+    ) -> Result<Expression, RuntimeError> {
+        loop {
+            let proto = Arc::clone(&self.protos[index]);
+
+            let params = proto.params.len();
+            if args.len() != params {
+                return Err(RuntimeError::Arity {
+                    name: proto.name.clone(),
+                    expected: params,
+                    found: args.len(),
+                });
+            }
+
+            let mut frame = Frame {
+                slots: vec![None; proto.locals.len()],
+                proto: Arc::clone(&proto),
+                stack: Vec::new(),
+                iterators: Vec::new(),
+                scopes: Vec::new(),
+                functions,
+                handlers: Vec::new(),
+                pending: Vec::new(),
+            };
+
+            for (slot, value) in args.into_iter().enumerate() {
+                self.store(&mut frame, slot, value)?;
+            }
+
+            let out = self.dispatch(&proto, &mut frame);
+
+            for slot in 0..frame.slots.len() {
+                self.unset(&mut frame, slot);
+            }
+
+            // Proto 0 is the top level rather than a call.
+            let out = match index {
+                0 => out,
+                _ => out.map_err(|e| e.called(&proto.name)),
+            };
+
+            match out? {
+                Outcome::Return(value) => return Ok(value),
+                Outcome::Tail(next, next_args, next_functions) => {
+                    index = next;
+                    args = next_args;
+                    functions = next_functions;
+                }
+            }
+        }
+    }
+
+    fn dispatch(&mut self, proto: &Proto, frame: &mut Frame) -> Result<Outcome, RuntimeError> {
+        let mut pc = 0;
+
+        loop {
+            let at = pc;
+            match self.instruction(proto, frame, &mut pc) {
+                Ok(None) => {}
+                Ok(Some(outcome)) => return Ok(outcome),
+                Err(e) => pc = self.handle(frame, e.at(proto.spans[at]))?,
+            }
+        }
+    }
+
+    /// Closes the innermost loop scope.
+    fn exit(&self, frame: &mut Frame) {
+        let (fresh, functions) = frame.scopes.pop().expect("loop has a scope");
+        for slot in fresh {
+            self.unset(frame, slot);
+        }
+        frame.functions = functions;
+    }
+
+    /// Unwinds the frame to the innermost handler that takes the error and
+    /// returns where to continue, or gives the error back if none does.
+    fn handle(&self, frame: &mut Frame, mut error: RuntimeError) -> Result<usize, RuntimeError> {
+        while let Some(handler) = frame.handlers.pop() {
+            error = abandon(frame, handler.pending, error);
+            if !handler.all && !error.catchable() {
+                continue;
+            }
+
+            frame.stack.truncate(handler.stack);
+            frame.iterators.truncate(handler.iterators);
+            while frame.scopes.len() > handler.scopes {
+                self.exit(frame);
+            }
+
+            if handler.all {
+                frame.pending.push(error);
+            } else {
+                frame.stack.push(error.value());
+            }
+            return Ok(handler.target);
+        }
+        Err(abandon(frame, 0, error))
+    }
+
+    /// Runs the instruction at `pc`, returning how the frame finished if it did.
+    fn instruction(
+        &mut self,
+        proto: &Proto,
+        frame: &mut Frame,
+        pc: &mut usize,
+    ) -> Result<Option<Outcome>, RuntimeError> {
+        if self.budgeted {
+            self.step()?;
+        }
+
+        let instruction = &proto.code[*pc];
+        *pc += 1;
+
+        match instruction {
+            Instruction::Constant(c) => frame.stack.push(proto.constants[*c].clone()),
+            Instruction::Nil => frame.stack.push(Expression::Nil),
+            Instruction::Load(slot) => {
+                let value = match &frame.slots[*slot] {
+                    Some(value) => value.clone(),
+                    None => self.lookup(frame, &proto.locals[*slot])?,
+                };
+                frame.stack.push(value);
+            }
+            Instruction::LoadName(n) => {
+                let value = self.lookup(frame, &proto.names[*n])?;
+                frame.stack.push(value);
+            }
+            Instruction::Store(slot) => {
+                let value = frame.pop();
+                self.store(frame, *slot, value)?;
+            }
+            Instruction::Assign(slot) => {
+                let value = frame.pop();
+                if frame.slots[*slot].is_none() {
+                    let name = frame.proto.locals[*slot].clone();
+                    return Err(RuntimeError::UndefinedVariable(name));
+                }
+                self.store(frame, *slot, value)?;
+                frame.stack.push(Expression::Nil);
+            }
+            Instruction::Pop => {
+                frame.pop();
+            }
+            Instruction::Infix(op) => {
+                let right = frame.pop();
+                let left = frame.pop();
+                frame.stack.push(infix(left, op.clone(), right)?);
+            }
+            Instruction::List(n) => {
+                let items = frame.pop_n(*n);
+                frame.stack.push(Expression::List(items));
+            }
+            Instruction::Map(n) => {
+                let mut entries = Vec::new();
+                let mut items = frame.pop_n(n * 2).into_iter();
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    insert(&mut entries, key, value);
+                }
+                frame.stack.push(Expression::Map(entries));
+            }
+            Instruction::Index => {
+                let index = frame.pop();
+                let value = frame.pop();
+                frame.stack.push(index_value(value, index)?);
+            }
+            Instruction::Jump(target) => *pc = *target,
+            Instruction::JumpUnless(target) => match frame.pop() {
+                Expression::Boolean(true) => {}
+                Expression::Boolean(false) => *pc = *target,
+                _ => {
+                    return Err(RuntimeError::Type(String::from(
+                        "must be true or false conditional value",
+                    )))
+                }
+            },
+            Instruction::Iterate => {
+                let items: Items = match frame.pop() {
+                    Expression::List(val) => Box::new(val.into_iter().map(Ok)),
+                    Expression::Lines(lines) => Box::new(lines.map(|line| {
+                        line.map(Expression::String)
+                            .map_err(|e| RuntimeError::Io(e.to_string()))
+                    })),
+                    _ => return Err(RuntimeError::Type(String::from("insert proper iterator"))),
+                };
+                frame.iterators.push(items);
+            }
+            Instruction::Next(slot, end) => {
+                let items = frame.iterators.last_mut().expect("loop has an iterator");
+                match items.next() {
+                    Some(item) => self.store(frame, *slot, item?)?,
+                    None => *pc = *end,
+                }
+            }
+            Instruction::EndIterate => {
+                frame.iterators.pop();
+            }
+            Instruction::Enter(scope) => {
+                let fresh = proto.scopes[*scope]
+                    .iter()
+                    .copied()
+                    .filter(|slot| frame.slots[*slot].is_none())
+                    .collect();
+                frame.scopes.push((fresh, frame.functions.clone()));
+            }
+            Instruction::Exit => self.exit(frame),
+            Instruction::Define(index) => {
+                let name = self.protos[*index].name.clone();
+                Arc::make_mut(&mut frame.functions).insert(name, *index);
+            }
+            Instruction::CallStd(std, n, target) => {
+                let mut args = frame.pop_n(*n);
+                self.functions = frame.functions.clone();
+
+                let out = match target {
+                    Some(slot) if std.mutates(&args) => {
+                        let mut list = args.remove(0);
+                        let out = std.run_mut(self, &mut list, args)?;
+                        self.store(frame, *slot, list)?;
+                        out
+                    }
+                    _ => std.run(self, args)?,
+                };
+                frame.stack.push(out);
+            }
+            Instruction::Call(n, argc, local) => {
+                let args = frame.pop_n(*argc);
+                let name = &proto.names[*n];
+
+                let out = if let Some(index) = frame.functions.get(name) {
+                    self.call(*index, args, frame.functions.clone())?
+                } else if let Some(Some(f @ Expression::Function(..))) =
+                    local.map(|slot| &frame.slots[slot])
+                {
+                    self.call_value(f.clone(), args, frame.functions.clone())?
+                } else {
+                    return Err(RuntimeError::UndefinedFunction(name.to_string()));
+                };
+                frame.stack.push(out);
+            }
+            Instruction::TailCall(n, argc, local) => {
+                let args = frame.pop_n(*argc);
+                let name = &proto.names[*n];
+
+                let index = if let Some(index) = frame.functions.get(name) {
+                    *index
+                } else if let Some(Some(Expression::Function(f, params, body))) =
+                    local.map(|slot| &frame.slots[slot])
+                {
+                    self.proto_of(f, params, body)?
+                } else {
+                    return Err(RuntimeError::UndefinedFunction(name.to_string()));
+                };
+                return Ok(Some(Outcome::Tail(index, args, frame.functions.clone())));
+            }
+            Instruction::Return => return Ok(Some(Outcome::Return(frame.pop()))),
+            Instruction::Catch(target) => frame.handlers.push(Handler::new(frame, *target, false)),
+            Instruction::Finally(target) => frame.handlers.push(Handler::new(frame, *target, true)),
+            Instruction::EndTry => {
+                frame.handlers.pop();
+            }
+            Instruction::Throw => return Err(RuntimeError::Thrown(frame.pop())),
+            Instruction::Rethrow => {
+                return Err(frame.pending.pop().expect("finally keeps the error"));
+            }
+            Instruction::Settle => match frame.pending.pop() {
+                Some(e) if !e.catchable() => return Err(e),
+                _ => {}
+            },
+        }
+        Ok(None)
+    }
+}
+
+impl Runtime for Machine {
+    fn context(&self) -> MutexGuard<'_, Context> {
+        self.context.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn invoke(
+        &mut self,
+        function: Expression,
+        input: Vec<Expression>,
+    ) -> Result<Expression, RuntimeError> {
+        let functions = mem::take(&mut self.functions);
+        let out = self.call_value(function, input, functions.clone());
+        self.functions = functions;
+        out
+    }
+
+    fn fork(&self) -> Box<dyn Runtime + Send> {
+        Box::new(Machine {
+            protos: self.protos.clone(),
+            bodies: self.bodies.clone(),
+            globals: self.globals.clone(),
+            context: self.context.clone(),
+            functions: self.functions.clone(),
+            budgeted: self.budgeted,
+            metered: self.metered,
+            depth: 0,
+        })
+    }
+}
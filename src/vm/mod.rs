@@ -0,0 +1,560 @@
+//! A bytecode compiler and stack machine, an alternative to the tree-walking
+//! interpreter selected with `--engine=vm`. Both engines share the built-ins
+//! and operator semantics, and the tests run every program through both.
+
+use crate::interpreter::{globals, Context, RuntimeError};
+use crate::parser::ast::Statement;
+use crate::resolver::resolve;
+
+use compiler::compile;
+use machine::Machine;
+
+mod code;
+mod compiler;
+mod machine;
+
+pub fn run(input: Vec<Statement>, context: Context) -> Result<(), RuntimeError> {
+    let input = resolve(input)?;
+    let globals = globals();
+    let protos = compile(&input, &globals)?;
+
+    Machine::new(protos, globals, context).run()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::interpreter::{interpret, Limits};
+    use crate::parser::ast::Expression;
+
+    /// Runs a program on both engines, each in a fresh `context()`, and
+    /// checks they agree. Errors come back without their trace, which
+    /// depends on timing once tasks run.
+    pub(crate) fn agree(source: &str, context: impl Fn() -> Context) -> Result<(), RuntimeError> {
+        let (rest, input) = crate::parser::parse(source).unwrap();
+        assert!(rest.trim().is_empty(), "unparsed: {}", rest);
+
+        let tree = interpret(input.clone(), context()).map_err(|e| e.cause().clone());
+        let vm = run(input, context()).map_err(|e| e.cause().clone());
+        assert_eq!(tree, vm, "engines disagree on {}", source);
+        vm
+    }
+
+    pub(crate) fn check(source: &str, limits: Limits) -> Result<(), RuntimeError> {
+        agree(source, || Context {
+            limits: limits.clone(),
+            ..Context::default()
+        })
+    }
+
+    pub(crate) fn exits(source: &str) -> Result<(), RuntimeError> {
+        check(source, Limits::default())
+    }
+
+    #[test]
+    fn test1() {
+        assert_eq!(
+            exits(
+                "let a = 10.0 + 3 * 2
+                let s = \"ab\" + \"cd\"
+                let m = { name: \"x\", list: [1, 2, [3]] }
+                if a == 16 and s == \"abcd\" and m.list[-1][0] == 3 {
+                    exit(len(s) + m.list[1])
+                } else {
+                    exit(1)
+                }"
+            ),
+            Err(RuntimeError::Exit(6))
+        );
+    }
+
+    #[test]
+    fn test2() {
+        assert_eq!(
+            exits(
+                "let total = 0
+                let n = 0
+                loop i : [1, 2, 3, 4, 5] {
+                    let inner = i * 2
+                    if i == 4 { break }
+                    total = total + inner
+                }
+                loop {
+                    n = n + 1
+                    if n == 3 { break }
+                }
+                exit(total + n)"
+            ),
+            Err(RuntimeError::Exit(15))
+        );
+
+        assert_eq!(
+            exits(
+                "loop i : [1] { let inner = i }
+                exit(inner)"
+            ),
+            Err(RuntimeError::UndefinedVariable(String::from("inner")))
+        );
+    }
+
+    #[test]
+    fn test3() {
+        assert_eq!(
+            exits(
+                "fn fib(n) {
+                    if n < 2 { return n }
+                    return fib(n - 1) + fib(n - 2)
+                }
+                fn twice(f, x) { return f(f(x)) }
+                fn inc(x) { return x + 1 }
+                fn outer() { return helper() }
+                fn helper() { return 2 }
+                let g = inc
+                exit(fib(10) + twice(inc, 0) + g(1) + outer())"
+            ),
+            Err(RuntimeError::Exit(61))
+        );
+    }
+
+    #[test]
+    fn test4() {
+        assert_eq!(
+            exits(
+                "fn desc(a, b) { return b - a }
+                fn big(x) { return x > 1 }
+                let xs = [3, 1, 2]
+                push(xs, 4)
+                sort(xs, desc)
+                let first = pop(xs)
+                loop x : [9] { push(xs, x) }
+                exit(sum(filter(xs, big)) + first)"
+            ),
+            Err(RuntimeError::Exit(19))
+        );
+    }
+
+    #[test]
+    fn test5() {
+        for source in [
+            "let a = b",
+            "fn f(x) { return x }\nf(1, 2)",
+            "let a = 1 + \"x\"",
+            "if 1 { }",
+            "undefined_fn()",
+            "fn f() { return y }\nlet y = 1\nf()",
+            "loop x : 5 { }",
+        ] {
+            assert!(exits(source).is_err(), "{}", source);
+        }
+
+        assert_eq!(
+            exits("fn f() { break }\nexit(len([f()]))"),
+            Err(RuntimeError::Exit(1))
+        );
+        assert_eq!(exits("return 1\nexit(3)"), Ok(()));
+        assert_eq!(
+            exits("let a = 1\nb = 2\nexit(a)"),
+            Err(RuntimeError::UndeclaredAssignment(String::from("b")))
+        );
+    }
+
+    #[test]
+    fn test6() {
+        let steps = Limits {
+            steps: Some(500),
+            ..Limits::default()
+        };
+        assert_eq!(
+            check("loop { }", steps),
+            Err(RuntimeError::LimitExceeded(String::from(
+                "more than 500 steps"
+            )))
+        );
+
+        let depth = Limits {
+            depth: Some(20),
+            ..Limits::default()
+        };
+        assert_eq!(
+            check("fn f(n) { return 1 + f(n + 1) }\nf(0)", depth.clone()),
+            Err(RuntimeError::LimitExceeded(String::from(
+                "call depth above 20"
+            )))
+        );
+
+        assert_eq!(
+            check(
+                "fn count(n, total) {
+                    if n == 0 { return total }
+                    loop { return count(n - 1, total + n) }
+                }
+                fn even(n) { if n == 0 { return true }\nreturn odd(n - 1) }
+                fn odd(n) { if n == 0 { return false }\nreturn even(n - 1) }
+                exit(count(5000, 0) - 12502500 + len([even(301)]))",
+                depth
+            ),
+            Err(RuntimeError::Exit(1))
+        );
+    }
+
+    #[test]
+    fn test7() {
+        assert_eq!(
+            exits(
+                "fn sq(x) { return x * x }
+                fn odd(x) { let even = idiv(x, 2) * 2\nreturn even != x }
+                fn add(a, b) { return a + b }
+                let xs = par_map([1, 2, 3, 4], sq)
+                let task = spawn(add, 20, 2)
+                if xs == [1, 4, 9, 16] and par_filter(xs, odd) == [1, 9] {
+                    exit(join(task) + len(join([\"a\", \"b\"], \"\")))
+                }"
+            ),
+            Err(RuntimeError::Exit(24))
+        );
+
+        assert_eq!(
+            exits(
+                "fn f(x) { if x > 1 { exit(x) }\nreturn x }
+                let t = spawn(f, 5)
+                par_map([1, 3, 2, 4], f)"
+            ),
+            Err(RuntimeError::Exit(3))
+        );
+        assert_eq!(
+            exits("fn f(x) { return x }\nexit(join(spawn(f, 7)))"),
+            Err(RuntimeError::Exit(7))
+        );
+    }
+
+    #[test]
+    fn test8() {
+        assert_eq!(
+            exits(
+                "fn produce(tx, n) {
+                    loop i : [1, 2, 3] { send(tx, i * n) }
+                    close(tx)
+                }
+                let a = channel()
+                let b = channel(1)
+                go produce(a[0], 1)
+                go produce(b[0], 10)
+                let total = 0
+                loop {
+                    let got = select([a[1], b[1]])
+                    if got == nil { break }
+                    total = total + got[1]
+                }
+                exit(total + len([recv(a[1]), try_recv(b[1])]))"
+            ),
+            Err(RuntimeError::Exit(68))
+        );
+
+        for source in [
+            "let c = channel()\nrecv(c[1])",
+            "fn wait(rx) { return recv(rx) }\nlet c = channel()\njoin(spawn(wait, c[1]))",
+            "let c = channel(1)\nsend(c[0], 1)\nsend(c[0], 2)",
+            "fn wait(rx) { try { return recv(rx) } catch e { return 0 } }
+            let c = channel()
+            let t = spawn(wait, c[1])
+            try { recv(channel()[1]) } catch e { exit(1) }",
+        ] {
+            assert_eq!(exits(source), Err(RuntimeError::Deadlock), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test9() {
+        assert_eq!(
+            exits(
+                "async fn produce(tx) {
+                    loop i : [1, 2, 3] { send(tx, i) }
+                    close(tx)
+                }
+                async fn consume(rx) {
+                    let total = 0
+                    loop {
+                        let x = recv(rx)
+                        if x == nil { return total }
+                        total = total + x
+                    }
+                }
+                async fn double(x) { return 2 * await x }
+                let c = channel(1)
+                let out = gather([produce(c[0]), consume(c[1]), 4])
+                let f = double(double(out[1]))
+                exit(await f + await f + out[2] + len([await out[0]]))"
+            ),
+            Err(RuntimeError::Exit(53))
+        );
+
+        assert_eq!(
+            exits(
+                "async fn f(x) { if x > 1 { exit(x) }\nreturn x }
+                gather([f(1), f(3), f(2)])"
+            ),
+            Err(RuntimeError::Exit(3))
+        );
+        assert_eq!(
+            exits("async fn f(rx) { return recv(rx) }\nawait f(channel()[1])"),
+            Err(RuntimeError::Deadlock)
+        );
+    }
+
+    #[test]
+    fn test10() {
+        assert_eq!(
+            exits(
+                "fn f(x) {
+                    try {
+                        if x > 1 { throw { message: \"big\", x: x } }
+                        return x
+                    } finally {
+                        x = 0
+                    }
+                }
+                fn g() { try { return 1 } finally { return 2 } }
+                let log = []
+                let total = 0
+                loop i : [1, 2, 3, 4] {
+                    try {
+                        total = total + f(i)
+                    } catch e {
+                        total = total + 10 * e.x
+                        if i == 3 { break }
+                    } finally {
+                        push(log, i)
+                    }
+                }
+                try {
+                    try { throw 5 } catch e { throw e + 1 } finally { push(log, 9) }
+                } catch e {
+                    total = total + e
+                }
+                if false { let missing = 0 }
+                try {
+                    let y = missing + 1
+                } catch e {
+                    if e.kind == \"undefined_variable\" and e.location.line == 29 {
+                        total = total + g()
+                    }
+                }
+                if log == [1, 2, 3, 9] { exit(total) }"
+            ),
+            Err(RuntimeError::Exit(59))
+        );
+
+        assert_eq!(
+            exits("try { exit(2) } catch e { exit(3) } finally { print(1) }"),
+            Err(RuntimeError::Exit(2))
+        );
+        assert_eq!(
+            exits("fn f() { throw [1] }\ntry { f() } finally { print(2) }"),
+            Err(RuntimeError::Thrown(Expression::List(vec![
+                Expression::Number(1.0)
+            ])))
+        );
+        let steps = Limits {
+            steps: Some(100),
+            ..Limits::default()
+        };
+        assert!(matches!(
+            check("try { loop { } } catch e { exit(1) }", steps),
+            Err(RuntimeError::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test11() {
+        let source = "fn inner(x) {
+            let y = x + 1
+            throw y
+        }
+        fn outer(x) { return 1 + inner(x) }
+        fn run(f, x) { return f(x) }
+        run(outer, 2)";
+        let (_, input) = crate::parser::parse(source).unwrap();
+
+        let tree = interpret(input.clone(), Context::default()).unwrap_err();
+        let vm = run(input, Context::default()).unwrap_err();
+        assert_eq!(tree, vm);
+
+        // The tail call from run leaves no location of its own.
+        let trace: Vec<_> = vm
+            .trace()
+            .iter()
+            .map(|t| (t.function.as_deref(), t.span.line, t.span.column))
+            .collect();
+        assert_eq!(
+            trace,
+            vec![(Some("inner"), 3, 13), (Some("outer"), 5, 23), (None, 7, 9)]
+        );
+    }
+
+    #[test]
+    fn test12() {
+        assert_eq!(
+            exits("loop { try { exit(3) } finally { break } }\nexit(0)"),
+            Err(RuntimeError::Exit(3))
+        );
+        assert_eq!(
+            exits(
+                "fn f() { try { exit(4) } finally { throw 1 } }
+                try { f() } catch e { exit(1) }"
+            ),
+            Err(RuntimeError::Exit(4))
+        );
+        assert_eq!(
+            exits(
+                "let n = 0
+                loop i : [1, 2] {
+                    try { throw i } finally { n = n + i\nbreak }
+                }
+                fn f() { try { throw 1 } finally { return 6 } }
+                exit(n + f())"
+            ),
+            Err(RuntimeError::Exit(7))
+        );
+
+        let depth = Limits {
+            depth: Some(3),
+            ..Limits::default()
+        };
+        assert!(matches!(
+            check(
+                "fn f(n) { return f(n + 1) + 1 }
+                fn g() { try { f(0) } finally { return 7 } }
+                exit(g())",
+                depth
+            ),
+            Err(RuntimeError::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test13() {
+        // Each thread has its own call depth.
+        let depth = Limits {
+            depth: Some(2),
+            ..Limits::default()
+        };
+        assert_eq!(
+            check(
+                "fn nap() { sleep(100) }
+                fn one() { return 1 }
+                let tasks = [spawn(nap), spawn(nap), spawn(nap)]
+                sleep(20)
+                let x = one()
+                loop t : tasks { join(t) }
+                exit(x)",
+                depth
+            ),
+            Err(RuntimeError::Exit(1))
+        );
+    }
+
+    #[test]
+    fn test14() {
+        // Built-ins that block stop waiting when the program runs out of time.
+        let time = Limits {
+            time: Some(std::time::Duration::from_millis(200)),
+            ..Limits::default()
+        };
+        let started = std::time::Instant::now();
+
+        for source in [
+            "sleep(10000)",
+            "cmd(\"sleep\", \"10\")",
+            "serve(0, {})",
+            "fn slow(tx) { sleep(10000)\nsend(tx, 1) }
+            let c = channel()
+            let t = spawn(slow, c[0])
+            recv(c[1])",
+        ] {
+            assert!(matches!(
+                check(source, time.clone()),
+                Err(RuntimeError::LimitExceeded(_))
+            ));
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test15() {
+        // Assigning to a variable whose `let` never ran is an error.
+        assert_eq!(
+            exits("if false { let x = 1 }\nx = 2"),
+            Err(RuntimeError::UndefinedVariable(String::from("x")))
+        );
+        assert_eq!(
+            exits("fn f() { if false { let z = 0 }\nz = 1 }\nf()"),
+            Err(RuntimeError::UndefinedVariable(String::from("z")))
+        );
+        assert_eq!(
+            exits("if false { let x = 1 }\ntry { x = 2 } catch e { exit(3) }"),
+            Err(RuntimeError::Exit(3))
+        );
+    }
+
+    #[test]
+    fn test16() {
+        // `async` and `await` are syntax, not functions a script can reach.
+        assert_eq!(
+            exits("fn f() { }\nasync(f)"),
+            Err(RuntimeError::UndefinedFunction(String::from("async")))
+        );
+        assert_eq!(
+            exits("let await = 2\nexit(await)"),
+            Err(RuntimeError::Exit(2))
+        );
+        assert_eq!(
+            exits(
+                "async fn count(n) { if n == 0 { return 0 }\nreturn 1 + await count(n - 1) }
+                exit(await count(3))"
+            ),
+            Err(RuntimeError::Exit(3))
+        );
+
+        let source = "async fn f(x) {
+            throw x
+        }
+        await f(1)";
+        let (_, input) = crate::parser::parse(source).unwrap();
+
+        let tree = interpret(input.clone(), Context::default()).unwrap_err();
+        let vm = run(input, Context::default()).unwrap_err();
+        assert_eq!(tree, vm);
+        let trace: Vec<_> = vm
+            .trace()
+            .iter()
+            .map(|t| (t.function.as_deref(), t.span.line))
+            .collect();
+        assert_eq!(trace, vec![(Some("f"), 2), (None, 4)]);
+    }
+
+    #[test]
+    fn test17() {
+        assert_eq!(
+            exits(
+                "let xs = [3]
+                let n = push(xs, 1, 2) + insert(xs, 0, 4)
+                if sort(xs) == nil and reverse(xs) == nil and xs == [4, 3, 2, 1] { exit(n) }"
+            ),
+            Err(RuntimeError::Exit(7))
+        );
+
+        // A change nothing could keep is an error rather than lost.
+        for source in [
+            "let m = { xs: [1] }\npush(m.xs, 2)",
+            "fn f() { return [1] }\npush(f(), 2)",
+            "sort([2, 1])",
+            "pop([1])",
+        ] {
+            assert!(
+                matches!(exits(source), Err(RuntimeError::Type(t)) if t.contains("in place")),
+                "{}",
+                source
+            );
+        }
+    }
+}